@@ -1,18 +1,35 @@
-use std::path::PathBuf;
+use std::{ops::RangeInclusive, path::PathBuf, time::Duration};
 
+use chrono::{DateTime, FixedOffset, Utc};
 use clap::{ArgGroup, Parser, ValueEnum, ValueHint};
+use futures::StreamExt;
 use serde::Deserialize;
+use tcd::{
+	bulk::{DEFAULT_BATCH_SIZE, DEFAULT_FLUSH_INTERVAL},
+	channel::{BroadcastType, Channel, VideoSort},
+	checkpoint::CheckpointStore,
+	gql::{
+		client::GqlClient,
+		limiter::DEFAULT_BURST,
+		retry::{ErrorClass, RetryPolicy},
+	},
+	parquet::DEFAULT_ROW_GROUP_SIZE,
+	sink::{Sink, UserSink},
+	user::{EnrichUsers, User, UserEnricher},
+	video::{Video, WHOLE_VIDEO},
+};
 
 #[derive(Deserialize, Clone)]
 #[serde(remote = "tcd::gql::prelude::Format")]
 pub enum Format {
 	JsonLines,
 	Csv,
+	Parquet,
 }
 
 impl ValueEnum for Format {
 	fn value_variants<'a>() -> &'a [Format] {
-		&[Format::Csv, Format::JsonLines]
+		&[Format::Csv, Format::JsonLines, Format::Parquet]
 	}
 
 	fn from_str(input: &str, ignore_case: bool) -> Result<Self, String> {
@@ -20,12 +37,14 @@ impl ValueEnum for Format {
 			match input.to_lowercase().as_str() {
 				"jsonl" => Ok(Format::JsonLines),
 				"csv" => Ok(Format::Csv),
+				"parquet" => Ok(Format::Parquet),
 				_ => Err(format!("{input} is not a valid format")),
 			}
 		} else {
 			match input {
 				"jsonl" => Ok(Format::JsonLines),
 				"csv" => Ok(Format::Csv),
+				"parquet" => Ok(Format::Parquet),
 				_ => Err(format!("{input} is not a valid format")),
 			}
 		}
@@ -35,6 +54,7 @@ impl ValueEnum for Format {
 		Some(match self {
 			Format::JsonLines => clap::builder::PossibleValue::new("jsonl"),
 			Format::Csv => clap::builder::PossibleValue::new("csv"),
+			Format::Parquet => clap::builder::PossibleValue::new("parquet"),
 		})
 	}
 }
@@ -44,6 +64,7 @@ impl From<Format> for tcd::gql::prelude::Format {
 		match format {
 			Format::JsonLines => tcd::gql::prelude::Format::JsonLines,
 			Format::Csv => tcd::gql::prelude::Format::Csv,
+			Format::Parquet => tcd::gql::prelude::Format::Parquet,
 		}
 	}
 }
@@ -53,6 +74,7 @@ impl From<&Format> for tcd::gql::prelude::Format {
 		match format {
 			Format::JsonLines => tcd::gql::prelude::Format::JsonLines,
 			Format::Csv => tcd::gql::prelude::Format::Csv,
+			Format::Parquet => tcd::gql::prelude::Format::Parquet,
 		}
 	}
 }
@@ -62,11 +84,198 @@ impl std::fmt::Display for Format {
 		match self {
 			Format::JsonLines => write!(f, "jsonl"),
 			Format::Csv => write!(f, "csv"),
+			Format::Parquet => write!(f, "parquet"),
 		}
 	}
 }
 
-#[allow(clippy::option_option)]
+#[derive(ValueEnum, Clone, Copy)]
+pub enum RetryClass {
+	Timeout,
+	Network,
+	Server,
+	RateLimited,
+	Decode,
+	DataMissing,
+}
+
+impl From<RetryClass> for ErrorClass {
+	fn from(class: RetryClass) -> Self {
+		match class {
+			RetryClass::Timeout => ErrorClass::Timeout,
+			RetryClass::Network => ErrorClass::Network,
+			RetryClass::Server => ErrorClass::Server,
+			RetryClass::RateLimited => ErrorClass::RateLimited,
+			RetryClass::Decode => ErrorClass::Decode,
+			RetryClass::DataMissing => ErrorClass::DataMissing,
+		}
+	}
+}
+
+impl std::fmt::Display for RetryClass {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RetryClass::Timeout => write!(f, "timeout"),
+			RetryClass::Network => write!(f, "network"),
+			RetryClass::Server => write!(f, "server"),
+			RetryClass::RateLimited => write!(f, "rate-limited"),
+			RetryClass::Decode => write!(f, "decode"),
+			RetryClass::DataMissing => write!(f, "data-missing"),
+		}
+	}
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum VideoType {
+	Archive,
+	Highlight,
+	Upload,
+	PastPremiere,
+	All,
+}
+
+impl From<VideoType> for BroadcastType {
+	fn from(kind: VideoType) -> Self {
+		match kind {
+			VideoType::Archive => BroadcastType::Archive,
+			VideoType::Highlight => BroadcastType::Highlight,
+			VideoType::Upload => BroadcastType::Upload,
+			VideoType::PastPremiere => BroadcastType::PastPremiere,
+			VideoType::All => BroadcastType::All,
+		}
+	}
+}
+
+impl std::fmt::Display for VideoType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			VideoType::Archive => write!(f, "archive"),
+			VideoType::Highlight => write!(f, "highlight"),
+			VideoType::Upload => write!(f, "upload"),
+			VideoType::PastPremiere => write!(f, "past-premiere"),
+			VideoType::All => write!(f, "all"),
+		}
+	}
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Sort {
+	Time,
+	Views,
+}
+
+impl From<Sort> for VideoSort {
+	fn from(sort: Sort) -> Self {
+		match sort {
+			Sort::Time => VideoSort::Time,
+			Sort::Views => VideoSort::Views,
+		}
+	}
+}
+
+impl std::fmt::Display for Sort {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Sort::Time => write!(f, "time"),
+			Sort::Views => write!(f, "views"),
+		}
+	}
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum Enrich {
+	Channels,
+	Commenters,
+}
+
+impl From<Enrich> for EnrichUsers {
+	fn from(enrich: Enrich) -> Self {
+		match enrich {
+			Enrich::Channels => EnrichUsers::Channels,
+			Enrich::Commenters => EnrichUsers::Commenters,
+		}
+	}
+}
+
+impl std::fmt::Display for Enrich {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Enrich::Channels => write!(f, "channels"),
+			Enrich::Commenters => write!(f, "commenters"),
+		}
+	}
+}
+
+/// Parses an RFC 3339 timestamp, or a time relative to now such as `7d`
+/// with a unit of `s`, `m`, `h`, `d` or `w`
+fn parse_time(input: &str) -> Result<DateTime<FixedOffset>, String> {
+	if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+		return Ok(time);
+	}
+
+	let error = || format!("{input} is not an RFC 3339 timestamp or a relative time like 7d");
+	let (amount, unit) = input.split_at(
+		input
+			.find(|c: char| !c.is_ascii_digit())
+			.unwrap_or(input.len()),
+	);
+	let amount = amount.parse::<i64>().map_err(|_| error())?;
+	let ago = match unit {
+		"s" => chrono::Duration::seconds(amount),
+		"m" => chrono::Duration::minutes(amount),
+		"h" => chrono::Duration::hours(amount),
+		"d" => chrono::Duration::days(amount),
+		"w" => chrono::Duration::weeks(amount),
+		_ => return Err(error()),
+	};
+
+	Ok((Utc::now() - ago).into())
+}
+
+/// Parses an offset into a video as `HH:MM:SS`, `MM:SS` or a number of seconds
+fn parse_offset(input: &str) -> Result<u32, String> {
+	let error = || format!("{input} is not an offset like 01:20:00 or a number of seconds");
+
+	input.split(':').try_fold(0u32, |offset, part| {
+		let part = part.parse::<u32>().map_err(|_| error())?;
+
+		offset
+			.checked_mul(60)
+			.and_then(|offset| offset.checked_add(part))
+			.ok_or_else(error)
+	})
+}
+
+/// Parses a number of seconds, which must fit in a [`Duration`]
+fn parse_seconds(input: &str) -> Result<f64, String> {
+	parse_duration(input, 1., "seconds")
+}
+
+/// Parses a number of minutes, which must fit in a [`Duration`]
+fn parse_minutes(input: &str) -> Result<f64, String> {
+	parse_duration(input, 60., "minutes")
+}
+
+/// Parses a number of requests per second, which must be above zero
+fn parse_rate(input: &str) -> Result<f64, String> {
+	input
+		.parse::<f64>()
+		.ok()
+		.filter(|rate| rate.is_finite() && *rate > 0.)
+		.ok_or_else(|| format!("{input} is not a positive number of requests per second"))
+}
+
+fn parse_duration(input: &str, scale: f64, unit: &str) -> Result<f64, String> {
+	let error = || format!("{input} is not a non-negative number of {unit}");
+	let amount = input.parse::<f64>().map_err(|_| error())?;
+
+	// Negative, NaN and huge amounts would panic when they are turned into a `Duration`
+	Duration::try_from_secs_f64(amount * scale)
+		.map(|_| amount)
+		.map_err(|_| error())
+}
+
+#[allow(clippy::option_option, clippy::struct_excessive_bools)]
 #[derive(Parser)]
 #[clap(
 	author,
@@ -79,22 +288,58 @@ impl std::fmt::Display for Format {
 {all-args}{after-help}
 "
 )]
-#[clap(group(ArgGroup::new("out").required(false).args(&["output", "postgres", "stdout"])))]
-#[clap(group(ArgGroup::new("in").required(true).args(&["channel", "video"])))]
+#[clap(group(ArgGroup::new("out").required(false).args(&["output", "postgres", "sqlite", "stdout"])))]
+#[clap(group(ArgGroup::new("in").required(true).args(&["channel", "clip", "video"])))]
 pub struct Args {
+	/// The number of comments buffered before they are copied to the database (`bulk` only)
+	#[clap(long, default_value_t = DEFAULT_BATCH_SIZE)]
+	pub batch_size: usize,
+
+	/// If specified, buffers comments across pages and videos and copies them to the database in batches (`postgres` only)
+	#[clap(long, default_value_t = false, requires = "postgres")]
+	pub bulk: bool,
+
+	/// The maximum number of requests to send at once (`rate` only)
+	#[clap(long, default_value_t = DEFAULT_BURST)]
+	pub burst: u32,
+
 	/// The channel(s) to download
 	#[clap(short = 'c', long)]
 	pub channel: Vec<String>,
 
+	/// The slugs of the clips to download the chat for
+	#[clap(long)]
+	pub clip: Vec<String>,
+
+	/// If specified, downloads the chat of each channel's clips instead of its videos
+	#[clap(long, default_value_t = false, requires = "channel")]
+	pub clips: bool,
+
 	/// The Twitch client ID to use in the request headers
 	#[clap(alias = "id", short = 'i', long)]
 	pub client_id: Option<String>,
 
+	/// Looks up the account details of each video's channel, or of every commenter too, after downloading (`postgres` and `sqlite` only)
+	#[clap(long)]
+	pub enrich: Option<Enrich>,
+
+	/// The longest number of seconds comments are buffered for before they are copied (`bulk` only)
+	#[clap(long, default_value_t = DEFAULT_FLUSH_INTERVAL.as_secs_f64(), value_parser = parse_seconds)]
+	pub flush_interval: f64,
+
 	/// Used with --output or --stdout
 	#[clap(alias = "fmt", short = 'f', long, default_value_t = Format::Csv)]
 	pub format: Format,
 
-	/// Downloads the first n videos from each channel
+	/// Skips comments sent before this offset into each video, as `HH:MM:SS` or seconds
+	#[clap(long, value_parser = parse_offset)]
+	pub from: Option<u32>,
+
+	/// A JSON file overriding the GraphQL endpoint, client ID or persisted query hashes [default: `GQL_CONFIG` env]
+	#[clap(long, value_hint = ValueHint::FilePath)]
+	pub gql_config: Option<PathBuf>,
+
+	/// Downloads the first n videos from each channel, or every video if 0
 	#[clap(short = 'l', long)]
 	pub limit: Option<usize>,
 
@@ -102,6 +347,10 @@ pub struct Args {
 	#[clap(short = 'e', long, default_value_t = false)]
 	pub live: bool,
 
+	/// If specified, captures the chat of each channel as it is sent instead of downloading videos
+	#[clap(long, default_value_t = false, requires = "channel")]
+	pub live_chat: bool,
+
 	/// If specified, pipes data to the file
 	#[clap(alias = "out", short = 'o', long, value_hint = ValueHint::FilePath)]
 	pub output: Option<PathBuf>,
@@ -114,6 +363,55 @@ pub struct Args {
 	#[clap(short = 'q', long, default_value_t = false)]
 	pub quiet: bool,
 
+	/// The maximum number of requests per second to send
+	#[clap(long, value_parser = parse_rate)]
+	pub rate: Option<f64>,
+
+	/// If specified, continues each video from its last saved checkpoint
+	#[clap(short = 'r', long, default_value_t = false)]
+	pub resume: bool,
+
+	/// The maximum number of attempts for each request
+	#[clap(long, default_value_t = 5)]
+	pub retries: u32,
+
+	/// The number of seconds to wait before the first retry, doubled after each retry
+	#[clap(long, default_value_t = 0.5, value_parser = parse_seconds)]
+	pub retry_delay: f64,
+
+	/// The classes of errors to retry
+	#[clap(
+		long,
+		value_delimiter = ',',
+		default_values_t = [
+			RetryClass::Timeout,
+			RetryClass::Network,
+			RetryClass::Server,
+			RetryClass::RateLimited,
+		]
+	)]
+	pub retry_on: Vec<RetryClass>,
+
+	/// The number of comments in each row group (`parquet` only)
+	#[clap(long, default_value_t = DEFAULT_ROW_GROUP_SIZE)]
+	pub row_group_size: usize,
+
+	/// The number of parts of each video to download at once, which disables checkpoints when above 1
+	#[clap(long, default_value_t = 1)]
+	pub segments: usize,
+
+	/// Skips videos created before this time, as an RFC 3339 timestamp or relative like `7d`
+	#[clap(long, value_parser = parse_time)]
+	pub since: Option<DateTime<FixedOffset>>,
+
+	/// The order to download each channel's videos in
+	#[clap(long, default_value_t = Sort::Time)]
+	pub sort: Sort,
+
+	/// The embedded database file to save to, created if it does not exist
+	#[clap(long, value_hint = ValueHint::FilePath)]
+	pub sqlite: Option<PathBuf>,
+
 	/// If specified, pipes data to stdout
 	#[clap(short = 's', long)]
 	pub stdout: bool,
@@ -122,11 +420,160 @@ pub struct Args {
 	#[clap(short = 't', long, default_value_t = 10)]
 	pub threads: usize,
 
+	/// Stops at comments sent after this offset into each video, as `HH:MM:SS` or seconds
+	#[clap(long, value_parser = parse_offset)]
+	pub to: Option<u32>,
+
+	/// The kind of videos to download from each channel
+	#[clap(long = "type", value_name = "TYPE", default_value_t = VideoType::Archive)]
+	pub video_type: VideoType,
+
+	/// Skips videos created after this time, as an RFC 3339 timestamp or relative like `7d`
+	#[clap(long, value_parser = parse_time)]
+	pub until: Option<DateTime<FixedOffset>>,
+
 	/// The video ids to download the chat for
 	#[clap(short = 'v', long)]
 	pub video: Vec<i64>,
 
 	/// The number of minutes to wait between polls (`live` only)
-	#[clap(short = 'w', long, default_value_t = 30.)]
+	#[clap(short = 'w', long, default_value_t = 30., value_parser = parse_minutes)]
 	pub wait: f64,
 }
+
+impl Args {
+	/// Makes a channel paginate the videos selected by `type`, `sort`, `since` and `until`
+	pub fn filter_channel(&self, channel: Channel) -> Channel {
+		channel
+			.with_broadcast_type(self.video_type.into())
+			.with_sort(self.sort.into())
+			.with_date_range(self.since, self.until)
+	}
+
+	/// Looks up the channels selected by `channel`, `type`, `sort`, `since` and `until`,
+	/// saving the details of their accounts to a sink
+	pub async fn channels<S: UserSink + ?Sized>(&self, gql: &GqlClient, sink: &S) -> Vec<Channel> {
+		let users = futures::stream::iter(
+			self.channel
+				.iter()
+				.map(|c| User::from_username(gql, c.as_str())),
+		)
+		.buffer_unordered(self.threads)
+		.filter_map(|u| async move {
+			match u {
+				Ok(u) => u,
+				Err(e) => {
+					crate::report(&e);
+					None
+				}
+			}
+		})
+		.collect::<Vec<_>>()
+		.await;
+
+		if let Err(e) = sink.save_user_details(&users).await {
+			crate::report(&e);
+		}
+
+		users
+			.into_iter()
+			.map(|u| self.filter_channel(Channel::from(u)))
+			.collect()
+	}
+
+	/// The pass that looks up the details of the saved users selected by `enrich`
+	pub fn enricher(&self) -> Option<UserEnricher> {
+		self.enrich.map(|users| UserEnricher::new(users.into()))
+	}
+
+	/// The number of videos to download from each channel, where a `--limit` of 0 means every video
+	pub fn video_limit(&self) -> Option<usize> {
+		self.limit.filter(|&limit| limit > 0)
+	}
+
+	/// Runs a pass of an enricher, if there is one
+	pub async fn enrich_users<S: UserSink + ?Sized>(
+		&self,
+		enricher: Option<&mut UserEnricher>,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+	) {
+		let Some(enricher) = enricher else {
+			return;
+		};
+
+		match enricher.enrich(gql, retry, sink, self.threads).await {
+			Ok(pass) => {
+				for e in &pass.failed {
+					crate::report(e);
+				}
+
+				if !self.quiet {
+					println!("Saved the details of {} users", pass.count);
+				}
+			}
+			Err(e) => crate::report(&e),
+		}
+	}
+
+	/// The offsets of the comments to download from each video, selected by `from` and `to`
+	pub fn offset_range(&self) -> RangeInclusive<u32> {
+		self.from.unwrap_or(0)..=self.to.unwrap_or(u32::MAX)
+	}
+
+	/// Whether videos that were still recording can be downloaded again from their
+	/// checkpoints, which are only used when each video is downloaded whole and in order
+	pub fn recheck_growing(&self) -> bool {
+		self.offset_range() == WHOLE_VIDEO && self.segments <= 1
+	}
+
+	/// Saves the comments of a video selected by `from`, `to` and `segments` to a sink
+	pub async fn write_video<S: Sink + ?Sized>(
+		&self,
+		video: Video,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		checkpoints: Option<&dyn CheckpointStore>,
+	) -> Result<(), S::Error> {
+		if self.segments > 1 {
+			video
+				.write_segments_to_sink(
+					gql,
+					retry,
+					sink,
+					self.offset_range(),
+					self.segments,
+					!self.quiet,
+				)
+				.await
+		} else {
+			video
+				.write_range_to_sink(
+					gql,
+					retry,
+					sink,
+					self.offset_range(),
+					checkpoints,
+					!self.quiet,
+				)
+				.await
+		}
+	}
+
+	/// Builds the retry policy from the `retry` arguments
+	pub fn retry_policy(&self) -> RetryPolicy {
+		RetryPolicy {
+			max_attempts: self.retries.max(1),
+			base_delay: Duration::from_secs_f64(self.retry_delay),
+			retry_on: self
+				.retry_on
+				.iter()
+				.copied()
+				.map(ErrorClass::from)
+				.collect(),
+			..RetryPolicy::default()
+		}
+	}
+}
@@ -0,0 +1,86 @@
+use crate::cli::Args;
+use futures::StreamExt;
+use tcd::{
+	channel::Channel,
+	clip::Clip,
+	gql::{client::GqlClient, retry::RetryPolicy},
+	sink::Sink,
+};
+
+/// Gets the slugs of the clips of each channel, up to `limit` per channel
+async fn channel_clips(gql: &GqlClient, args: &Args, retry: &RetryPolicy) -> Vec<String> {
+	let mut slugs = Vec::new();
+
+	for username in &args.channel {
+		let channel = match Channel::from_username(gql, username.as_str()).await {
+			Ok(Some(channel)) => channel,
+			Ok(None) => continue,
+			Err(e) => {
+				crate::report(&e);
+				continue;
+			}
+		};
+
+		let limit = args.limit.unwrap_or(usize::MAX);
+		let mut clips = channel.clips(gql, retry);
+		let mut count = 0;
+
+		while let Some(page) = clips.next().await {
+			let page = match page {
+				Ok(page) => page,
+				Err(e) => {
+					crate::report(&e);
+					break;
+				}
+			};
+
+			for edge in page.edges.into_iter().take(limit - count) {
+				slugs.push(edge.node.slug);
+				count += 1;
+			}
+
+			if count >= limit {
+				break;
+			}
+		}
+	}
+
+	slugs
+}
+
+/// Looks up a clip and saves the chat sent during it
+async fn write_clip<S: Sink + ?Sized>(
+	gql: &GqlClient,
+	retry: &RetryPolicy,
+	sink: &S,
+	slug: &str,
+	verbose: bool,
+) {
+	match Clip::from_slug(gql, retry, slug).await {
+		Ok(Some(clip)) => {
+			if let Err(e) = clip.write_to_sink(gql, retry, sink, verbose).await {
+				crate::report(&e);
+			}
+		}
+		Ok(None) => eprintln!("Clip {slug} does not exist"),
+		Err(e) => crate::report(&e),
+	}
+}
+
+pub async fn run<S: Sink + ?Sized>(gql: &GqlClient, args: &Args, sink: &S) {
+	let retry = args.retry_policy();
+	let mut slugs = args.clip.clone();
+
+	if args.clips {
+		slugs.extend(channel_clips(gql, args, &retry).await);
+	}
+
+	futures::stream::iter(
+		slugs
+			.iter()
+			.map(|slug| write_clip(gql, &retry, sink, slug, !args.quiet)),
+	)
+	.buffer_unordered(args.threads)
+	.collect::<Vec<_>>()
+	.await;
+}
@@ -0,0 +1,98 @@
+use crate::cli::Args;
+use std::{
+	fs::File,
+	io::{BufWriter, Write},
+	sync::Mutex,
+	time::Duration,
+};
+use tcd::{
+	gql::{client::GqlClient, prelude::Format},
+	live::{capture, Batches, IRC_ENDPOINT},
+	prisma,
+	sink::{ModerationSink, PrismaSink, StreamSink},
+	sqlite::SqliteStore,
+	video::CSV_HEADER,
+};
+
+/// The number of seconds to wait before reconnecting to the chat server
+const RECONNECT_DELAY: u64 = 5;
+
+/// Captures chat until the process is stopped, reconnecting whenever
+/// the connection is lost or the server asks for it, without losing unsaved comments
+async fn capture_forever<S: ModerationSink + ?Sized>(gql: &GqlClient, args: &Args, sink: &S) {
+	let retry = args.retry_policy();
+	let mut batches = Batches::default();
+
+	loop {
+		if let Err(e) = capture(
+			IRC_ENDPOINT,
+			gql,
+			&retry,
+			&args.channel,
+			sink,
+			&mut batches,
+			!args.quiet,
+		)
+		.await
+		{
+			crate::report(&e);
+		}
+
+		tokio::time::sleep(Duration::from_secs(RECONNECT_DELAY)).await;
+	}
+}
+
+pub async fn run(gql: GqlClient, mut args: Args) {
+	if args.postgres.is_some() {
+		let client = match prisma::new_client().await {
+			Ok(client) => client,
+			Err(e) => panic!("Failed to connect to database: {e}"),
+		};
+
+		capture_forever(&gql, &args, &PrismaSink::new(&client)).await;
+	} else if let Some(path) = &args.sqlite {
+		let store = match SqliteStore::open(path) {
+			Ok(store) => store,
+			Err(e) => panic!("Failed to open database: {e}"),
+		};
+
+		capture_forever(&gql, &args, &store).await;
+	} else {
+		let format = Format::from(&args.format);
+
+		// Parquet files are only readable once they are finished
+		assert!(
+			format != Format::Parquet,
+			"--live-chat cannot be used with --format parquet"
+		);
+
+		let mut empty = true;
+		let out: Box<dyn Write + Send> = if let Some(path) = &args.output {
+			match File::options().create(true).append(true).open(path) {
+				Ok(file) => {
+					empty = file.metadata().map_or(true, |m| m.len() == 0);
+
+					Box::new(file)
+				}
+				Err(e) => panic!("Failed to open output file: {e}"),
+			}
+		} else {
+			Box::new(std::io::stdout())
+		};
+
+		let stream = Mutex::new(BufWriter::new(out));
+
+		if format == Format::Csv && empty {
+			stream
+				.lock()
+				.unwrap()
+				.write_all(format!("{CSV_HEADER}\n").as_bytes())
+				.expect("Failed to write to output file");
+		}
+
+		// Progress is only printed when it does not mix with the comments
+		args.quiet |= args.output.is_none();
+
+		capture_forever(&gql, &args, &StreamSink::new(&stream, &format)).await;
+	}
+}
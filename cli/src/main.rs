@@ -1,14 +1,34 @@
 #![warn(clippy::pedantic)]
 
 mod cli;
+mod clips;
+mod live;
 mod pg;
+mod sqlite;
 mod writer;
 
 use clap::Parser;
 use cli::Args;
 use dotenv::dotenv;
+use std::path::PathBuf;
+use tcd::gql::{
+	client::{GqlClient, GqlConfig},
+	limiter::RateLimiter,
+};
 
-static CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+/// Prints an error and its chain of causes to stderr
+pub fn report(error: &dyn std::error::Error) {
+	let mut message = error.to_string();
+	let mut source = error.source();
+
+	while let Some(cause) = source {
+		message.push_str(": ");
+		message.push_str(&cause.to_string());
+		source = cause.source();
+	}
+
+	eprintln!("{message}");
+}
 
 #[tokio::main]
 async fn main() {
@@ -16,38 +36,49 @@ async fn main() {
 
 	let args = Args::parse();
 
-	let mut headers = reqwest::header::HeaderMap::new();
-	let client_id = std::env::var("CLIENT_ID");
-
-	headers.insert(
-		"Client-ID",
-		// First, try to get the client ID from the command-line arguments
-		reqwest::header::HeaderValue::from_str(if let Some(client_id) = args.client_id.as_ref() {
-			client_id
-		// Otherwise, check the environment variable CLIENT_ID
-		} else if let Ok(client_id) = client_id.as_ref() {
-			client_id
-		// Otherwise, use the default client ID
-		} else {
-			CLIENT_ID
-		})
-		.expect("Invalid CLIENT_ID header value"),
-	);
+	let mut gql = GqlClient::new(reqwest::Client::new());
+
+	// First, try to get the config from the command-line arguments,
+	// otherwise check the environment variable GQL_CONFIG
+	if let Some(path) = args
+		.gql_config
+		.clone()
+		.or_else(|| std::env::var_os("GQL_CONFIG").map(PathBuf::from))
+	{
+		gql = gql.with_config(GqlConfig::from_file(&path).expect("Failed to read the GQL config"));
+	}
 
-	let http = reqwest::ClientBuilder::new()
-		.default_headers(headers)
-		.build()
-		.expect("Failed to build HTTP client");
+	// The client ID from the command-line arguments or the environment variable
+	// CLIENT_ID takes precedence over the config and the default client ID
+	if let Some(client_id) = args
+		.client_id
+		.clone()
+		.or_else(|| std::env::var("CLIENT_ID").ok())
+	{
+		gql = gql.with_client_id(client_id);
+	}
+
+	if let Some(rate) = args.rate {
+		gql = gql.with_rate_limit(RateLimiter::new(rate, args.burst));
+	}
+
+	if args.live_chat {
+		if let Some(Some(postgres)) = &args.postgres {
+			std::env::set_var("DATABASE_URL", postgres);
+		}
 
-	if let Some(postgres) = &args.postgres {
+		crate::live::run(gql, args).await;
+	} else if let Some(postgres) = &args.postgres {
 		if let Some(postgres) = postgres {
 			std::env::set_var("DATABASE_URL", postgres);
 		} else {
 			std::env::var("DATABASE_URL").expect("DATABASE_URL env not set, either set it or specify a connection string to --postgres");
 		}
 
-		crate::pg::run(http, args).await;
+		crate::pg::run(gql, args).await;
+	} else if let Some(path) = args.sqlite.clone() {
+		crate::sqlite::run(gql, args, &path).await;
 	} else {
-		crate::writer::run(http, args).await;
+		crate::writer::run(gql, args).await;
 	}
 }
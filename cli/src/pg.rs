@@ -1,59 +1,120 @@
+use std::time::Duration;
+
 use crate::cli::Args;
 use futures::StreamExt;
 use prisma_client_rust::Direction;
 use tcd::{
-	channel::Channel,
-	gql::prelude::{PaginateFilter, PaginateMut, Save, WriteChunk},
-	prisma,
+	bulk::BulkWriter,
+	channel::{Channel, VideoSort},
+	checkpoint::{CheckpointStore, PrismaCheckpoints},
+	gql::{
+		client::GqlClient,
+		prelude::{PaginateFilter, PaginateMut},
+		retry::RetryPolicy,
+	},
+	prisma::{self, PrismaClient},
+	sink::{PrismaSink, Sink},
 	video::Video,
+	watermark::{PrismaWatermarks, WatermarkTracker},
 };
 
-async fn run_channels(
-	http: &reqwest::Client,
+/// Where the comments of the videos and their checkpoints are saved
+struct Database<'a, S: Sink + ?Sized> {
+	client: &'a PrismaClient,
+	sink: &'a S,
+	checkpoints: &'a dyn CheckpointStore,
+	/// The writer that buffers the comments, if they are copied in batches
+	bulk: Option<&'a BulkWriter>,
+}
+
+/// Gets when the oldest video of a channel in the database was created,
+/// since the videos sorted by time before it were downloaded by an earlier run
+async fn oldest_video(
+	client: &PrismaClient,
+	channel: &Channel,
+) -> chrono::DateTime<chrono::FixedOffset> {
+	match client
+		.video()
+		.find_many(vec![prisma::video::WhereParam::AuthorIdEquals(channel.id)])
+		.order_by(prisma::video::OrderByParam::CreatedAt(Direction::Asc))
+		.take(1)
+		.exec()
+		.await
+		.map(|mut v| {
+			if v.is_empty() {
+				None
+			} else {
+				Some(v.remove(0))
+			}
+		}) {
+		Ok(Some(video)) => video.created_at,
+		Ok(None) => {
+			chrono::DateTime::<chrono::Utc>::MIN_UTC.with_timezone(&chrono::FixedOffset::east(0))
+		}
+		Err(e) => panic!("Failed to fetch latest video for {}: {e}", channel.username),
+	}
+}
+
+async fn run_channels<S: Sink + ?Sized>(
+	gql: &GqlClient,
+	args: &Args,
+	retry: &RetryPolicy,
 	mut channels: Vec<Channel>,
-	limit: &mut usize,
-	threads: usize,
-	quiet: bool,
-	client: tcd::prisma::PrismaClient,
+	tracker: &mut WatermarkTracker<'_>,
+	database: &Database<'_, S>,
 	first: bool,
-) -> (tcd::prisma::PrismaClient, Vec<Channel>) {
+) -> Vec<Channel> {
+	let client = database.client;
+
 	for channel in &mut channels {
-		let mut stop = false;
-		let stop_at = channel.last_video_id.unwrap_or(0);
-
-		let start_at = if first {
-			match client
-				.video()
-				.find_many(vec![prisma::video::WhereParam::AuthorIdEquals(channel.id)])
-				.order_by(prisma::video::OrderByParam::CreatedAt(Direction::Asc))
-				.take(1)
-				.exec()
-				.await
-				.map(|mut v| {
-					if v.is_empty() {
-						None
-					} else {
-						Some(v.remove(0))
-					}
-				}) {
-				Ok(Some(video)) => video.created_at,
-				Ok(None) => chrono::DateTime::<chrono::Utc>::MIN_UTC
-					.with_timezone(&chrono::FixedOffset::east(0)),
-				Err(e) => panic!("Failed to fetch latest video for {}: {e}", channel.username),
+		let (id, sort) = (channel.id, channel.sort);
+		let mut limit = args.video_limit();
+		let mut saved = Vec::new();
+		let mut failed = Vec::new();
+		let resumed = match tracker.watermark(id).await {
+			Ok(watermark) => watermark.is_some(),
+			Err(e) => {
+				crate::report(&e);
+				continue;
 			}
+		};
+
+		// Only videos sorted by time can be skipped by their creation date,
+		// and a channel with a watermark continues from it instead
+		let start_at = if first && sort == VideoSort::Time && !resumed {
+			oldest_video(client, channel).await
 		} else {
 			chrono::DateTime::<chrono::Utc>::MIN_UTC.with_timezone(&chrono::FixedOffset::east(0))
 		};
 
-		let mut videos = channel.paginate_mut(http);
+		let mut videos = channel.paginate_mut(gql, retry);
+
+		let mut complete = true;
 
 		while let Some(container) = videos.next().await {
-			let mut videos = container.edges;
+			let container = match container {
+				Ok(container) => container,
+				Err(e) => {
+					crate::report(&e);
+					complete = false;
+					break;
+				}
+			};
+			let mut videos = container.edges.into_iter().map(Video::from).collect();
+
+			// Only videos newer than the ones downloaded by the last poll are downloaded
+			let mut stop = match tracker.retain_new(id, sort, &mut videos).await {
+				Ok(stop) => stop,
+				Err(e) => {
+					crate::report(&e);
+					break;
+				}
+			};
 
-			// If the remaining videos to download is greater than 0,
-			// update the counter and stop if it reaches 0
-			if *limit > 0 {
-				if videos.len() > *limit {
+			// Update the number of videos left to download from the channel,
+			// and stop once there are none left
+			if let Some(limit) = &mut limit {
+				if videos.len() >= *limit {
 					videos.truncate(*limit);
 					stop = true;
 				}
@@ -61,99 +122,236 @@ async fn run_channels(
 				*limit -= videos.len();
 			}
 
-			let idx = videos.iter().position(|v| v.node.id == stop_at);
+			let idx = videos.iter().position(|v| v.created_at < start_at);
 
 			if let Some(idx) = idx {
-				videos.truncate(idx);
+				videos.drain(..idx);
 				stop = true;
 			}
 
-			let idx = videos.iter().position(|v| v.node.created_at < start_at);
+			for (video, result) in futures::stream::iter(videos.into_iter().map(|v| async {
+				if let Err(e) = database.sink.save_video(&v).await {
+					crate::report(&e);
+				}
 
-			if let Some(idx) = idx {
-				videos.drain(..idx);
-				stop = true;
-			}
+				let video = v.clone_without_thumbnail();
+
+				let result = args
+					.write_video(v, gql, retry, database.sink, Some(database.checkpoints))
+					.await;
 
-			futures::stream::iter(videos.into_iter().map(Video::from).map(|v| async {
-				v.save(&client).await.ok();
-				v.write_to_pg(http, &client, !quiet).await
+				(video, result)
 			}))
-			.buffer_unordered(threads)
+			.buffer_unordered(args.threads)
 			.collect::<Vec<_>>()
-			.await;
+			.await
+			{
+				match result {
+					Ok(()) => saved.push(video),
+					Err(e) => {
+						crate::report(&e);
+						failed.push(video);
+					}
+				}
+			}
 
 			if stop {
 				break;
 			}
 		}
+
+		// The watermark must not move past comments that are still buffered
+		if !write_bulk(database.bulk, args.quiet).await {
+			continue;
+		}
+
+		// The videos on the pages that were not downloaded are unknown,
+		// so the watermark stays where it is until the next poll
+		if !complete {
+			continue;
+		}
+
+		if let Err(e) = tracker.record(id, &saved, &failed).await {
+			crate::report(&e);
+		}
 	}
 
-	(client, channels)
+	channels
+}
+
+async fn run_videos<S: Sink + ?Sized>(
+	gql: &GqlClient,
+	args: &Args,
+	retry: &RetryPolicy,
+	database: &Database<'_, S>,
+) {
+	let videos = Video::paginate_filter(gql, retry, &args.video);
+	let mut chunked = videos.chunks(args.threads);
+
+	while let Some(chunk) = chunked.next().await {
+		futures::stream::iter(
+			chunk
+				.into_iter()
+				.filter_map(|v| match v {
+					Ok(v) => Some(Video::from(v)),
+					Err(e) => {
+						crate::report(&e);
+						None
+					}
+				})
+				.map(|v| async {
+					if let Err(e) = v.save_to(database.sink).await {
+						crate::report(&e);
+					}
+
+					args.write_video(v, gql, retry, database.sink, Some(database.checkpoints))
+						.await
+				}),
+		)
+		.buffer_unordered(args.threads)
+		.for_each(|result| async move {
+			if let Err(e) = result {
+				crate::report(&e);
+			}
+		})
+		.await;
+	}
 }
 
-pub async fn run(http: reqwest::Client, args: Args) {
+pub async fn run(gql: GqlClient, args: Args) {
 	let client = match prisma::new_client().await {
 		Ok(client) => client,
 		Err(e) => panic!("Failed to connect to database: {e}"),
 	};
 
+	if args.clips || !args.clip.is_empty() {
+		crate::clips::run(&gql, &args, &PrismaSink::new(&client)).await;
+
+		return;
+	}
+
+	let checkpoints = PrismaCheckpoints::new(&client, args.resume);
+	let sink = PrismaSink::new(&client);
+	let bulk = connect_bulk(&args).await;
+
+	if let Some(bulk) = &bulk {
+		let database = Database {
+			client: &client,
+			sink: bulk,
+			checkpoints: bulk,
+			bulk: Some(bulk),
+		};
+
+		run_database(&gql, &args, &sink, &database).await;
+	} else {
+		let database = Database {
+			client: &client,
+			sink: &sink,
+			checkpoints: &checkpoints,
+			bulk: None,
+		};
+
+		run_database(&gql, &args, &sink, &database).await;
+	}
+}
+
+/// Downloads the videos or channels in the arguments to the database,
+/// saving the details of users with `sink`
+async fn run_database<S: Sink + ?Sized>(
+	gql: &GqlClient,
+	args: &Args,
+	sink: &PrismaSink<'_>,
+	database: &Database<'_, S>,
+) {
+	let client = database.client;
+	let retry = args.retry_policy();
+	let mut enricher = args.enricher();
+
 	if args.channel.is_empty() {
-		let videos = Video::paginate_filter(&http, &args.video);
-		let mut chunked = videos.chunks(args.threads);
-
-		while let Some(chunk) = chunked.next().await {
-			futures::stream::iter(
-				chunk
-					.into_iter()
-					.filter_map(|v| match v {
-						Ok(v) => Some(Video::from(v)),
-						Err(_) => None,
-					})
-					.map(|v| v.write_to_pg(&http, &client, !args.quiet)),
-			)
-			.buffer_unordered(args.threads)
-			.collect::<Vec<_>>()
+		run_videos(gql, args, &retry, database).await;
+
+		write_bulk(database.bulk, args.quiet).await;
+		args.enrich_users(enricher.as_mut(), gql, &retry, sink)
 			.await;
-		}
 	} else {
-		let channels = futures::stream::iter(args.channel.into_iter().map(|c| {
-			// TODO: fix this without leaking
-			let c: &'static str = Box::leak(Box::from(c));
-
-			Channel::from_username(&http, c)
-		}))
-		.buffer_unordered(args.threads)
-		.filter_map(|c| async move {
-			if let Ok(Some(c)) = c {
-				Some(c)
-			} else {
-				None
-			}
-		})
-		.collect::<Vec<_>>()
-		.await;
+		let channels = args.channels(gql, sink).await;
 
-		let mut limit = args.limit.unwrap_or(0);
-		let threads = args.threads;
+		let watermarks = PrismaWatermarks::new(client, args.resume);
+		let mut tracker = WatermarkTracker::new(Some(&watermarks));
+		let mut channels =
+			run_channels(gql, args, &retry, channels, &mut tracker, database, true).await;
 
-		let (client, channels) = run_channels(
-			&http, channels, &mut limit, threads, args.quiet, client, true,
-		)
-		.await;
+		write_bulk(database.bulk, args.quiet).await;
+		args.enrich_users(enricher.as_mut(), gql, &retry, sink)
+			.await;
 
 		if args.live {
-			let mut client: tcd::prisma::PrismaClient = client;
-			let mut channels = channels;
-
 			loop {
 				tokio::time::sleep(std::time::Duration::from_secs_f64(args.wait * 60.)).await;
 
-				(client, channels) = run_channels(
-					&http, channels, &mut limit, threads, args.quiet, client, false,
-				)
-				.await;
+				if args.recheck_growing() {
+					for e in tracker
+						.recheck(
+							gql,
+							&retry,
+							database.sink,
+							database.checkpoints,
+							!args.quiet,
+						)
+						.await
+					{
+						crate::report(&e);
+					}
+				}
+
+				channels =
+					run_channels(gql, args, &retry, channels, &mut tracker, database, false).await;
+
+				write_bulk(database.bulk, args.quiet).await;
+				args.enrich_users(enricher.as_mut(), gql, &retry, sink)
+					.await;
 			}
 		}
 	}
 }
+
+/// Connects the writer that copies comments to the database in batches, if `bulk` is set
+async fn connect_bulk(args: &Args) -> Option<BulkWriter> {
+	if !args.bulk {
+		return None;
+	}
+
+	let url = std::env::var("DATABASE_URL").expect("DATABASE_URL env not set");
+
+	match BulkWriter::connect(&url, args.resume).await {
+		Ok(bulk) => Some(
+			bulk.with_batch_size(args.batch_size)
+				.with_flush_interval(Duration::from_secs_f64(args.flush_interval))
+				.with_retry_policy(args.retry_policy()),
+		),
+		Err(e) => panic!("Failed to connect to database: {e}"),
+	}
+}
+
+/// Copies the comments the bulk writer still has buffered, if there is one,
+/// returning whether everything buffered was written
+async fn write_bulk(bulk: Option<&BulkWriter>, quiet: bool) -> bool {
+	let Some(bulk) = bulk else {
+		return true;
+	};
+
+	match bulk.write().await {
+		Ok(count) => {
+			if !quiet {
+				println!("Copied {count} new comments");
+			}
+
+			true
+		}
+		Err(e) => {
+			crate::report(&e);
+
+			false
+		}
+	}
+}
@@ -0,0 +1,225 @@
+use crate::cli::Args;
+use futures::StreamExt;
+use std::path::Path;
+use tcd::{
+	channel::{Channel, VideoSort},
+	checkpoint::SqliteCheckpoints,
+	gql::{
+		client::GqlClient,
+		prelude::{PaginateFilter, PaginateMut},
+		retry::RetryPolicy,
+	},
+	sink::Sink,
+	sqlite::SqliteStore,
+	video::Video,
+	watermark::{SqliteWatermarks, WatermarkTracker},
+};
+
+async fn run_channels(
+	gql: &GqlClient,
+	args: &Args,
+	retry: &RetryPolicy,
+	mut channels: Vec<Channel>,
+	tracker: &mut WatermarkTracker<'_>,
+	store: &SqliteStore,
+	first: bool,
+) -> Vec<Channel> {
+	let checkpoints = SqliteCheckpoints::new(store, args.resume);
+
+	for channel in &mut channels {
+		let (id, sort) = (channel.id, channel.sort);
+		let mut limit = args.video_limit();
+		let mut saved = Vec::new();
+		let mut failed = Vec::new();
+		let resumed = match tracker.watermark(id).await {
+			Ok(watermark) => watermark.is_some(),
+			Err(e) => {
+				crate::report(&e);
+				continue;
+			}
+		};
+
+		// Only videos sorted by time can be skipped by their creation date,
+		// and a channel with a watermark continues from it instead
+		let start_at = if first && sort == VideoSort::Time && !resumed {
+			match store.oldest_video(channel.id).await {
+				Ok(Some(created_at)) => created_at,
+				Ok(None) => chrono::DateTime::<chrono::Utc>::MIN_UTC
+					.with_timezone(&chrono::FixedOffset::east(0)),
+				Err(e) => panic!("Failed to fetch latest video for {}: {e}", channel.username),
+			}
+		} else {
+			chrono::DateTime::<chrono::Utc>::MIN_UTC.with_timezone(&chrono::FixedOffset::east(0))
+		};
+
+		let mut videos = channel.paginate_mut(gql, retry);
+
+		let mut complete = true;
+
+		while let Some(container) = videos.next().await {
+			let container = match container {
+				Ok(container) => container,
+				Err(e) => {
+					crate::report(&e);
+					complete = false;
+					break;
+				}
+			};
+			let mut videos = container.edges.into_iter().map(Video::from).collect();
+
+			// Only videos newer than the ones downloaded by the last poll are downloaded
+			let mut stop = match tracker.retain_new(id, sort, &mut videos).await {
+				Ok(stop) => stop,
+				Err(e) => {
+					crate::report(&e);
+					break;
+				}
+			};
+
+			// Update the number of videos left to download from the channel,
+			// and stop once there are none left
+			if let Some(limit) = &mut limit {
+				if videos.len() >= *limit {
+					videos.truncate(*limit);
+					stop = true;
+				}
+
+				*limit -= videos.len();
+			}
+
+			let idx = videos.iter().position(|v| v.created_at < start_at);
+
+			if let Some(idx) = idx {
+				videos.drain(..idx);
+				stop = true;
+			}
+
+			for (video, result) in futures::stream::iter(videos.into_iter().map(|v| async {
+				if let Err(e) = store.save_video(&v).await {
+					crate::report(&e);
+				}
+
+				let video = v.clone_without_thumbnail();
+
+				let result = args
+					.write_video(v, gql, retry, store, Some(&checkpoints))
+					.await;
+
+				(video, result)
+			}))
+			.buffer_unordered(args.threads)
+			.collect::<Vec<_>>()
+			.await
+			{
+				match result {
+					Ok(()) => saved.push(video),
+					Err(e) => {
+						crate::report(&e);
+						failed.push(video);
+					}
+				}
+			}
+
+			if stop {
+				break;
+			}
+		}
+
+		// The videos on the pages that were not downloaded are unknown,
+		// so the watermark stays where it is until the next poll
+		if !complete {
+			continue;
+		}
+
+		if let Err(e) = tracker.record(id, &saved, &failed).await {
+			crate::report(&e);
+		}
+	}
+
+	channels
+}
+
+pub async fn run(gql: GqlClient, args: Args, path: &Path) {
+	let store = match SqliteStore::open(path) {
+		Ok(store) => store,
+		Err(e) => panic!("Failed to open database: {e}"),
+	};
+
+	if args.clips || !args.clip.is_empty() {
+		crate::clips::run(&gql, &args, &store).await;
+
+		return;
+	}
+
+	let retry = args.retry_policy();
+	let mut enricher = args.enricher();
+	let checkpoints = SqliteCheckpoints::new(&store, args.resume);
+
+	if args.channel.is_empty() {
+		let videos = Video::paginate_filter(&gql, &retry, &args.video);
+		let mut chunked = videos.chunks(args.threads);
+
+		while let Some(chunk) = chunked.next().await {
+			futures::stream::iter(
+				chunk
+					.into_iter()
+					.filter_map(|v| match v {
+						Ok(v) => Some(Video::from(v)),
+						Err(e) => {
+							crate::report(&e);
+							None
+						}
+					})
+					.map(|v| async {
+						if let Err(e) = store.save_video(&v).await {
+							crate::report(&e);
+						}
+
+						args.write_video(v, &gql, &retry, &store, Some(&checkpoints))
+							.await
+					}),
+			)
+			.buffer_unordered(args.threads)
+			.for_each(|result| async move {
+				if let Err(e) = result {
+					crate::report(&e);
+				}
+			})
+			.await;
+		}
+
+		args.enrich_users(enricher.as_mut(), &gql, &retry, &store)
+			.await;
+	} else {
+		let channels = args.channels(&gql, &store).await;
+
+		let watermarks = SqliteWatermarks::new(&store, args.resume);
+		let mut tracker = WatermarkTracker::new(Some(&watermarks));
+		let mut channels =
+			run_channels(&gql, &args, &retry, channels, &mut tracker, &store, true).await;
+
+		args.enrich_users(enricher.as_mut(), &gql, &retry, &store)
+			.await;
+
+		if args.live {
+			loop {
+				tokio::time::sleep(std::time::Duration::from_secs_f64(args.wait * 60.)).await;
+
+				if args.recheck_growing() {
+					for e in tracker
+						.recheck(&gql, &retry, &store, &checkpoints, !args.quiet)
+						.await
+					{
+						crate::report(&e);
+					}
+				}
+
+				channels =
+					run_channels(&gql, &args, &retry, channels, &mut tracker, &store, false).await;
+
+				args.enrich_users(enricher.as_mut(), &gql, &retry, &store)
+					.await;
+			}
+		}
+	}
+}
@@ -7,30 +7,147 @@ use std::{
 };
 use tcd::{
 	channel::Channel,
-	gql::prelude::{Format, PaginateFilter, PaginateMut, WriteChunk},
-	video::Video,
+	checkpoint::{CheckpointStore, FileCheckpoints},
+	gql::{
+		client::GqlClient,
+		prelude::{Format, PaginateFilter, PaginateMut},
+		retry::RetryPolicy,
+	},
+	parquet::ParquetWriter,
+	sink::StreamSink,
+	video::{Video, CSV_HEADER},
+	watermark::{FileWatermarks, WatermarkStore, WatermarkTracker},
 };
 
+/// Where the comments are written to
+enum Output {
+	Stream(Mutex<BufWriter<Box<dyn Write + Send>>>),
+	Parquet(Box<ParquetWriter<Box<dyn Write + Send>>>),
+}
+
+impl Output {
+	async fn write(
+		&self,
+		video: Video,
+		gql: &GqlClient,
+		args: &Args,
+		retry: &RetryPolicy,
+		format: &Format,
+		checkpoints: Option<&dyn CheckpointStore>,
+	) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		match self {
+			Output::Stream(stream) => {
+				let sink = StreamSink::new(stream, format);
+
+				args.write_video(video, gql, retry, &sink, checkpoints)
+					.await
+					.map_err(Into::into)
+			}
+			Output::Parquet(writer) => args
+				.write_video(video, gql, retry, writer.as_ref(), None)
+				.await
+				.map_err(Into::into),
+		}
+	}
+
+	/// Writes the chat of the clips in the arguments
+	async fn write_clips(&self, gql: &GqlClient, args: &Args, format: &Format) {
+		match self {
+			Output::Stream(stream) => {
+				crate::clips::run(gql, args, &StreamSink::new(stream, format)).await;
+			}
+			Output::Parquet(writer) => crate::clips::run(gql, args, writer.as_ref()).await,
+		}
+	}
+
+	/// Downloads the chat sent since the last poll for the videos that were still recording
+	async fn recheck(
+		&self,
+		tracker: &mut WatermarkTracker<'_>,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		format: &Format,
+		checkpoints: Option<&dyn CheckpointStore>,
+	) {
+		// Videos can only be downloaded again from where they left off with a checkpoint
+		if let (Output::Stream(stream), Some(checkpoints)) = (self, checkpoints) {
+			let sink = StreamSink::new(stream, format);
+
+			for e in tracker.recheck(gql, retry, &sink, checkpoints, false).await {
+				crate::report(&e);
+			}
+		}
+	}
+
+	/// Flushes the stream. Parquet files are only written out by `finish`.
+	fn flush(&self) {
+		if let Output::Stream(stream) = self {
+			stream
+				.lock()
+				.unwrap()
+				.flush()
+				.expect("Failed to flush output file");
+		}
+	}
+
+	fn finish(self) {
+		let mut out = match self {
+			Output::Stream(stream) => stream
+				.into_inner()
+				.unwrap()
+				.into_inner()
+				.unwrap_or_else(|_| panic!("Failed to flush output file")),
+			Output::Parquet(writer) => writer.finish().expect("Failed to write the Parquet file"),
+		};
+
+		out.flush().expect("Failed to flush output file");
+	}
+}
+
 async fn run_channels(
-	http: &reqwest::Client,
+	gql: &GqlClient,
+	args: &Args,
+	retry: &RetryPolicy,
 	mut channels: Vec<Channel>,
-	limit: &mut usize,
-	threads: usize,
-	stream: Mutex<BufWriter<Box<dyn Write + Send>>>,
-	format: &Format,
-) -> (Mutex<BufWriter<Box<dyn Write + Send>>>, Vec<Channel>) {
+	tracker: &mut WatermarkTracker<'_>,
+	output: &Output,
+	checkpoints: Option<&dyn CheckpointStore>,
+) -> Vec<Channel> {
+	let format = Format::from(&args.format);
+
 	for channel in &mut channels {
-		let mut stop = false;
-		let stop_at = channel.last_video_id.unwrap_or(0);
-		let mut videos = channel.paginate_mut(http);
+		let (id, sort) = (channel.id, channel.sort);
+		let mut limit = args.video_limit();
+		let mut saved = Vec::new();
+		let mut failed = Vec::new();
+		let mut videos = channel.paginate_mut(gql, retry);
+
+		let mut complete = true;
 
 		while let Some(container) = videos.next().await {
-			let mut videos = container.edges;
+			let container = match container {
+				Ok(container) => container,
+				Err(e) => {
+					crate::report(&e);
+					complete = false;
+					break;
+				}
+			};
+			let mut videos = container.edges.into_iter().map(Video::from).collect();
 
-			// If the remaining videos to download is greater than 0,
-			// update the counter and stop if it reaches 0
-			if *limit > 0 {
-				if videos.len() > *limit {
+			// Only videos newer than the ones downloaded by the last poll are downloaded
+			let mut stop = match tracker.retain_new(id, sort, &mut videos).await {
+				Ok(stop) => stop,
+				Err(e) => {
+					crate::report(&e);
+					break;
+				}
+			};
+
+			// Update the number of videos left to download from the channel,
+			// and stop once there are none left
+			if let Some(limit) = &mut limit {
+				if videos.len() >= *limit {
 					videos.truncate(*limit);
 					stop = true;
 				}
@@ -38,118 +155,232 @@ async fn run_channels(
 				*limit -= videos.len();
 			}
 
-			let idx = videos.iter().position(|v| v.node.id == stop_at);
+			for (video, result) in futures::stream::iter(videos.into_iter().map(|v| async {
+				let video = v.clone_without_thumbnail();
 
-			if let Some(idx) = idx {
-				videos.truncate(idx);
-				stop = true;
-			}
+				let result = output
+					.write(v, gql, args, retry, &format, checkpoints)
+					.await;
 
-			futures::stream::iter(
-				videos
-					.into_iter()
-					.map(Video::from)
-					.map(|v| v.write_to_stream(http, &stream, format)),
-			)
-			.buffer_unordered(threads)
+				(video, result)
+			}))
+			.buffer_unordered(args.threads)
 			.collect::<Vec<_>>()
-			.await;
+			.await
+			{
+				match result {
+					Ok(()) => saved.push(video),
+					Err(e) => {
+						crate::report(e.as_ref());
+						failed.push(video);
+					}
+				}
+			}
 
 			if stop {
 				break;
 			}
 		}
+
+		// The videos on the pages that were not downloaded are unknown,
+		// so the watermark stays where it is until the next poll
+		if !complete {
+			continue;
+		}
+
+		if let Err(e) = tracker.record(id, &saved, &failed).await {
+			crate::report(&e);
+		}
 	}
 
-	stream
-		.lock()
-		.unwrap()
-		.flush()
-		.expect("Failed to flush output file");
+	output.flush();
 
-	(stream, channels)
+	channels
 }
 
-pub async fn run(http: reqwest::Client, mut args: Args) {
-	// Suppress logs when writing to a file or stdout
-	args.quiet = true;
+/// Opens the output file, or stdout, and whether it was empty
+fn open_output(args: &Args) -> (Box<dyn Write + Send>, bool) {
+	// When resuming, keep what has already been written and append to it
+	let mut empty = true;
+	let stream: Box<dyn Write + Send> = if let Some(path) = &args.output {
+		match File::options()
+			.write(true)
+			.create(true)
+			.append(args.resume)
+			.truncate(!args.resume)
+			.open(path)
+		{
+			Ok(file) => {
+				empty = file.metadata().map_or(true, |m| m.len() == 0);
 
-	let stream: Mutex<BufWriter<Box<dyn Write + Send>>> = if let Some(path) = &args.output {
-		match File::options().write(true).create(true).open(path) {
-			Ok(file) => Mutex::new(BufWriter::new(Box::new(file))),
+				Box::new(file)
+			}
 			Err(e) => {
 				panic!("Failed to open output file: {e}");
 			}
 		}
 	} else {
-		Mutex::new(BufWriter::new(Box::new(std::io::stdout())))
+		Box::new(std::io::stdout())
 	};
 
+	(stream, empty)
+}
+
+/// Opens the watermark file next to the output file, if there is one
+fn open_watermarks(args: &Args, format: &Format) -> Option<FileWatermarks> {
+	let path = args
+		.output
+		.as_ref()
+		.filter(|_| *format != Format::Parquet)?;
+
+	match FileWatermarks::open(FileWatermarks::sidecar(path), args.resume) {
+		Ok(watermarks) => Some(watermarks),
+		Err(e) => panic!("Failed to open watermark file: {e}"),
+	}
+}
+
+async fn run_videos(
+	gql: &GqlClient,
+	args: &Args,
+	retry: &RetryPolicy,
+	output: &Output,
+	checkpoints: Option<&dyn CheckpointStore>,
+) {
+	let format = Format::from(&args.format);
+	let videos = Video::paginate_filter(gql, retry, &args.video);
+	let mut chunked = videos.chunks(args.threads);
+
+	while let Some(chunk) = chunked.next().await {
+		futures::stream::iter(
+			chunk
+				.into_iter()
+				.filter_map(|v| match v {
+					Ok(v) => Some(Video::from(v)),
+					Err(e) => {
+						crate::report(&e);
+						None
+					}
+				})
+				.map(|v| output.write(v, gql, args, retry, &format, checkpoints)),
+		)
+		.buffer_unordered(args.threads)
+		.for_each(|result| async move {
+			if let Err(e) = result {
+				crate::report(e.as_ref());
+			}
+		})
+		.await;
+	}
+}
+
+pub async fn run(gql: GqlClient, mut args: Args) {
+	// Suppress logs when writing to a file or stdout
+	args.quiet = true;
+
 	let format = Format::from(&args.format);
 
-	if format == Format::Csv {
+	// A Parquet file is only readable once its footer has been written,
+	// so it can neither be appended to nor left open while polling
+	if format == Format::Parquet {
+		assert!(
+			!args.resume && !args.live,
+			"--resume and --live cannot be used with --format parquet"
+		);
+	}
+
+	let (out, empty) = open_output(&args);
+
+	let output = if format == Format::Parquet {
+		Output::Parquet(Box::new(
+			ParquetWriter::new(out, args.row_group_size).expect("Failed to open the Parquet file"),
+		))
+	} else {
+		Output::Stream(Mutex::new(BufWriter::new(out)))
+	};
+
+	let checkpoints = args
+		.output
+		.as_ref()
+		.filter(|_| format != Format::Parquet)
+		.map(
+			|path| match FileCheckpoints::open(FileCheckpoints::sidecar(path), args.resume) {
+				Ok(checkpoints) => checkpoints,
+				Err(e) => panic!("Failed to open checkpoint file: {e}"),
+			},
+		);
+	let checkpoints = checkpoints.as_ref().map(|c| c as &dyn CheckpointStore);
+	let watermarks = open_watermarks(&args, &format);
+	let mut tracker = WatermarkTracker::new(watermarks.as_ref().map(|w| w as &dyn WatermarkStore));
+
+	if let (Format::Csv, true, Output::Stream(stream)) = (&format, empty, &output) {
 		stream
 			.lock()
 			.unwrap()
-			.write_all(b"channel,video_id,comment_id,commenter,created_at,text\n")
+			.write_all(format!("{CSV_HEADER}\n").as_bytes())
 			.expect("Failed to write to output file");
 	}
 
-	if args.channel.is_empty() {
-		let videos = Video::paginate_filter(&http, &args.video);
-		let mut chunked = videos.chunks(args.threads);
-
-		while let Some(chunk) = chunked.next().await {
-			futures::stream::iter(
-				chunk
-					.into_iter()
-					.filter_map(|v| match v {
-						Ok(v) => Some(Video::from(v)),
-						Err(_) => None,
-					})
-					.map(|v| v.write_to_stream(&http, &stream, &format)),
-			)
-			.buffer_unordered(args.threads)
-			.collect::<Vec<_>>()
-			.await;
-		}
+	let retry = args.retry_policy();
+
+	if args.clips || !args.clip.is_empty() {
+		output.write_clips(&gql, &args, &format).await;
+	} else if args.channel.is_empty() {
+		run_videos(&gql, &args, &retry, &output, checkpoints).await;
 	} else {
-		let channels = futures::stream::iter(args.channel.into_iter().map(|c| {
+		let channels = futures::stream::iter(args.channel.iter().map(|c| {
 			// TODO: fix this without leaking
-			let c: &'static str = Box::leak(Box::from(c));
+			let c: &'static str = Box::leak(Box::from(c.as_str()));
 
-			Channel::from_username(&http, c)
+			Channel::from_username(&gql, c)
 		}))
 		.buffer_unordered(args.threads)
 		.filter_map(|c| async move {
-			if let Ok(Some(c)) = c {
-				Some(c)
-			} else {
-				None
+			match c {
+				Ok(c) => c,
+				Err(e) => {
+					crate::report(&e);
+					None
+				}
 			}
 		})
+		.map(|c| args.filter_channel(c))
 		.collect::<Vec<_>>()
 		.await;
 
-		let mut limit = args.limit.unwrap_or(0);
-		let threads = args.threads;
-		let (stream, channels) =
-			run_channels(&http, channels, &mut limit, threads, stream, &format).await;
+		let mut channels = run_channels(
+			&gql,
+			&args,
+			&retry,
+			channels,
+			&mut tracker,
+			&output,
+			checkpoints,
+		)
+		.await;
 
 		if args.live {
-			let mut stream: Mutex<BufWriter<Box<dyn Write + Send>>> = stream;
-			let mut channels = channels;
-
 			loop {
 				tokio::time::sleep(std::time::Duration::from_secs_f64(args.wait * 60.)).await;
 
-				(stream, channels) =
-					run_channels(&http, channels, &mut limit, threads, stream, &format).await;
+				if args.recheck_growing() {
+					output
+						.recheck(&mut tracker, &gql, &retry, &format, checkpoints)
+						.await;
+				}
+
+				channels = run_channels(
+					&gql,
+					&args,
+					&retry,
+					channels,
+					&mut tracker,
+					&output,
+					checkpoints,
+				)
+				.await;
 			}
 		}
-
-		let mut stream = stream.lock().unwrap();
-
-		stream.flush().expect("Failed to flush output file");
 	}
+
+	output.finish();
 }
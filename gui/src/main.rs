@@ -7,7 +7,7 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::modal::Modal;
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
 use iced::alignment::{self, Alignment};
 use iced::theme::{self, Theme};
 use iced::widget::image::Handle;
@@ -19,30 +19,28 @@ use iced::{Application, Command, Element, Font, Length, Settings};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use tcd::channel::{Channel, ChannelError};
+use tcd::gql::client::{GqlClient, GqlConfig};
+use tcd::gql::limiter::{RateLimiter, DEFAULT_BURST, DEFAULT_RATE};
 use tcd::gql::prelude::{ChunkError, PaginateMut, WriteChunk};
+use tcd::gql::retry::RetryPolicy;
 use tcd::video::Video;
 
-static CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
-static HTTP: Lazy<reqwest::Client> = Lazy::new(|| {
-	let mut headers = reqwest::header::HeaderMap::new();
-	let client_id = std::env::var("CLIENT_ID");
+static HTTP: Lazy<GqlClient> = Lazy::new(|| {
+	let mut gql = GqlClient::new(reqwest::Client::new());
 
-	headers.insert(
-		"Client-ID",
-		reqwest::header::HeaderValue::from_str(if let Ok(client_id) = client_id.as_ref() {
-			client_id
-		// Otherwise, use the default client ID
-		} else {
-			CLIENT_ID
-		})
-		.expect("Invalid CLIENT_ID header value"),
-	);
-
-	reqwest::ClientBuilder::new()
-		.default_headers(headers)
-		.build()
-		.expect("Failed to build HTTP client")
+	if let Ok(path) = std::env::var("GQL_CONFIG") {
+		gql = gql.with_config(
+			GqlConfig::from_file(path.as_ref()).expect("Failed to read the GQL config"),
+		);
+	}
+
+	if let Ok(client_id) = std::env::var("CLIENT_ID") {
+		gql = gql.with_client_id(client_id);
+	}
+
+	gql.with_rate_limit(RateLimiter::new(DEFAULT_RATE, DEFAULT_BURST))
 });
+static RETRY: Lazy<RetryPolicy> = Lazy::new(RetryPolicy::default);
 static NEXT_ID: AtomicU64 = AtomicU64::new(0);
 
 pub fn main() -> iced::Result {
@@ -159,7 +157,10 @@ impl Application for App {
 				if let Some(Ok(mut channel)) = self.channel.clone() {
 					Command::perform(
 						async move {
-							let data = channel.paginate_mut(&HTTP).collect::<Vec<_>>().await;
+							let data = channel
+								.paginate_mut(&HTTP, &RETRY)
+								.try_collect::<Vec<_>>()
+								.await?;
 							let videos = data
 								.into_iter()
 								.flat_map(|container| container.edges.into_iter().map(Video::from))
@@ -167,14 +168,14 @@ impl Application for App {
 
 							let videos = futures::stream::iter(videos)
 								.map(|mut video| async move {
-									video.get_thumbnail(&HTTP).await.ok();
+									video.get_thumbnail(HTTP.http()).await.ok();
 									video
 								})
 								.buffered(10)
 								.collect::<Vec<_>>()
 								.await;
 
-							Ok(videos)
+							Ok::<_, ChunkError>(videos)
 						},
 						Message::VideoResult,
 					)
@@ -220,7 +221,13 @@ impl Application for App {
 						let stream = Arc::new(Mutex::new(BufWriter::new(Box::new(writer))));
 
 						video
-							.write_to_stream(&HTTP, &stream, &tcd::gql::prelude::Format::Csv)
+							.write_to_stream(
+								&HTTP,
+								&RETRY,
+								&stream,
+								&tcd::gql::prelude::Format::Csv,
+								None,
+							)
 							.await
 					},
 					move |_| Message::Downloaded(task_id),
@@ -4,6 +4,7 @@ SELECT
 	"Comment"."id" AS "comment_id",
 	"Author"."username" AS "commenter",
 	"Comment"."createdAt" AS "created_at",
+	"Comment"."offset" AS "offset",
 	"Comment"."text" AS "text"
 FROM "Comment"
 	LEFT JOIN "User" "Author"
@@ -27,9 +27,6 @@ CREATE TABLE "User" (
     CONSTRAINT "User_pkey" PRIMARY KEY ("id")
 );
 
--- AddForeignKey
-ALTER TABLE "CommentFragment" ADD CONSTRAINT "CommentFragment_commentId_fkey" FOREIGN KEY ("commentId") REFERENCES "Comment"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
-
 -- AddForeignKey
 ALTER TABLE "Comment" ADD CONSTRAINT "Comment_userId_fkey" FOREIGN KEY ("userId") REFERENCES "User"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
 
@@ -38,3 +35,124 @@ ALTER TABLE "Comment" ADD CONSTRAINT "Comment_videoId_fkey" FOREIGN KEY ("videoI
 
 -- AddForeignKey
 ALTER TABLE "Video" ADD CONSTRAINT "Video_authorId_fkey" FOREIGN KEY ("authorId") REFERENCES "User"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
+
+-- A database created with only the tables above is upgraded by the statements below
+
+-- CreateTable
+CREATE TABLE "CommentBadge" (
+    "commentId" UUID NOT NULL,
+    "setId" TEXT NOT NULL,
+    "version" TEXT NOT NULL,
+
+    CONSTRAINT "CommentBadge_pkey" PRIMARY KEY ("commentId","setId")
+);
+
+-- CreateTable
+CREATE TABLE "CommentFragment" (
+    "commentId" UUID NOT NULL,
+    "position" INTEGER NOT NULL,
+    "text" TEXT NOT NULL,
+    "emoteId" TEXT,
+    "emoteStart" INTEGER,
+    "emoteEnd" INTEGER,
+
+    CONSTRAINT "CommentFragment_pkey" PRIMARY KEY ("commentId","position")
+);
+
+-- CreateTable
+CREATE TABLE "Checkpoint" (
+    "videoId" BIGINT NOT NULL,
+    "cursor" TEXT,
+    "offset" INTEGER NOT NULL,
+    "completed" BOOLEAN NOT NULL DEFAULT false,
+    "updatedAt" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "Checkpoint_pkey" PRIMARY KEY ("videoId")
+);
+
+-- CreateTable
+CREATE TABLE "Watermark" (
+    "channelId" BIGINT NOT NULL,
+    "videoId" BIGINT NOT NULL,
+    "createdAt" TIMESTAMP(3) NOT NULL,
+    "updatedAt" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "Watermark_pkey" PRIMARY KEY ("channelId")
+);
+
+-- CreateTable
+CREATE TABLE "GrowingVideo" (
+    "videoId" BIGINT NOT NULL,
+    "channelId" BIGINT NOT NULL,
+    "length" INTEGER NOT NULL,
+
+    CONSTRAINT "GrowingVideo_pkey" PRIMARY KEY ("videoId")
+);
+
+-- CreateTable
+CREATE TABLE "UsernameHistory" (
+    "userId" BIGINT NOT NULL,
+    "username" TEXT NOT NULL,
+    "firstSeen" TIMESTAMP(3) NOT NULL,
+    "lastSeen" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "UsernameHistory_pkey" PRIMARY KEY ("userId","username")
+);
+
+-- CreateTable
+CREATE TABLE "Clip" (
+    "id" BIGINT NOT NULL,
+    "slug" TEXT NOT NULL,
+    "title" TEXT NOT NULL,
+    "broadcasterId" BIGINT NOT NULL,
+    "videoId" BIGINT,
+    "createdAt" TIMESTAMP(3) NOT NULL,
+    "duration" INTEGER NOT NULL,
+    "offset" INTEGER,
+
+    CONSTRAINT "Clip_pkey" PRIMARY KEY ("id")
+);
+
+-- AlterTable
+ALTER TABLE "Comment" ADD COLUMN "offset" INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN "color" TEXT,
+ADD COLUMN "kind" TEXT NOT NULL DEFAULT 'chat',
+ADD COLUMN "bits" INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN "subTier" TEXT,
+ADD COLUMN "subMonths" INTEGER,
+ADD COLUMN "giftCount" INTEGER,
+ADD COLUMN "giftRecipient" TEXT,
+ADD COLUMN "raidViewers" INTEGER,
+ADD COLUMN "inferred" BOOLEAN NOT NULL DEFAULT false,
+ADD COLUMN "deleted" BOOLEAN NOT NULL DEFAULT false;
+
+-- Comments saved before offsets were recorded start at 0, new ones always have one
+ALTER TABLE "Comment" ALTER COLUMN "offset" DROP DEFAULT;
+
+-- AlterTable
+ALTER TABLE "Video" ADD COLUMN "title" TEXT,
+ADD COLUMN "length" INTEGER,
+ADD COLUMN "thumbnailUrl" TEXT,
+ADD COLUMN "game" TEXT,
+ADD COLUMN "viewCount" BIGINT,
+ADD COLUMN "language" TEXT;
+
+-- AlterTable
+ALTER TABLE "User" ADD COLUMN "displayName" TEXT,
+ADD COLUMN "profileImageUrl" TEXT,
+ADD COLUMN "description" TEXT;
+
+-- AddForeignKey
+ALTER TABLE "CommentFragment" ADD CONSTRAINT "CommentFragment_commentId_fkey" FOREIGN KEY ("commentId") REFERENCES "Comment"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "CommentBadge" ADD CONSTRAINT "CommentBadge_commentId_fkey" FOREIGN KEY ("commentId") REFERENCES "Comment"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "UsernameHistory" ADD CONSTRAINT "UsernameHistory_userId_fkey" FOREIGN KEY ("userId") REFERENCES "User"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "Clip" ADD CONSTRAINT "Clip_broadcasterId_fkey" FOREIGN KEY ("broadcasterId") REFERENCES "User"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
+
+-- AddForeignKey
+ALTER TABLE "Clip" ADD CONSTRAINT "Clip_videoId_fkey" FOREIGN KEY ("videoId") REFERENCES "Video"("id") ON DELETE SET NULL ON UPDATE CASCADE;
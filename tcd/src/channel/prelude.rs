@@ -1,117 +1,229 @@
-use async_stream::stream;
+use async_stream::{stream, try_stream};
 use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
 use futures::stream::BoxStream;
+#[cfg(feature = "postgres")]
 use prisma_client_rust::QueryError;
 
+#[cfg(feature = "postgres")]
+use crate::{
+	gql::prelude::Save,
+	prisma::{self, PrismaClient},
+};
 use crate::{
 	gql::{
-		prelude::{Chunk, ChunkError, PaginateMut, Save},
-		request::{
-			GqlPlayerContextVariables, GqlRequest, GqlRequestExtensions, GqlRequestPersistedQuery,
-			GqlVideoFilterVariables, GqlViewerCardVariables,
-		},
+		client::GqlClient,
+		prelude::{Chunk, ChunkError, PaginateMut, RequestError},
+		request::{GqlClipsCriteria, GqlClipsFilterVariables, GqlVideoFilterVariables, Operation},
+		retry::RetryPolicy,
 		structs::{
-			GqlChannelResponse, GqlEdgeContainer, GqlResponse, GqlTrackedUserResponse,
-			GqlUserResponse, GqlVideo,
+			GqlClip, GqlClipsUserResponse, GqlEdgeContainer, GqlResponse, GqlTrackedUserResponse,
+			GqlVideo,
 		},
 	},
-	prisma::PrismaClient,
+	user::User,
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Channel {
 	pub id: i64,
 	pub username: String,
+	/// The oldest video paginated so far
 	pub last_video_id: Option<i64>,
+	/// The kind of videos to paginate
+	pub broadcast_type: BroadcastType,
+	/// The order to paginate videos in
+	pub sort: VideoSort,
+	/// Skips videos created before this time
+	pub since: Option<DateTime<FixedOffset>>,
+	/// Skips videos created after this time
+	pub until: Option<DateTime<FixedOffset>>,
+}
+
+/// The kind of a video, depending on how it was published
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BroadcastType {
+	/// A past broadcast, saved automatically
+	#[default]
+	Archive,
+	/// A part of a past broadcast, saved by the channel
+	Highlight,
+	/// A video uploaded by the channel
+	Upload,
+	/// An upload that was first shown as a premiere
+	PastPremiere,
+	/// Every kind of video
+	All,
+}
+
+impl BroadcastType {
+	/// The value of the `broadcastType` variable, which is omitted for [`BroadcastType::All`]
+	#[must_use]
+	pub const fn name(self) -> Option<&'static str> {
+		match self {
+			Self::Archive => Some("ARCHIVE"),
+			Self::Highlight => Some("HIGHLIGHT"),
+			Self::Upload => Some("UPLOAD"),
+			Self::PastPremiere => Some("PAST_PREMIERE"),
+			Self::All => None,
+		}
+	}
 }
 
-#[derive(Clone, Debug)]
-pub enum ChannelError {
-	Request,
-	Json,
+/// The order of a channel's videos
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VideoSort {
+	/// Newest first
+	#[default]
+	Time,
+	/// Most viewed first
+	Views,
 }
 
+impl VideoSort {
+	/// The value of the `videoSort` variable
+	#[must_use]
+	pub const fn name(self) -> &'static str {
+		match self {
+			Self::Time => "TIME",
+			Self::Views => "VIEWS",
+		}
+	}
+}
+
+/// An error that occurred while looking up a channel by its username
+pub type ChannelError = RequestError;
+
 impl Channel {
 	/// Gets a channel from a username
 	#[allow(clippy::missing_errors_doc)]
 	pub async fn from_username<S: Into<String>>(
-		http: &reqwest::Client,
+		gql: &GqlClient,
 		username: S,
 	) -> Result<Option<Self>, ChannelError> {
-		let user = http
-			.post("https://gql.twitch.tv/gql")
-			.json(&GqlRequest {
-				operation_name: "PlayerTrackingContextQuery",
-				variables: GqlPlayerContextVariables {
-					channel: username.into().as_str(),
-					is_live: true,
-					has_collection: false,
-					collection_id: "",
-					video_id: "",
-					has_video: false,
-					slug: "",
-					has_clip: false,
-				},
-				extensions: GqlRequestExtensions {
-					persisted_query: GqlRequestPersistedQuery {
-						version: 1,
-						sha256_hash:
-							"3fbf508886ff5e008cb94047acc752aad7428c07b6055995604de16c4b01160a",
-					},
+		Ok(User::from_username(gql, username).await?.map(Self::from))
+	}
+
+	/// Paginates a different kind of videos
+	#[must_use]
+	pub fn with_broadcast_type(self, broadcast_type: BroadcastType) -> Self {
+		Self {
+			broadcast_type,
+			..self
+		}
+	}
+
+	/// Paginates videos in a different order
+	#[must_use]
+	pub fn with_sort(self, sort: VideoSort) -> Self {
+		Self { sort, ..self }
+	}
+
+	/// Only paginates videos created between `since` and `until`, inclusive
+	#[must_use]
+	pub fn with_date_range(
+		self,
+		since: Option<DateTime<FixedOffset>>,
+		until: Option<DateTime<FixedOffset>>,
+	) -> Self {
+		Self {
+			since,
+			until,
+			..self
+		}
+	}
+
+	/// Whether a video created at `created_at` is in the date range
+	#[must_use]
+	pub fn contains(&self, created_at: DateTime<FixedOffset>) -> bool {
+		self.since.is_none_or(|since| created_at >= since)
+			&& self.until.is_none_or(|until| created_at <= until)
+	}
+
+	/// Gets a page of the channel's most viewed clips
+	async fn clips_chunk(
+		&self,
+		gql: &GqlClient,
+		cursor: Option<&str>,
+	) -> Result<GqlEdgeContainer<GqlClip>, ChunkError> {
+		let body: GqlResponse<GqlClipsUserResponse> = gql
+			.request(
+				Operation::ClipsCardsUser,
+				GqlClipsFilterVariables {
+					username: &self.username,
+					limit: 20,
+					criteria: GqlClipsCriteria { filter: "ALL_TIME" },
+					cursor,
 				},
-			})
-			.send()
+			)
 			.await
-			.map_err(|_| ChannelError::Request)?;
-
-		let user: GqlResponse<GqlChannelResponse> =
-			user.json().await.map_err(|_| ChannelError::Json)?;
-		let user = match user.data.user {
-			Some(user) => user,
-			None => return Ok(None),
-		};
-
-		let user = http
-			.post("https://gql.twitch.tv/gql")
-			.json(&GqlRequest {
-				operation_name: "ViewerCard",
-				variables: GqlViewerCardVariables {
-					channel_id: user.id,
-					channel_name: &user.username,
-					has_channel_id: true,
-					username: &user.username,
-					badge_collection: true,
-					standard_gifting: false,
-				},
-				extensions: GqlRequestExtensions {
-					persisted_query: GqlRequestPersistedQuery {
-						version: 1,
-						sha256_hash:
-							"20e51233313878f971daa32dfc039b2e2183822e62c13f47c48448d5d5e4f5e9",
-					},
-				},
+			.map_err(|e| {
+				ChunkError::from_response(e, Operation::ClipsCardsUser, self.id.to_string())
+			})?;
+
+		body.data
+			.user
+			.and_then(|user| user.clips)
+			.ok_or(ChunkError::DataMissing {
+				operation: Operation::ClipsCardsUser.name(),
+				id: self.id,
 			})
-			.send()
-			.await
-			.map_err(|_| ChannelError::Request)?;
+	}
+
+	/// Gets a stream of all clips of the channel, most viewed first.
+	/// Use [`crate::clip::Clip::from_slug`] to find the video each clip was cut from.
+	#[must_use]
+	pub fn clips<'a>(
+		&'a self,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<GqlClip>, ChunkError>> {
+		Box::pin(try_stream! {
+			let mut data = retry.run(|| self.clips_chunk(gql, None)).await?;
+
+			loop {
+				let has_next = data.page_info.has_next_page;
+				let cursor = data.edges.last().and_then(|edge| edge.cursor.clone());
 
-		let user: GqlResponse<GqlUserResponse> =
-			user.json().await.map_err(|_| ChannelError::Json)?;
+				yield data;
 
-		Ok(Some(Self {
-			id: user.data.user.id,
-			username: user.data.user.username,
+				match cursor {
+					Some(cursor) if has_next => {
+						data = retry.run(|| self.clips_chunk(gql, Some(cursor.as_str()))).await?;
+					}
+					_ => break,
+				}
+			}
+		})
+	}
+}
+
+impl From<User> for Channel {
+	fn from(user: User) -> Self {
+		Self {
+			id: user.id,
+			username: user.username,
 			last_video_id: None,
-		}))
+			broadcast_type: BroadcastType::default(),
+			sort: VideoSort::default(),
+			since: None,
+			until: None,
+		}
 	}
 }
 
+#[cfg(feature = "postgres")]
 #[async_trait]
 impl Save for Channel {
+	/// Saves the channel's user, renaming an existing one
 	async fn save(&self, client: &PrismaClient) -> Result<(), QueryError> {
 		client
 			.user()
-			.create(self.id, self.username.clone(), vec![])
+			.upsert(
+				prisma::user::UniqueWhereParam::IdEquals(self.id),
+				prisma::user::create(self.id, self.username.clone(), vec![]),
+				vec![prisma::user::username::set(self.username.clone())],
+			)
 			.exec()
 			.await?;
 
@@ -124,92 +236,86 @@ impl Chunk<GqlEdgeContainer<GqlVideo>> for Channel {
 	/// Gets the next chunk of videos for the channel from a cursor
 	async fn chunk_by_cursor<'a, S: Into<&'a str> + Send>(
 		&self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
 		cursor: S,
 	) -> Result<GqlEdgeContainer<GqlVideo>, ChunkError> {
-		let response = http
-			.post("https://gql.twitch.tv/gql")
-			.json(&GqlRequest {
-				operation_name: "FilterableVideoTower_Videos",
-				variables: GqlVideoFilterVariables {
+		let body: GqlResponse<GqlTrackedUserResponse> = gql
+			.request(
+				Operation::FilterableVideoTowerVideos,
+				GqlVideoFilterVariables {
 					limit: 30,
 					username: &self.username,
-					r#type: "ARCHIVE",
-					sort: "TIME",
+					r#type: self.broadcast_type.name(),
+					sort: self.sort.name(),
 					cursor: Some(cursor.into()),
 				},
-				extensions: GqlRequestExtensions {
-					persisted_query: GqlRequestPersistedQuery {
-						version: 1,
-						sha256_hash:
-							"a937f1d22e269e39a03b509f65a7490f9fc247d7f83d6ac1421523e3b68042cb",
-					},
-				},
-			})
-			.send()
+			)
 			.await
-			.map_err(|_| ChunkError::Reqwest)?;
+			.map_err(|e| {
+				ChunkError::from_response(
+					e,
+					Operation::FilterableVideoTowerVideos,
+					self.id.to_string(),
+				)
+			})?;
 
-		let body: GqlResponse<GqlTrackedUserResponse> =
-			response.json().await.map_err(|_| ChunkError::Reqwest)?;
-
-		body.data.user.videos.ok_or(ChunkError::DataMissing)
+		body.data.user.videos.ok_or(ChunkError::DataMissing {
+			operation: Operation::FilterableVideoTowerVideos.name(),
+			id: self.id,
+		})
 	}
 
 	/// Gets the first chunk of videos for the channel
-	async fn first_chunk(
-		&self,
-		http: &reqwest::Client,
-	) -> Result<GqlEdgeContainer<GqlVideo>, ChunkError> {
-		let response = http
-			.post("https://gql.twitch.tv/gql")
-			.json(&GqlRequest {
-				operation_name: "FilterableVideoTower_Videos",
-				variables: GqlVideoFilterVariables {
+	async fn first_chunk(&self, gql: &GqlClient) -> Result<GqlEdgeContainer<GqlVideo>, ChunkError> {
+		let body: GqlResponse<GqlTrackedUserResponse> = gql
+			.request(
+				Operation::FilterableVideoTowerVideos,
+				GqlVideoFilterVariables {
 					limit: 30,
 					username: &self.username,
-					r#type: "ARCHIVE",
-					sort: "TIME",
+					r#type: self.broadcast_type.name(),
+					sort: self.sort.name(),
 					cursor: None,
 				},
-				extensions: GqlRequestExtensions {
-					persisted_query: GqlRequestPersistedQuery {
-						version: 1,
-						sha256_hash:
-							"a937f1d22e269e39a03b509f65a7490f9fc247d7f83d6ac1421523e3b68042cb",
-					},
-				},
-			})
-			.send()
+			)
 			.await
-			.map_err(|_| ChunkError::Reqwest)?;
-
-		let body: GqlResponse<GqlTrackedUserResponse> =
-			response.json().await.map_err(|_| ChunkError::Reqwest)?;
+			.map_err(|e| {
+				ChunkError::from_response(
+					e,
+					Operation::FilterableVideoTowerVideos,
+					self.id.to_string(),
+				)
+			})?;
 
-		body.data.user.videos.ok_or(ChunkError::DataMissing)
+		body.data.user.videos.ok_or(ChunkError::DataMissing {
+			operation: Operation::FilterableVideoTowerVideos.name(),
+			id: self.id,
+		})
 	}
 }
 
 impl PaginateMut<GqlVideo> for Channel {
-	/// Gets a stream of all videos for the channel
+	/// Gets a stream of all videos for the channel created between `since` and `until`,
+	/// ending after the first page that could not be downloaded
 	fn paginate_mut<'a>(
 		&'a mut self,
-		http: &'a reqwest::Client,
-	) -> BoxStream<'a, GqlEdgeContainer<GqlVideo>> {
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<GqlVideo>, ChunkError>> {
 		Box::pin(stream! {
 			let mut cursor: Option<String> = None;
 
 			loop {
-				let data = match cursor {
-					Some(cursor) => match self.chunk_by_cursor(http, cursor.as_str()).await {
-						Ok(data) => data,
-						Err(_) => break,
-					},
-					None => match self.first_chunk(http).await {
-						Ok(data) => data,
-						Err(_) => break,
-					},
+				let result = match cursor {
+					Some(cursor) => retry.run(|| self.chunk_by_cursor(gql, cursor.as_str())).await,
+					None => retry.run(|| self.first_chunk(gql)).await,
+				};
+				let mut data = match result {
+					Ok(data) => data,
+					Err(e) => {
+						yield Err(e);
+						break;
+					}
 				};
 
 				cursor = match data.edges.last() {
@@ -221,9 +327,18 @@ impl PaginateMut<GqlVideo> for Channel {
 					None => None,
 				};
 
-				yield data;
+				// Videos sorted by time are newest first, so every page after
+				// one with a video older than `since` only has older videos
+				let passed_since = self.sort == VideoSort::Time
+					&& self.since.is_some_and(|since| {
+						data.edges.iter().any(|edge| edge.node.created_at < since)
+					});
+
+				data.edges.retain(|edge| self.contains(edge.node.created_at));
+
+				yield Ok(data);
 
-				if cursor.is_none() {
+				if cursor.is_none() || passed_since {
 					break;
 				}
 			}
@@ -0,0 +1,313 @@
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+use std::{collections::HashSet, sync::Mutex};
+use std::{
+	fmt, io,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use async_trait::async_trait;
+#[cfg(feature = "postgres")]
+use prisma_client_rust::QueryError;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "postgres")]
+use crate::prisma::{self, PrismaClient};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStore;
+use crate::{
+	gql::prelude::StartAt,
+	sidecar::{sidecar_path, Keyed, SidecarFile},
+};
+
+/// The position of the last comment saved for a video
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+	pub video_id: i64,
+	/// The cursor of the last comment that was saved
+	pub cursor: Option<String>,
+	/// The `contentOffsetSeconds` of the last comment that was saved,
+	/// used when the cursor is missing
+	pub offset: u32,
+	/// Whether every comment for the video has been saved
+	pub completed: bool,
+}
+
+impl Checkpoint {
+	/// Where to continue paginating the comments from
+	#[must_use]
+	pub fn start_at(&self) -> StartAt {
+		match &self.cursor {
+			Some(cursor) => StartAt::Cursor(cursor.clone()),
+			None => StartAt::Offset(self.offset),
+		}
+	}
+}
+
+/// A place to persist checkpoints so that downloads can be resumed.
+/// Checkpoints left by earlier runs are only used when the store is opened with `resume`,
+/// but the ones saved since it was opened can always be loaded.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+	async fn load(&self, video_id: i64) -> Result<Option<Checkpoint>, StoreError>;
+	async fn save(&self, checkpoint: &Checkpoint) -> Result<(), StoreError>;
+
+	/// Marks every comment for the video as saved
+	async fn complete(&self, video_id: i64) -> Result<(), StoreError> {
+		let checkpoint = match self.load(video_id).await? {
+			Some(checkpoint) => Checkpoint {
+				completed: true,
+				..checkpoint
+			},
+			None => Checkpoint {
+				video_id,
+				cursor: None,
+				offset: 0,
+				completed: true,
+			},
+		};
+
+		self.save(&checkpoint).await
+	}
+
+	/// Lets the comments sent after the last saved one be downloaded,
+	/// for a video that was completed while it was still recording
+	async fn reopen(&self, video_id: i64) -> Result<(), StoreError> {
+		match self.load(video_id).await? {
+			Some(checkpoint) if checkpoint.completed => {
+				self.save(&Checkpoint {
+					completed: false,
+					..checkpoint
+				})
+				.await
+			}
+			_ => Ok(()),
+		}
+	}
+}
+
+/// An error that occurred while loading or saving checkpoints or watermarks,
+/// whose source is the error of the file or database they are stored in
+#[derive(Clone, Debug)]
+pub struct StoreError(Arc<dyn std::error::Error + Send + Sync>);
+
+impl StoreError {
+	pub(crate) fn new<E: std::error::Error + Send + Sync + 'static>(error: E) -> Self {
+		Self(Arc::new(error))
+	}
+}
+
+impl fmt::Display for StoreError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("failed to load or save the download progress")
+	}
+}
+
+impl std::error::Error for StoreError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		Some(self.0.as_ref())
+	}
+}
+
+impl From<io::Error> for StoreError {
+	fn from(error: io::Error) -> Self {
+		Self::new(error)
+	}
+}
+
+#[cfg(feature = "postgres")]
+impl From<QueryError> for StoreError {
+	fn from(error: QueryError) -> Self {
+		Self::new(error)
+	}
+}
+
+/// Gets the position to resume a video from, or `None` if it has already been completed
+pub(crate) async fn resume_from(
+	checkpoints: Option<&dyn CheckpointStore>,
+	video_id: i64,
+) -> Result<Option<StartAt>, StoreError> {
+	let checkpoint = match checkpoints {
+		Some(checkpoints) => checkpoints.load(video_id).await?,
+		None => None,
+	};
+
+	Ok(match checkpoint {
+		Some(checkpoint) if checkpoint.completed => None,
+		Some(checkpoint) => Some(checkpoint.start_at()),
+		None => Some(StartAt::Beginning),
+	})
+}
+
+impl Keyed for Checkpoint {
+	fn key(&self) -> i64 {
+		self.video_id
+	}
+}
+
+/// Decides which checkpoints in a database can be loaded: all of them when resuming,
+/// and otherwise only the ones saved since the store was created
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+pub(crate) struct ResumeFilter {
+	resume: bool,
+	saved: Mutex<HashSet<i64>>,
+}
+
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+impl ResumeFilter {
+	pub fn new(resume: bool) -> Self {
+		Self {
+			resume,
+			saved: Mutex::new(HashSet::new()),
+		}
+	}
+
+	/// Whether the checkpoint of a video in the database can be loaded
+	pub fn allows(&self, video_id: i64) -> bool {
+		self.resume || self.saved.lock().unwrap().contains(&video_id)
+	}
+
+	/// Records that the checkpoint of a video was saved by this run
+	pub fn saved(&self, video_id: i64) {
+		self.saved.lock().unwrap().insert(video_id);
+	}
+}
+
+/// Stores checkpoints in a JSON Lines file next to the output file
+pub struct FileCheckpoints(SidecarFile<Checkpoint>);
+
+impl FileCheckpoints {
+	/// Opens the checkpoint file at `path`
+	#[allow(clippy::missing_errors_doc)]
+	pub fn open<P: Into<PathBuf>>(path: P, resume: bool) -> io::Result<Self> {
+		SidecarFile::open(path.into(), resume).map(Self)
+	}
+
+	/// The path of the checkpoint file for an output file
+	#[must_use]
+	pub fn sidecar(output: &Path) -> PathBuf {
+		sidecar_path(output, "state")
+	}
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpoints {
+	async fn load(&self, video_id: i64) -> Result<Option<Checkpoint>, StoreError> {
+		Ok(self.0.get(video_id))
+	}
+
+	async fn save(&self, checkpoint: &Checkpoint) -> Result<(), StoreError> {
+		Ok(self.0.save(checkpoint).await?)
+	}
+}
+
+/// Stores checkpoints in the `Checkpoint` table
+#[cfg(feature = "postgres")]
+pub struct PrismaCheckpoints<'a> {
+	client: &'a PrismaClient,
+	filter: ResumeFilter,
+}
+
+#[cfg(feature = "postgres")]
+impl PrismaCheckpoints<'_> {
+	/// Stores checkpoints with `client`
+	#[must_use]
+	pub fn new(client: &PrismaClient, resume: bool) -> PrismaCheckpoints<'_> {
+		PrismaCheckpoints {
+			client,
+			filter: ResumeFilter::new(resume),
+		}
+	}
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl CheckpointStore for PrismaCheckpoints<'_> {
+	async fn load(&self, video_id: i64) -> Result<Option<Checkpoint>, StoreError> {
+		if !self.filter.allows(video_id) {
+			return Ok(None);
+		}
+
+		let checkpoint = self
+			.client
+			.checkpoint()
+			.find_unique(prisma::checkpoint::UniqueWhereParam::VideoIdEquals(
+				video_id,
+			))
+			.exec()
+			.await?;
+
+		Ok(checkpoint.map(|c| Checkpoint {
+			video_id: c.video_id,
+			cursor: c.cursor,
+			offset: u32::try_from(c.offset).unwrap_or(0),
+			completed: c.completed,
+		}))
+	}
+
+	async fn save(&self, checkpoint: &Checkpoint) -> Result<(), StoreError> {
+		let offset = i32::try_from(checkpoint.offset).unwrap_or(i32::MAX);
+
+		self.client
+			.checkpoint()
+			.upsert(
+				prisma::checkpoint::UniqueWhereParam::VideoIdEquals(checkpoint.video_id),
+				prisma::checkpoint::create(
+					checkpoint.video_id,
+					offset,
+					vec![
+						prisma::checkpoint::cursor::set(checkpoint.cursor.clone()),
+						prisma::checkpoint::completed::set(checkpoint.completed),
+					],
+				),
+				vec![
+					prisma::checkpoint::cursor::set(checkpoint.cursor.clone()),
+					prisma::checkpoint::offset::set(offset),
+					prisma::checkpoint::completed::set(checkpoint.completed),
+				],
+			)
+			.exec()
+			.await?;
+		self.filter.saved(checkpoint.video_id);
+
+		Ok(())
+	}
+}
+
+#[cfg(feature = "sqlite")]
+/// Stores checkpoints in the `Checkpoint` table of a [`SqliteStore`]
+pub struct SqliteCheckpoints<'a> {
+	store: &'a SqliteStore,
+	filter: ResumeFilter,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteCheckpoints<'_> {
+	/// Stores checkpoints in `store`
+	#[must_use]
+	pub fn new(store: &SqliteStore, resume: bool) -> SqliteCheckpoints<'_> {
+		SqliteCheckpoints {
+			store,
+			filter: ResumeFilter::new(resume),
+		}
+	}
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl CheckpointStore for SqliteCheckpoints<'_> {
+	async fn load(&self, video_id: i64) -> Result<Option<Checkpoint>, StoreError> {
+		if !self.filter.allows(video_id) {
+			return Ok(None);
+		}
+
+		Ok(self.store.load_checkpoint(video_id).await?)
+	}
+
+	async fn save(&self, checkpoint: &Checkpoint) -> Result<(), StoreError> {
+		self.store.save_checkpoint(checkpoint).await?;
+		self.filter.saved(checkpoint.video_id);
+
+		Ok(())
+	}
+}
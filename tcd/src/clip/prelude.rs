@@ -0,0 +1,137 @@
+use std::ops::RangeInclusive;
+
+use chrono::{DateTime, FixedOffset};
+use futures::StreamExt;
+
+use crate::{
+	gql::{
+		client::GqlClient,
+		prelude::{ChunkError, PaginateFilter},
+		request::{GqlClipVariables, Operation},
+		retry::RetryPolicy,
+		structs::{GqlClip, GqlClipResponse, GqlEdge, GqlResponse},
+	},
+	sink::{save_page, Sink},
+	video::Video,
+};
+
+/// A clip cut from a video on Twitch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Clip {
+	pub id: i64,
+	pub slug: String,
+	pub title: String,
+	/// The channel the clip was cut from, not the user who cut it
+	pub broadcaster: String,
+	pub broadcaster_id: i64,
+	pub created_at: DateTime<FixedOffset>,
+	/// The length of the clip in seconds
+	pub duration: u32,
+	/// The video the clip was cut from, if it is still available
+	pub video_id: Option<i64>,
+	/// The number of seconds into the video the clip starts at
+	pub offset: Option<u32>,
+}
+
+impl Clip {
+	/// Gets a clip and the video it was cut from by its slug,
+	/// the last part of its URL
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn from_slug(
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		slug: &str,
+	) -> Result<Option<Self>, ChunkError> {
+		let body: GqlResponse<GqlClipResponse> = retry
+			.run(|| async {
+				gql.request(Operation::ShareClipRenderStatus, GqlClipVariables { slug })
+					.await
+					.map_err(|e| {
+						ChunkError::from_response(
+							e,
+							Operation::ShareClipRenderStatus,
+							slug.to_string(),
+						)
+					})
+			})
+			.await?;
+
+		Ok(body.data.clip.map(Self::from))
+	}
+
+	/// The offsets into the video that the chat of the clip was sent at
+	#[must_use]
+	pub fn range(&self) -> Option<RangeInclusive<u32>> {
+		let start = self.offset?;
+
+		Some(start..=start.saturating_add(self.duration))
+	}
+
+	/// Saves the clip and the comments sent during it to a sink,
+	/// under the video the clip was cut from
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn write_to_sink<S: Sink + ?Sized>(
+		&self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		verbose: bool,
+	) -> Result<(), S::Error> {
+		let (Some(video_id), Some(range)) = (self.video_id, self.range()) else {
+			return Err(ChunkError::DataMissing {
+				operation: Operation::ShareClipRenderStatus.name(),
+				id: self.id,
+			}
+			.into());
+		};
+
+		let video = Video::paginate_filter(gql, retry, &[video_id])
+			.next()
+			.await
+			.transpose()?
+			.map(Video::from)
+			.ok_or(ChunkError::DataMissing {
+				operation: Operation::VideoMetadata.name(),
+				id: video_id,
+			})?;
+
+		video.save_to(sink).await?;
+		sink.save_clip(self).await?;
+
+		let mut pages = video.paginate_range(gql, retry, range);
+
+		while let Some(page) = pages.next().await {
+			let page = page?;
+
+			if !page.edges.is_empty() {
+				save_page(sink, &video, &page.edges, verbose).await?;
+			}
+		}
+
+		sink.flush().await
+	}
+}
+
+impl From<GqlClip> for Clip {
+	/// Converts a GraphQL clip to a clip
+	fn from(clip: GqlClip) -> Self {
+		Self {
+			id: clip.id,
+			slug: clip.slug,
+			title: clip.title,
+			broadcaster: clip.broadcaster.username,
+			broadcaster_id: clip.broadcaster.id,
+			created_at: clip.created_at,
+			duration: clip.duration,
+			video_id: clip.video.map(|video| video.id),
+			offset: clip.offset,
+		}
+	}
+}
+
+impl From<GqlEdge<GqlClip>> for Clip {
+	/// Converts a GraphQL clip edge to a clip
+	fn from(clip: GqlEdge<GqlClip>) -> Self {
+		Self::from(clip.node)
+	}
+}
@@ -0,0 +1,200 @@
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{self, BufReader},
+	path::Path,
+	sync::Arc,
+};
+
+use reqwest::{
+	header::{HeaderMap, HeaderName, HeaderValue},
+	StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+	limiter::RateLimiter,
+	prelude::{read_response, ResponseError},
+	request::{GqlRequest, GqlRequestExtensions, GqlRequestPersistedQuery, Operation},
+};
+
+/// The Twitch GraphQL endpoint
+pub const ENDPOINT: &str = "https://gql.twitch.tv/gql";
+
+/// The client ID used by the Twitch website
+pub const CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+
+/// Overrides for the values built into [`GqlClient`], so that rotated
+/// persisted query hashes can be fixed without a new release
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GqlConfig {
+	pub endpoint: Option<String>,
+	pub client_id: Option<String>,
+	/// The sha256 hash for each operation, keyed by operation name
+	pub operations: HashMap<Operation, String>,
+}
+
+impl GqlConfig {
+	/// Reads a config from a JSON file
+	#[allow(clippy::missing_errors_doc)]
+	pub fn from_file(path: &Path) -> io::Result<Self> {
+		Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+	}
+}
+
+/// The client used for every request to the Twitch GraphQL API
+#[derive(Clone, Debug)]
+pub struct GqlClient {
+	http: reqwest::Client,
+	endpoint: String,
+	client_id: String,
+	headers: HeaderMap,
+	hashes: HashMap<Operation, String>,
+	limiter: Option<Arc<RateLimiter>>,
+}
+
+impl GqlClient {
+	/// Creates a client for the default endpoint and client ID, without a rate limit
+	#[must_use]
+	pub fn new(http: reqwest::Client) -> Self {
+		Self {
+			http,
+			endpoint: ENDPOINT.to_string(),
+			client_id: CLIENT_ID.to_string(),
+			headers: HeaderMap::new(),
+			hashes: HashMap::new(),
+			limiter: None,
+		}
+	}
+
+	/// Sends requests to a different GraphQL endpoint
+	#[must_use]
+	pub fn with_endpoint<S: Into<String>>(self, endpoint: S) -> Self {
+		Self {
+			endpoint: endpoint.into(),
+			..self
+		}
+	}
+
+	/// Sends a different `Client-ID` header
+	#[must_use]
+	pub fn with_client_id<S: Into<String>>(self, client_id: S) -> Self {
+		Self {
+			client_id: client_id.into(),
+			..self
+		}
+	}
+
+	/// Sends an additional header with every request
+	#[must_use]
+	pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+		self.headers.insert(name, value);
+		self
+	}
+
+	/// Uses a different persisted query hash for an operation
+	#[must_use]
+	pub fn with_hash<S: Into<String>>(mut self, operation: Operation, hash: S) -> Self {
+		self.hashes.insert(operation, hash.into());
+		self
+	}
+
+	/// Applies every value set in the config
+	#[must_use]
+	pub fn with_config(mut self, config: GqlConfig) -> Self {
+		if let Some(endpoint) = config.endpoint {
+			self.endpoint = endpoint;
+		}
+
+		if let Some(client_id) = config.client_id {
+			self.client_id = client_id;
+		}
+
+		self.hashes.extend(config.operations);
+		self
+	}
+
+	/// Limits the requests made by this client and all of its clones
+	#[must_use]
+	pub fn with_rate_limit(self, limiter: RateLimiter) -> Self {
+		Self {
+			limiter: Some(Arc::new(limiter)),
+			..self
+		}
+	}
+
+	/// The underlying HTTP client, for requests that are not made to the GraphQL API
+	#[must_use]
+	pub fn http(&self) -> &reqwest::Client {
+		&self.http
+	}
+
+	/// The GraphQL endpoint requests are sent to
+	#[must_use]
+	pub fn endpoint(&self) -> &str {
+		&self.endpoint
+	}
+
+	/// The persisted query hash sent for an operation
+	#[must_use]
+	pub fn hash(&self, operation: Operation) -> &str {
+		self.hashes
+			.get(&operation)
+			.map_or(operation.default_hash(), String::as_str)
+	}
+
+	/// The rate limiter shared by this client, if any
+	#[must_use]
+	pub fn limiter(&self) -> Option<&RateLimiter> {
+		self.limiter.as_deref()
+	}
+
+	/// Sends a persisted query, waiting for the rate limiter first
+	async fn send<V: Serialize>(
+		&self,
+		operation: Operation,
+		variables: V,
+	) -> Result<reqwest::Response, reqwest::Error> {
+		if let Some(limiter) = &self.limiter {
+			limiter.acquire().await;
+		}
+
+		let response = self
+			.http
+			.post(&self.endpoint)
+			.headers(self.headers.clone())
+			.header("Client-ID", &self.client_id)
+			.json(&GqlRequest {
+				operation_name: operation.name(),
+				variables,
+				extensions: GqlRequestExtensions {
+					persisted_query: GqlRequestPersistedQuery {
+						version: 1,
+						sha256_hash: self.hash(operation),
+					},
+				},
+			})
+			.send()
+			.await;
+
+		if let (Some(limiter), Ok(response)) = (&self.limiter, &response) {
+			if response.status() == StatusCode::TOO_MANY_REQUESTS {
+				limiter.on_rate_limited();
+			} else {
+				limiter.on_success();
+			}
+		}
+
+		response
+	}
+
+	/// Sends a persisted query and decodes the response
+	pub(crate) async fn request<V: Serialize, T: DeserializeOwned>(
+		&self,
+		operation: Operation,
+		variables: V,
+	) -> Result<T, ResponseError> {
+		read_response(self.send(operation, variables).await).await
+	}
+}
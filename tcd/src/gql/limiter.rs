@@ -0,0 +1,123 @@
+use std::{
+	sync::{Mutex, MutexGuard, PoisonError},
+	time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// The number of requests per second used when no rate is configured
+pub const DEFAULT_RATE: f64 = 10.;
+
+/// The number of requests that can be made at once used when no burst is configured
+pub const DEFAULT_BURST: u32 = 10;
+
+/// A token bucket that limits the number of requests per second,
+/// shared between every concurrent download
+#[derive(Debug)]
+pub struct RateLimiter {
+	/// The configured number of requests per second
+	rate: f64,
+	/// The maximum number of requests that can be made at once
+	burst: f64,
+	state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+	tokens: f64,
+	/// The current number of requests per second, lowered after a 429
+	rate: f64,
+	last_refill: Instant,
+	/// No requests are made before this instant after a 429
+	paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+	/// Creates a limiter that allows `rate` requests per second
+	/// on average, and up to `burst` requests at once
+	#[must_use]
+	pub fn new(rate: f64, burst: u32) -> Self {
+		let rate = rate.max(f64::EPSILON);
+		let burst = f64::from(burst.max(1));
+
+		Self {
+			rate,
+			burst,
+			state: Mutex::new(State {
+				tokens: burst,
+				rate,
+				last_refill: Instant::now(),
+				paused_until: None,
+			}),
+		}
+	}
+
+	fn state(&self) -> MutexGuard<'_, State> {
+		// The state is always left consistent, so a poisoned lock can be reused
+		self.state.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// The current number of requests per second, which is lower than the
+	/// configured rate while recovering from a 429
+	#[must_use]
+	pub fn current_rate(&self) -> f64 {
+		self.state().rate
+	}
+
+	/// Waits until a request can be made
+	pub async fn acquire(&self) {
+		loop {
+			let wait = {
+				let mut state = self.state();
+				let now = Instant::now();
+
+				match state.paused_until {
+					Some(until) if until > now => until - now,
+					_ => {
+						state.paused_until = None;
+						state.refill(now, self.burst);
+
+						if state.tokens >= 1. {
+							state.tokens -= 1.;
+
+							return;
+						}
+
+						Duration::from_secs_f64((1. - state.tokens) / state.rate)
+					}
+				}
+			};
+
+			tokio::time::sleep(wait).await;
+		}
+	}
+
+	/// Halves the rate and pauses all requests after the server responded with 429
+	pub fn on_rate_limited(&self) {
+		let mut state = self.state();
+		let now = Instant::now();
+
+		state.refill(now, self.burst);
+		state.rate = (state.rate / 2.).max(self.rate / 64.);
+		state.tokens = 0.;
+		state.paused_until = Some(now + Duration::from_secs_f64(1. / state.rate));
+	}
+
+	/// Slowly restores the rate after a successful request
+	pub fn on_success(&self) {
+		let mut state = self.state();
+
+		if state.rate < self.rate {
+			state.rate = (state.rate + self.rate / 32.).min(self.rate);
+		}
+	}
+}
+
+impl State {
+	fn refill(&mut self, now: Instant, burst: f64) {
+		let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+		self.tokens = (self.tokens + elapsed * self.rate).min(burst);
+		self.last_refill = now;
+	}
+}
@@ -1,3 +1,6 @@
+pub mod client;
+pub mod limiter;
 pub mod prelude;
 pub mod request;
+pub mod retry;
 pub mod structs;
@@ -1,15 +1,26 @@
 use std::{
+	fmt,
 	io::{BufWriter, Write},
-	sync::Mutex,
+	sync::{Arc, Mutex},
 };
 
 use async_trait::async_trait;
 use futures::stream::BoxStream;
+#[cfg(feature = "postgres")]
 use prisma_client_rust::QueryError;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 
-use crate::prisma::PrismaClient;
+use crate::{checkpoint::CheckpointStore, sink::StreamError};
+#[cfg(feature = "postgres")]
+use crate::{prisma::PrismaClient, sink::PrismaError};
 
-use super::structs::GqlEdgeContainer;
+use super::{
+	client::GqlClient,
+	request::Operation,
+	retry::{ErrorClass, RetryPolicy},
+	structs::GqlEdgeContainer,
+};
 
 // https://github.com/serde-rs/json/issues/329
 #[allow(clippy::missing_errors_doc)]
@@ -39,28 +50,52 @@ pub mod string {
 	}
 }
 
+#[cfg(feature = "postgres")]
 #[async_trait]
 pub trait Save {
 	async fn save(&self, client: &PrismaClient) -> Result<(), QueryError>;
 }
 
+/// Where to start paginating from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StartAt {
+	/// The first page
+	Beginning,
+	/// The page after the one that contains the edge with this cursor
+	Cursor(String),
+	/// The page that starts at this many seconds into the video
+	Offset(u32),
+}
+
 pub trait Paginate<T>: Chunk<GqlEdgeContainer<T>> {
-	fn paginate<'a>(
+	fn paginate_from<'a>(
 		&'a self,
-		http: &'a reqwest::Client,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+		start: StartAt,
 	) -> BoxStream<'a, Result<GqlEdgeContainer<T>, ChunkError>>;
+
+	fn paginate<'a>(
+		&'a self,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<T>, ChunkError>> {
+		self.paginate_from(gql, retry, StartAt::Beginning)
+	}
 }
 
 pub trait PaginateMut<T>: Chunk<GqlEdgeContainer<T>> {
 	fn paginate_mut<'a>(
 		&'a mut self,
-		http: &'a reqwest::Client,
-	) -> BoxStream<'a, GqlEdgeContainer<T>>;
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<T>, ChunkError>>;
 }
 
 pub trait PaginateFilter<T> {
 	fn paginate_filter<'a>(
-		http: &'a reqwest::Client,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
 		ids: &'a [i64],
 	) -> BoxStream<'a, Result<T, ChunkError>>;
 }
@@ -69,40 +104,282 @@ pub trait PaginateFilter<T> {
 pub enum Format {
 	JsonLines,
 	Csv,
+	/// Written with a [`crate::parquet::ParquetWriter`] rather than a stream
+	#[cfg(feature = "parquet")]
+	Parquet,
 }
 
 #[async_trait]
 pub trait WriteChunk<T>: Paginate<T> {
+	#[cfg(feature = "postgres")]
 	async fn write_to_pg(
 		self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
 		client: &PrismaClient,
+		checkpoints: Option<&dyn CheckpointStore>,
 		verbose: bool,
-	) -> Result<(), ChunkError>;
+	) -> Result<(), PrismaError>;
 	async fn write_to_stream(
 		self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
 		stream: &Mutex<BufWriter<impl Write + Send>>,
 		format: &Format,
-	) -> Result<(), ChunkError>;
+		checkpoints: Option<&dyn CheckpointStore>,
+	) -> Result<(), StreamError>;
 }
 
 #[async_trait]
 pub trait Chunk<T> {
 	async fn chunk_by_cursor<'a, S: Into<&'a str> + Send>(
 		&self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
 		cursor: S,
 	) -> Result<T, ChunkError>;
-	async fn first_chunk(&self, http: &reqwest::Client) -> Result<T, ChunkError>;
+	async fn first_chunk(&self, gql: &GqlClient) -> Result<T, ChunkError>;
+}
+
+/// A GraphQL request that failed, with the operation it was for and the video,
+/// channel or clip it was about
+#[derive(Clone, Debug)]
+pub enum RequestError {
+	/// The request could not be sent, or its body could not be read
+	Reqwest {
+		operation: &'static str,
+		target: String,
+		source: Arc<reqwest::Error>,
+	},
+	/// The server responded with a non-success status code
+	Status {
+		operation: &'static str,
+		target: String,
+		status: StatusCode,
+	},
+	/// The response body could not be deserialized
+	Serde {
+		operation: &'static str,
+		target: String,
+		source: Arc<serde_json::Error>,
+	},
+}
+
+impl RequestError {
+	pub(crate) fn from_response(
+		error: ResponseError,
+		operation: Operation,
+		target: String,
+	) -> Self {
+		let operation = operation.name();
+
+		match error {
+			ResponseError::Reqwest(source) => Self::Reqwest {
+				operation,
+				target,
+				source: Arc::new(source),
+			},
+			ResponseError::Status(status) => Self::Status {
+				operation,
+				target,
+				status,
+			},
+			ResponseError::Serde(source) => Self::Serde {
+				operation,
+				target,
+				source: Arc::new(source),
+			},
+		}
+	}
+
+	/// The name of the GraphQL operation that failed
+	#[must_use]
+	pub fn operation(&self) -> &'static str {
+		match self {
+			Self::Reqwest { operation, .. }
+			| Self::Status { operation, .. }
+			| Self::Serde { operation, .. } => operation,
+		}
+	}
+
+	/// The id of the video or channel, or the username or slug, that the request was for
+	#[must_use]
+	pub fn target(&self) -> &str {
+		match self {
+			Self::Reqwest { target, .. }
+			| Self::Status { target, .. }
+			| Self::Serde { target, .. } => target,
+		}
+	}
+
+	/// The HTTP status code of the response, if one was received
+	#[must_use]
+	pub fn status(&self) -> Option<StatusCode> {
+		match self {
+			Self::Status { status, .. } => Some(*status),
+			Self::Reqwest { source, .. } => source.status(),
+			Self::Serde { .. } => None,
+		}
+	}
+
+	/// The class of the error, used to decide whether to retry the request
+	#[must_use]
+	pub fn class(&self) -> ErrorClass {
+		match self {
+			Self::Reqwest { source, .. } => {
+				if source.is_timeout() {
+					ErrorClass::Timeout
+				} else if source.is_decode() {
+					ErrorClass::Decode
+				} else if source.status().is_some() {
+					ErrorClass::Other
+				} else {
+					ErrorClass::Network
+				}
+			}
+			Self::Status { status, .. } => {
+				if *status == StatusCode::TOO_MANY_REQUESTS {
+					ErrorClass::RateLimited
+				} else if status.is_server_error() {
+					ErrorClass::Server
+				} else {
+					ErrorClass::Other
+				}
+			}
+			Self::Serde { .. } => ErrorClass::Decode,
+		}
+	}
+}
+
+impl fmt::Display for RequestError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Reqwest {
+				operation, target, ..
+			} => write!(f, "failed to send {operation} request for {target}"),
+			Self::Status {
+				operation,
+				target,
+				status,
+			} => write!(f, "{operation} request for {target} returned {status}"),
+			Self::Serde {
+				operation, target, ..
+			} => write!(f, "failed to decode {operation} response for {target}"),
+		}
+	}
+}
+
+impl std::error::Error for RequestError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Reqwest { source, .. } => Some(source.as_ref()),
+			Self::Serde { source, .. } => Some(source.as_ref()),
+			Self::Status { .. } => None,
+		}
+	}
 }
 
+/// An error that occurred while downloading a chunk of data
 #[derive(Clone, Debug)]
 pub enum ChunkError {
-	Reqwest,
-	Serde,
-	Prisma,
-	Io,
-	Csv,
-	DataMissing,
+	/// A GraphQL request failed
+	Request(RequestError),
+	/// The response did not contain the requested data
+	DataMissing { operation: &'static str, id: i64 },
+}
+
+impl ChunkError {
+	/// The name of the GraphQL operation that failed, if any
+	#[must_use]
+	pub fn operation(&self) -> Option<&'static str> {
+		match self {
+			Self::Request(error) => Some(error.operation()),
+			Self::DataMissing { operation, .. } => Some(*operation),
+		}
+	}
+
+	/// The video, channel or clip that the request was for, if any
+	#[must_use]
+	pub fn target(&self) -> Option<String> {
+		match self {
+			Self::Request(error) => Some(error.target().to_string()),
+			Self::DataMissing { id, .. } => Some(id.to_string()),
+		}
+	}
+
+	/// The HTTP status code of the response, if one was received
+	#[must_use]
+	pub fn status(&self) -> Option<StatusCode> {
+		match self {
+			Self::Request(error) => error.status(),
+			Self::DataMissing { .. } => None,
+		}
+	}
+
+	/// The class of the error, used to decide whether to retry the request
+	#[must_use]
+	pub fn class(&self) -> ErrorClass {
+		match self {
+			Self::Request(error) => error.class(),
+			Self::DataMissing { .. } => ErrorClass::DataMissing,
+		}
+	}
+
+	pub(crate) fn from_response(
+		error: ResponseError,
+		operation: Operation,
+		target: String,
+	) -> Self {
+		Self::Request(RequestError::from_response(error, operation, target))
+	}
+}
+
+impl fmt::Display for ChunkError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Request(error) => error.fmt(f),
+			Self::DataMissing { operation, id } => {
+				write!(f, "{operation} response for {id} is missing data")
+			}
+		}
+	}
+}
+
+impl std::error::Error for ChunkError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Request(error) => error.source(),
+			Self::DataMissing { .. } => None,
+		}
+	}
+}
+
+impl From<RequestError> for ChunkError {
+	fn from(error: RequestError) -> Self {
+		Self::Request(error)
+	}
+}
+
+/// The ways in which reading a GraphQL response can fail, before
+/// the context of the request is attached
+#[derive(Debug)]
+pub(crate) enum ResponseError {
+	Reqwest(reqwest::Error),
+	Status(StatusCode),
+	Serde(serde_json::Error),
+}
+
+/// Checks the status of a response and deserializes its body
+pub(crate) async fn read_response<T: DeserializeOwned>(
+	response: Result<reqwest::Response, reqwest::Error>,
+) -> Result<T, ResponseError> {
+	let response = response.map_err(ResponseError::Reqwest)?;
+	let status = response.status();
+
+	if !status.is_success() {
+		return Err(ResponseError::Status(status));
+	}
+
+	let body = response.bytes().await.map_err(ResponseError::Reqwest)?;
+
+	serde_json::from_slice(&body).map_err(ResponseError::Serde)
 }
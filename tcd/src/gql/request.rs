@@ -1,5 +1,70 @@
 use crate::gql::prelude::string;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+/// A persisted GraphQL query used by the library
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum Operation {
+	#[serde(rename = "FilterableVideoTower_Videos")]
+	FilterableVideoTowerVideos,
+	VideoCommentsByOffsetOrCursor,
+	VideoMetadata,
+	PlayerTrackingContextQuery,
+	ViewerCard,
+	#[serde(rename = "ClipsCards__User")]
+	ClipsCardsUser,
+	ShareClipRenderStatus,
+	UseLive,
+}
+
+impl Operation {
+	/// The name of the operation as sent to the API
+	#[must_use]
+	pub const fn name(self) -> &'static str {
+		match self {
+			Self::FilterableVideoTowerVideos => "FilterableVideoTower_Videos",
+			Self::VideoCommentsByOffsetOrCursor => "VideoCommentsByOffsetOrCursor",
+			Self::VideoMetadata => "VideoMetadata",
+			Self::PlayerTrackingContextQuery => "PlayerTrackingContextQuery",
+			Self::ViewerCard => "ViewerCard",
+			Self::ClipsCardsUser => "ClipsCards__User",
+			Self::ShareClipRenderStatus => "ShareClipRenderStatus",
+			Self::UseLive => "UseLive",
+		}
+	}
+
+	/// The sha256 hash of the persisted query known to work at the time of release
+	#[must_use]
+	pub const fn default_hash(self) -> &'static str {
+		match self {
+			Self::FilterableVideoTowerVideos => {
+				"a937f1d22e269e39a03b509f65a7490f9fc247d7f83d6ac1421523e3b68042cb"
+			}
+			Self::VideoCommentsByOffsetOrCursor => {
+				"b70a3591ff0f4e0313d126c6a1502d79a1c02baebb288227c582044aa76adf6a"
+			}
+			Self::VideoMetadata => {
+				"49b5b8f268cdeb259d75b58dcb0c1a748e3b575003448a2333dc5cdafd49adad"
+			}
+			Self::PlayerTrackingContextQuery => {
+				"3fbf508886ff5e008cb94047acc752aad7428c07b6055995604de16c4b01160a"
+			}
+			Self::ViewerCard => "20e51233313878f971daa32dfc039b2e2183822e62c13f47c48448d5d5e4f5e9",
+			Self::ClipsCardsUser => {
+				"b73ad2bfaecfd30a9e6c28fada15bd97032c83ec77a0440766a56fe0bd632777"
+			}
+			Self::ShareClipRenderStatus => {
+				"f130048a462a0ac86bb54d653c968c514e9ab9ca94db52368c1179e97b0f16eb"
+			}
+			Self::UseLive => "639d5f11bfb8bf3053b424d9ef650d04c4ebb7d94711d644afb08fe9a0fad5d9",
+		}
+	}
+}
+
+impl std::fmt::Display for Operation {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(self.name())
+	}
+}
 
 #[derive(Serialize)]
 pub struct GqlVideoMetadataVariables<'a> {
@@ -49,13 +114,40 @@ pub struct GqlVideoFilterVariables<'a> {
 	pub limit: usize,
 	#[serde(rename(serialize = "channelOwnerLogin"))]
 	pub username: &'a str,
+	/// The kind of videos to list, or `None` for every kind
 	#[serde(rename(serialize = "broadcastType"))]
-	pub r#type: &'static str,
+	pub r#type: Option<&'static str>,
 	#[serde(rename(serialize = "videoSort"))]
 	pub sort: &'static str,
 	pub cursor: Option<&'a str>,
 }
 
+#[derive(Serialize)]
+pub struct GqlClipsFilterVariables<'a> {
+	#[serde(rename(serialize = "login"))]
+	pub username: &'a str,
+	pub limit: usize,
+	pub criteria: GqlClipsCriteria,
+	pub cursor: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct GqlClipsCriteria {
+	/// The period to list clips from, such as `ALL_TIME` or `LAST_WEEK`
+	pub filter: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct GqlClipVariables<'a> {
+	pub slug: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct GqlUseLiveVariables<'a> {
+	#[serde(rename(serialize = "channelLogin"))]
+	pub username: &'a str,
+}
+
 #[derive(Serialize)]
 pub struct GqlVideoCommentsByOffsetVariables {
 	#[serde(with = "string", rename(serialize = "videoID"))]
@@ -73,23 +165,23 @@ pub struct GqlVideoCommentsByCursorVariables<'a> {
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Serialize)]
-pub struct GqlRequest<V> {
+pub struct GqlRequest<'a, V> {
 	#[serde(rename(serialize = "operationName"))]
 	pub operation_name: &'static str,
 	pub variables: V,
-	pub extensions: GqlRequestExtensions,
+	pub extensions: GqlRequestExtensions<'a>,
 }
 
 #[derive(Serialize)]
-pub struct GqlRequestExtensions {
+pub struct GqlRequestExtensions<'a> {
 	#[serde(rename(serialize = "persistedQuery"))]
-	pub persisted_query: GqlRequestPersistedQuery,
+	pub persisted_query: GqlRequestPersistedQuery<'a>,
 }
 
 #[derive(Serialize)]
-pub struct GqlRequestPersistedQuery {
+pub struct GqlRequestPersistedQuery<'a> {
 	#[serde(rename(serialize = "version"))]
 	pub version: u8,
 	#[serde(rename(serialize = "sha256Hash"))]
-	pub sha256_hash: &'static str,
+	pub sha256_hash: &'a str,
 }
@@ -0,0 +1,111 @@
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+
+use super::prelude::ChunkError;
+
+/// A broad category of [`ChunkError`], used to decide whether
+/// a failed request should be retried
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+	/// The request timed out
+	Timeout,
+	/// The connection failed or was interrupted
+	Network,
+	/// The server responded with a 5xx status code
+	Server,
+	/// The server responded with 429 Too Many Requests
+	RateLimited,
+	/// The response body could not be decoded
+	Decode,
+	/// The response did not contain the requested data
+	DataMissing,
+	/// Any other error, such as a 4xx status code or a database error
+	Other,
+}
+
+/// Controls how failed GraphQL requests are retried
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+	/// The maximum number of attempts per request, including the first
+	pub max_attempts: u32,
+	/// The delay before the first retry, doubled for every subsequent retry
+	pub base_delay: Duration,
+	/// The upper bound for the delay between two attempts
+	pub max_delay: Duration,
+	/// The classes of errors that should be retried
+	pub retry_on: Vec<ErrorClass>,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: 5,
+			base_delay: Duration::from_millis(500),
+			max_delay: Duration::from_secs(30),
+			retry_on: vec![
+				ErrorClass::Timeout,
+				ErrorClass::Network,
+				ErrorClass::Server,
+				ErrorClass::RateLimited,
+			],
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// A policy that never retries
+	#[must_use]
+	pub fn none() -> Self {
+		Self {
+			max_attempts: 1,
+			..Self::default()
+		}
+	}
+
+	/// Whether the request should be attempted again after
+	/// failing with `error` on attempt number `attempt` (starting at 1)
+	#[must_use]
+	pub fn should_retry(&self, error: &ChunkError, attempt: u32) -> bool {
+		self.retries(error.class(), attempt)
+	}
+
+	/// Whether an error of class `class` is attempted again after attempt number `attempt`
+	pub(crate) fn retries(&self, class: ErrorClass, attempt: u32) -> bool {
+		attempt < self.max_attempts && self.retry_on.contains(&class)
+	}
+
+	/// The delay before the next attempt after attempt number `attempt` failed,
+	/// chosen uniformly between zero and the exponential backoff ("full jitter")
+	#[must_use]
+	pub fn delay(&self, attempt: u32) -> Duration {
+		let backoff = self
+			.base_delay
+			.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+			.min(self.max_delay);
+
+		backoff.mul_f64(rand::thread_rng().gen::<f64>())
+	}
+
+	/// Runs `request` until it succeeds, fails with an error that should
+	/// not be retried, or runs out of attempts
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn run<T, F, Fut>(&self, mut request: F) -> Result<T, ChunkError>
+	where
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T, ChunkError>>,
+	{
+		let mut attempt = 1;
+
+		loop {
+			match request().await {
+				Ok(data) => return Ok(data),
+				Err(e) if self.should_retry(&e, attempt) => {
+					tokio::time::sleep(self.delay(attempt)).await;
+					attempt += 1;
+				}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
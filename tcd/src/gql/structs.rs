@@ -1,6 +1,6 @@
 use super::prelude::string;
 use chrono::{DateTime, FixedOffset, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug)]
 pub struct GqlResponse<T> {
@@ -14,7 +14,7 @@ pub struct GqlPageInfo {
 	pub has_next_page: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct GqlEdge<T> {
 	pub cursor: Option<String>,
 	pub node: T,
@@ -45,12 +45,14 @@ pub struct GqlChannel {
 	pub username: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct GqlCommenter {
 	#[serde(with = "string")]
 	pub id: i64,
 	#[serde(rename(deserialize = "login"))]
 	pub username: String,
+	#[serde(rename(deserialize = "displayName"))]
+	pub display_name: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -63,6 +65,9 @@ pub struct GqlUser {
 	pub display_name: String,
 	#[serde(rename(deserialize = "createdAt"))]
 	pub created_at: DateTime<Utc>,
+	#[serde(rename(deserialize = "profileImageURL"))]
+	pub profile_image_url: Option<String>,
+	pub description: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -78,26 +83,84 @@ pub struct GqlVideo {
 	pub title: String,
 	#[serde(rename(deserialize = "previewThumbnailURL"))]
 	pub thumbnail_url: String,
+	pub game: Option<GqlGame>,
+	#[serde(rename(deserialize = "viewCount"))]
+	pub view_count: Option<i64>,
+	pub language: Option<String>,
+}
+
+/// The game or category a video was streamed under
+#[derive(Deserialize, Debug)]
+pub struct GqlGame {
+	pub id: String,
+	pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GqlClipVideo {
+	#[serde(with = "string")]
+	pub id: i64,
 }
 
 #[derive(Deserialize, Debug)]
+pub struct GqlClip {
+	#[serde(with = "string")]
+	pub id: i64,
+	pub slug: String,
+	pub title: String,
+	#[serde(rename(deserialize = "createdAt"))]
+	pub created_at: DateTime<FixedOffset>,
+	#[serde(rename(deserialize = "durationSeconds"))]
+	pub duration: u32,
+	pub broadcaster: GqlChannel,
+	/// The video the clip was cut from, unless it was deleted or never published.
+	/// Clips listed for a channel do not include it.
+	#[serde(default)]
+	pub video: Option<GqlClipVideo>,
+	/// The number of seconds into the video the clip starts at
+	#[serde(default, rename(deserialize = "videoOffsetSeconds"))]
+	pub offset: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct GqlEmote {
 	#[serde(rename(deserialize = "emoteID"))]
 	pub emote_id: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct GqlCommentMessageFragment {
 	pub emote: Option<GqlEmote>,
 	pub text: String,
 }
 
-#[derive(Deserialize, Debug)]
+/// A chat badge, such as `subscriber` with the tenure in months as its version
+#[derive(Deserialize, Clone, Debug)]
+pub struct GqlBadge {
+	#[serde(rename(deserialize = "setID"))]
+	pub set_id: String,
+	pub version: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct GqlCommentMessage {
 	pub fragments: Vec<GqlCommentMessageFragment>,
+	#[serde(default, rename(deserialize = "userBadges"))]
+	pub badges: Vec<GqlBadge>,
+	/// The chat colour of the commenter as a hex code, if they picked one
+	#[serde(rename(deserialize = "userColor"))]
+	pub color: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+impl GqlCommentMessage {
+	/// The full text of the message
+	#[must_use]
+	pub fn text(&self) -> String {
+		self.fragments.iter().map(|f| f.text.as_str()).collect()
+	}
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct GqlComment {
 	pub id: String,
 	pub commenter: Option<GqlCommenter>,
@@ -106,6 +169,246 @@ pub struct GqlComment {
 	#[serde(rename(deserialize = "createdAt"))]
 	pub created_at: DateTime<FixedOffset>,
 	pub message: GqlCommentMessage,
+	/// What the message is, as told by the tags of a live chat message.
+	/// Replayed chat does not say, so [`GqlComment::details`] reads it from the text.
+	#[serde(skip)]
+	pub tags: Option<MessageDetails>,
+	/// Whether a moderator deleted the comment, which only live chat tells
+	#[serde(skip)]
+	pub deleted: bool,
+}
+
+impl GqlComment {
+	/// What the message is, such as a cheer or a subscription, and its bits and sub metadata.
+	/// Without tags they are guessed from the text, and marked as [`MessageDetails::inferred`].
+	#[must_use]
+	pub fn details(&self) -> MessageDetails {
+		if let Some(tags) = &self.tags {
+			return tags.clone();
+		}
+
+		let text = self.message.text();
+		let details = self
+			.commenter
+			.as_ref()
+			.and_then(|commenter| {
+				[&commenter.display_name, &commenter.username]
+					.into_iter()
+					.find_map(|name| strip_prefix_ignore_case(&text, name))
+			})
+			.and_then(subscription_notice);
+
+		let details = details.unwrap_or_else(|| {
+			let bits = cheer_bits(&text);
+
+			MessageDetails {
+				kind: if bits > 0 {
+					MessageKind::Cheer
+				} else {
+					MessageKind::Chat
+				},
+				bits,
+				..MessageDetails::default()
+			}
+		});
+
+		MessageDetails {
+			inferred: true,
+			..details
+		}
+	}
+}
+
+/// The kind of a chat message, named like the `msg-id` tag of Twitch chat
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+	/// A plain chat message
+	#[default]
+	Chat,
+	/// A message with bits attached
+	Cheer,
+	/// A first subscription
+	Sub,
+	/// A subscription shared after the first month
+	Resub,
+	/// A subscription gifted to one user
+	SubGift,
+	/// Several subscriptions gifted to random users of the channel
+	SubMysteryGift,
+	/// Another channel raiding with its viewers
+	Raid,
+	/// Any other notice, such as an announcement
+	Notice,
+}
+
+impl MessageKind {
+	/// The name of the kind, such as `resub`
+	#[must_use]
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Chat => "chat",
+			Self::Cheer => "cheer",
+			Self::Sub => "sub",
+			Self::Resub => "resub",
+			Self::SubGift => "subgift",
+			Self::SubMysteryGift => "submysterygift",
+			Self::Raid => "raid",
+			Self::Notice => "notice",
+		}
+	}
+
+	/// Gets the kind for the `msg-id` tag of a `USERNOTICE`
+	#[must_use]
+	pub fn from_notice(msg_id: &str) -> Self {
+		match msg_id {
+			"sub" => Self::Sub,
+			"resub" => Self::Resub,
+			"subgift" | "anonsubgift" => Self::SubGift,
+			"submysterygift" | "anonsubmysterygift" => Self::SubMysteryGift,
+			"raid" => Self::Raid,
+			_ => Self::Notice,
+		}
+	}
+}
+
+/// The kind of a chat message and what was cheered, subscribed or gifted with it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MessageDetails {
+	pub kind: MessageKind,
+	/// The number of bits cheered
+	pub bits: u32,
+	/// The plan of a subscription: `1000`, `2000`, `3000` or `Prime`
+	pub sub_tier: Option<String>,
+	/// The number of months subscribed in total
+	pub sub_months: Option<u32>,
+	/// The number of subscriptions gifted
+	pub gift_count: Option<u32>,
+	/// The user a subscription was gifted to
+	pub gift_recipient: Option<String>,
+	/// The number of viewers a raid brought
+	pub raid_viewers: Option<u32>,
+	/// Whether the rest was read from the text of a replayed message instead of from chat tags.
+	/// Text can look like a cheer without being one, such as `Kappa123`, so these are guesses.
+	pub inferred: bool,
+}
+
+/// The prefixes of the cheermotes, which cheer bits when followed by an amount like `Cheer100`
+const CHEERMOTES: &[&str] = &[
+	"cheer",
+	"doodlecheer",
+	"biblethump",
+	"cheerwhal",
+	"corgo",
+	"uni",
+	"showlove",
+	"party",
+	"seemsgood",
+	"pride",
+	"kappa",
+	"frankerz",
+	"heyguys",
+	"dansgame",
+	"elegiggle",
+	"trihard",
+	"kreygasm",
+	"4head",
+	"swiftrage",
+	"notlikethis",
+	"failfish",
+	"vohiyo",
+	"pjsalt",
+	"mrdestructoid",
+	"bday",
+	"ripcheer",
+	"shamrock",
+	"streamlands",
+];
+
+/// Sums the bits of the cheermotes in a message
+fn cheer_bits(text: &str) -> u32 {
+	text.split_whitespace()
+		.filter_map(|word| {
+			let word = word.to_lowercase();
+			let prefix = word.trim_end_matches(|c: char| c.is_ascii_digit());
+
+			if prefix.len() == word.len() || !CHEERMOTES.contains(&prefix) {
+				return None;
+			}
+
+			word[prefix.len()..].parse::<u32>().ok()
+		})
+		.fold(0, u32::saturating_add)
+}
+
+/// Reads the details of the notice Twitch shows in replayed chat for a subscription or gift,
+/// given the text after the name of the user
+fn subscription_notice(text: &str) -> Option<MessageDetails> {
+	if let Some(rest) = text.strip_prefix(" subscribed ") {
+		let (sub_tier, rest) = if let Some(rest) = rest.strip_prefix("with Prime.") {
+			("Prime".to_string(), rest)
+		} else {
+			let rest = rest.strip_prefix("at Tier ")?;
+			let (tier, rest) = rest.split_once('.')?;
+
+			(plan(tier)?, rest)
+		};
+		let sub_months = rest
+			.trim_start()
+			.strip_prefix("They've subscribed for ")
+			.and_then(|rest| rest.split_once(' '))
+			.and_then(|(months, _)| months.parse().ok());
+
+		return Some(MessageDetails {
+			kind: if sub_months.is_some() {
+				MessageKind::Resub
+			} else {
+				MessageKind::Sub
+			},
+			sub_tier: Some(sub_tier),
+			sub_months,
+			..MessageDetails::default()
+		});
+	}
+
+	if let Some(rest) = text.strip_prefix(" gifted a Tier ") {
+		let (tier, rest) = rest.split_once(' ')?;
+		let recipient = rest.strip_prefix("sub to ")?.split_once('!')?.0;
+
+		return Some(MessageDetails {
+			kind: MessageKind::SubGift,
+			sub_tier: Some(plan(tier)?),
+			gift_count: Some(1),
+			gift_recipient: Some(recipient.to_string()),
+			..MessageDetails::default()
+		});
+	}
+
+	let rest = text.strip_prefix(" is gifting ")?;
+	let (count, rest) = rest.split_once(' ')?;
+	let (tier, _) = rest.strip_prefix("Tier ")?.split_once(' ')?;
+
+	Some(MessageDetails {
+		kind: MessageKind::SubMysteryGift,
+		sub_tier: Some(plan(tier)?),
+		gift_count: Some(count.parse().ok()?),
+		..MessageDetails::default()
+	})
+}
+
+/// Turns a tier like `1` into its plan like `1000`
+fn plan(tier: &str) -> Option<String> {
+	let tier = tier.parse::<u32>().ok()?;
+
+	(1..=3).contains(&tier).then(|| (tier * 1000).to_string())
+}
+
+/// Strips a prefix such as a username, ignoring its case
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+	let head = text.get(..prefix.len())?;
+
+	head.eq_ignore_ascii_case(prefix)
+		.then(|| &text[prefix.len()..])
 }
 
 #[derive(Deserialize, Debug)]
@@ -122,6 +425,13 @@ pub struct GqlTrackedUser {
 	pub videos: Option<GqlEdgeContainer<GqlVideo>>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct GqlClipsUser {
+	#[serde(with = "string")]
+	pub id: i64,
+	pub clips: Option<GqlEdgeContainer<GqlClip>>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct GqlChannelResponse {
 	pub user: Option<GqlChannel>,
@@ -130,7 +440,7 @@ pub struct GqlChannelResponse {
 #[derive(Deserialize, Debug)]
 pub struct GqlUserResponse {
 	#[serde(rename(deserialize = "targetUser"))]
-	pub user: GqlUser,
+	pub user: Option<GqlUser>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -147,3 +457,35 @@ pub struct GqlVideoContentResponse {
 pub struct GqlVideoMetadataResponse {
 	pub video: Option<GqlVideo>,
 }
+
+#[derive(Deserialize, Debug)]
+pub struct GqlClipsUserResponse {
+	pub user: Option<GqlClipsUser>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GqlClipResponse {
+	pub clip: Option<GqlClip>,
+}
+
+/// The broadcast a channel is streaming
+#[derive(Deserialize, Debug)]
+pub struct GqlStream {
+	#[serde(with = "string")]
+	pub id: i64,
+	#[serde(rename(deserialize = "createdAt"))]
+	pub created_at: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GqlLiveUser {
+	#[serde(with = "string")]
+	pub id: i64,
+	/// The current broadcast, or `None` if the channel is offline
+	pub stream: Option<GqlStream>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GqlLiveUserResponse {
+	pub user: Option<GqlLiveUser>,
+}
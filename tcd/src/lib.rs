@@ -1,7 +1,22 @@
 #![feature(iter_intersperse)]
 #![warn(clippy::pedantic)]
 
+#[cfg(feature = "postgres")]
+pub mod bulk;
 pub mod channel;
+pub mod checkpoint;
+pub mod clip;
 pub mod gql;
+#[cfg(feature = "live")]
+pub mod live;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "postgres")]
 pub mod prisma;
+mod sidecar;
+pub mod sink;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod user;
 pub mod video;
+pub mod watermark;
@@ -0,0 +1,713 @@
+use std::{
+	collections::HashMap,
+	fmt,
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use tokio::time::Instant;
+use tokio_tungstenite::{connect_async, tungstenite};
+
+use crate::{
+	channel::{BroadcastType, Channel, VideoSort},
+	gql::{
+		client::GqlClient,
+		prelude::{Chunk, ChunkError},
+		request::{GqlUseLiveVariables, Operation},
+		retry::RetryPolicy,
+		structs::{
+			GqlBadge, GqlComment, GqlCommentMessage, GqlCommentMessageFragment, GqlCommenter,
+			GqlEdge, GqlEmote, GqlLiveUserResponse, GqlResponse, MessageDetails, MessageKind,
+		},
+	},
+	sink::{save_page, ClearChat, ClearMessage, ModerationSink, Sink},
+	video::Video,
+};
+
+/// The Twitch chat server, which speaks IRC over a WebSocket
+pub const IRC_ENDPOINT: &str = "wss://irc-ws.chat.twitch.tv:443";
+
+/// The number of comments buffered for a channel before they are saved
+const BATCH_SIZE: usize = 100;
+
+/// The longest a comment is buffered for before it is saved
+const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the broadcast of a channel is looked up, to notice streams starting and ending
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The most the creation of a broadcast's archive can differ from the start of the stream
+const ARCHIVE_DELAY: chrono::Duration = chrono::Duration::minutes(5);
+
+/// A single IRC message and its tags
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IrcMessage {
+	pub tags: HashMap<String, String>,
+	pub prefix: Option<String>,
+	pub command: String,
+	pub params: Vec<String>,
+}
+
+impl IrcMessage {
+	/// Parses a line without its trailing `\r\n`
+	#[must_use]
+	pub fn parse(line: &str) -> Option<Self> {
+		let mut rest = line;
+		let mut message = Self::default();
+
+		if let Some(tags) = rest.strip_prefix('@') {
+			let (tags, tail) = tags.split_once(' ')?;
+
+			message.tags = tags
+				.split(';')
+				.map(|tag| match tag.split_once('=') {
+					Some((key, value)) => (key.to_string(), unescape_tag(value)),
+					None => (tag.to_string(), String::new()),
+				})
+				.collect();
+			rest = tail.trim_start();
+		}
+
+		if let Some(prefix) = rest.strip_prefix(':') {
+			let (prefix, tail) = prefix.split_once(' ')?;
+
+			message.prefix = Some(prefix.to_string());
+			rest = tail.trim_start();
+		}
+
+		let (params, trailing) = match rest.split_once(" :") {
+			Some((params, trailing)) => (params, Some(trailing)),
+			None => (rest, None),
+		};
+		let mut params = params.split(' ').filter(|param| !param.is_empty());
+
+		message.command = params.next()?.to_string();
+		message.params = params.map(str::to_string).collect();
+		message.params.extend(trailing.map(str::to_string));
+
+		Some(message)
+	}
+
+	/// Gets a tag, treating empty tags as missing
+	fn tag(&self, name: &str) -> Option<&str> {
+		self.tags
+			.get(name)
+			.map(String::as_str)
+			.filter(|value| !value.is_empty())
+	}
+
+	/// The nickname of the sender, from the prefix
+	fn nick(&self) -> Option<&str> {
+		let prefix = self.prefix.as_deref()?;
+
+		Some(prefix.split_once('!').map_or(prefix, |(nick, _)| nick))
+	}
+
+	/// The channel the message was sent to, without the leading `#`
+	fn channel(&self) -> Option<&str> {
+		self.params.first()?.strip_prefix('#')
+	}
+
+	fn sent_at(&self) -> DateTime<FixedOffset> {
+		let sent_at = self
+			.tag("tmi-sent-ts")
+			.and_then(|ms| ms.parse().ok())
+			.map_or_else(SystemTime::now, |ms| UNIX_EPOCH + Duration::from_millis(ms));
+
+		DateTime::<Utc>::from(sent_at).into()
+	}
+
+	/// Gets a numeric tag
+	fn number(&self, name: &str) -> Option<u32> {
+		self.tag(name)?.parse().ok()
+	}
+
+	/// The details of a `USERNOTICE` from its `msg-id` and `msg-param-*` tags
+	fn notice_details(&self) -> MessageDetails {
+		let kind = self
+			.tag("msg-id")
+			.map_or(MessageKind::Notice, MessageKind::from_notice);
+
+		MessageDetails {
+			kind,
+			bits: 0,
+			sub_tier: self.tag("msg-param-sub-plan").map(str::to_string),
+			sub_months: self.number("msg-param-cumulative-months"),
+			gift_count: match kind {
+				MessageKind::SubGift => Some(self.number("msg-param-gift-months").unwrap_or(1)),
+				MessageKind::SubMysteryGift => self.number("msg-param-mass-gift-count"),
+				_ => None,
+			},
+			gift_recipient: self
+				.tag("msg-param-recipient-user-name")
+				.map(str::to_string),
+			raid_viewers: self.number("msg-param-viewerCount"),
+			inferred: false,
+		}
+	}
+}
+
+/// Reverses the escaping of tag values
+fn unescape_tag(value: &str) -> String {
+	let mut unescaped = String::with_capacity(value.len());
+	let mut chars = value.chars();
+
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			unescaped.push(c);
+			continue;
+		}
+
+		match chars.next() {
+			Some(':') => unescaped.push(';'),
+			Some('s') => unescaped.push(' '),
+			Some('r') => unescaped.push('\r'),
+			Some('n') => unescaped.push('\n'),
+			Some(c) => unescaped.push(c),
+			None => {}
+		}
+	}
+
+	unescaped
+}
+
+/// Something that happened in a channel's chat
+#[derive(Debug)]
+pub enum ChatEvent {
+	/// A chat message, or the message shared with a sub, raid or other `USERNOTICE`.
+	/// Its offset is 0 until [`capture`] sets it from the start of the broadcast.
+	Comment {
+		channel: String,
+		channel_id: i64,
+		comment: GqlComment,
+	},
+	Clear(ClearChat),
+	Delete(ClearMessage),
+}
+
+impl ChatEvent {
+	/// Gets the event for a `PRIVMSG`, `USERNOTICE`, `CLEARCHAT` or `CLEARMSG` message
+	#[must_use]
+	pub fn from_irc(message: &IrcMessage) -> Option<Self> {
+		let channel = message.channel()?.to_string();
+		let channel_id = message.tag("room-id")?.parse().ok()?;
+
+		match message.command.as_str() {
+			"PRIVMSG" => {
+				let text = message.params.get(1)?;
+				// `/me` messages are wrapped in a CTCP ACTION
+				let text = text
+					.strip_prefix("\u{1}ACTION ")
+					.and_then(|text| text.strip_suffix('\u{1}'))
+					.unwrap_or(text);
+				let details = match message.number("bits") {
+					Some(bits) => MessageDetails {
+						kind: MessageKind::Cheer,
+						bits,
+						..MessageDetails::default()
+					},
+					None => MessageDetails::default(),
+				};
+
+				Some(Self::Comment {
+					channel,
+					channel_id,
+					comment: comment(message, message.nick()?, text, details)?,
+				})
+			}
+			"USERNOTICE" => {
+				let text = message
+					.params
+					.get(1)
+					.map(String::as_str)
+					.or_else(|| message.tag("system-msg"))?;
+
+				Some(Self::Comment {
+					channel,
+					channel_id,
+					comment: comment(
+						message,
+						message.tag("login")?,
+						text,
+						message.notice_details(),
+					)?,
+				})
+			}
+			"CLEARCHAT" => Some(Self::Clear(ClearChat {
+				channel,
+				channel_id,
+				target: message
+					.tag("target-user-id")
+					.and_then(|id| id.parse().ok())
+					.zip(message.params.get(1).cloned()),
+				duration: message
+					.tag("ban-duration")
+					.and_then(|duration| duration.parse().ok()),
+				created_at: message.sent_at(),
+			})),
+			"CLEARMSG" => Some(Self::Delete(ClearMessage {
+				channel,
+				channel_id,
+				id: message.tag("target-msg-id")?.to_string(),
+			})),
+			_ => None,
+		}
+	}
+}
+
+/// Builds a comment from the tags of a message
+fn comment(
+	message: &IrcMessage,
+	username: &str,
+	text: &str,
+	details: MessageDetails,
+) -> Option<GqlComment> {
+	Some(GqlComment {
+		id: message.tag("id")?.to_string(),
+		commenter: Some(GqlCommenter {
+			id: message.tag("user-id")?.parse().ok()?,
+			username: username.to_string(),
+			display_name: message.tag("display-name").unwrap_or(username).to_string(),
+		}),
+		offset: 0,
+		created_at: message.sent_at(),
+		message: GqlCommentMessage {
+			fragments: fragments(text, message.tag("emotes")),
+			badges: message
+				.tag("badges")
+				.into_iter()
+				.flat_map(|badges| badges.split(','))
+				.filter_map(|badge| badge.split_once('/'))
+				.map(|(set_id, version)| GqlBadge {
+					set_id: set_id.to_string(),
+					version: version.to_string(),
+				})
+				.collect(),
+			color: message.tag("color").map(str::to_string),
+		},
+		tags: Some(details),
+		deleted: false,
+	})
+}
+
+/// Splits a message into text and emote fragments using the `emotes` tag,
+/// which looks like `25:0-4,12-16/1902:6-10` with inclusive character offsets
+fn fragments(text: &str, emotes: Option<&str>) -> Vec<GqlCommentMessageFragment> {
+	let chars = text.chars().collect::<Vec<_>>();
+	let mut ranges = emotes
+		.into_iter()
+		.flat_map(|emotes| emotes.split('/'))
+		.filter_map(|emote| emote.split_once(':'))
+		.flat_map(|(id, ranges)| {
+			ranges.split(',').filter_map(move |range| {
+				let (start, end) = range.split_once('-')?;
+
+				Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?, id))
+			})
+		})
+		.collect::<Vec<_>>();
+
+	ranges.sort_unstable();
+
+	let mut fragments = Vec::new();
+	let mut position = 0;
+
+	for (start, end, id) in ranges {
+		// Skip ranges that overlap or do not fit in the message
+		if start < position || end < start || end >= chars.len() {
+			continue;
+		}
+
+		if start > position {
+			fragments.push(GqlCommentMessageFragment {
+				emote: None,
+				text: chars[position..start].iter().collect(),
+			});
+		}
+
+		fragments.push(GqlCommentMessageFragment {
+			emote: Some(GqlEmote {
+				emote_id: id.to_string(),
+			}),
+			text: chars[start..=end].iter().collect(),
+		});
+		position = end + 1;
+	}
+
+	if position < chars.len() || fragments.is_empty() {
+		fragments.push(GqlCommentMessageFragment {
+			emote: None,
+			text: chars[position..].iter().collect(),
+		});
+	}
+
+	fragments
+}
+
+/// Looks up the archived video of the broadcast a channel is streaming, which Twitch
+/// creates when the stream starts. Returns `None` while the channel is offline, or if
+/// it does not archive its broadcasts.
+async fn broadcast(
+	gql: &GqlClient,
+	retry: &RetryPolicy,
+	channel: &str,
+	channel_id: i64,
+) -> Result<Option<Video>, ChunkError> {
+	let body: GqlResponse<GqlLiveUserResponse> = retry
+		.run(|| async {
+			gql.request(
+				Operation::UseLive,
+				GqlUseLiveVariables { username: channel },
+			)
+			.await
+			.map_err(|e| ChunkError::from_response(e, Operation::UseLive, channel_id.to_string()))
+		})
+		.await?;
+
+	let Some(stream) = body.data.user.and_then(|user| user.stream) else {
+		return Ok(None);
+	};
+
+	let archives = Channel {
+		id: channel_id,
+		username: channel.to_string(),
+		last_video_id: None,
+		broadcast_type: BroadcastType::Archive,
+		sort: VideoSort::Time,
+		since: None,
+		until: None,
+	};
+	let videos = retry.run(|| archives.first_chunk(gql)).await?;
+
+	Ok(videos
+		.edges
+		.into_iter()
+		.find(|edge| (edge.node.created_at - stream.created_at).abs() <= ARCHIVE_DELAY)
+		.map(Video::from))
+}
+
+/// The comments of a channel that have not been saved yet
+struct Batch {
+	channel: String,
+	channel_id: i64,
+	/// The archived video the comments are saved under, or `None` while there is none
+	video: Option<Video>,
+	/// When the broadcast was last looked up
+	looked_up_at: Option<Instant>,
+	comments: Vec<GqlEdge<GqlComment>>,
+}
+
+impl Batch {
+	/// Marks the comments not saved yet that a ban, timeout or cleared chat removes as deleted
+	fn clear(&mut self, clear: &ClearChat) {
+		for edge in &mut self.comments {
+			let commenter = edge.node.commenter.as_ref().map(|commenter| commenter.id);
+
+			if edge.node.created_at <= clear.created_at
+				&& clear
+					.target
+					.as_ref()
+					.is_none_or(|(id, _)| commenter == Some(*id))
+			{
+				edge.node.deleted = true;
+			}
+		}
+	}
+
+	fn new(channel: &str, channel_id: i64) -> Self {
+		Self {
+			channel: channel.to_string(),
+			channel_id,
+			video: None,
+			looked_up_at: None,
+			comments: Vec::new(),
+		}
+	}
+
+	/// Looks up the broadcast of the channel if it has not been looked up recently,
+	/// saving it and the channel the first time it is seen
+	async fn refresh<S: Sink + ?Sized>(
+		&mut self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+	) -> Result<(), S::Error> {
+		if self
+			.looked_up_at
+			.is_some_and(|at| at.elapsed() < LOOKUP_INTERVAL)
+		{
+			return Ok(());
+		}
+
+		let video = broadcast(gql, retry, &self.channel, self.channel_id).await?;
+
+		if let Some(video) = &video {
+			if self.video.as_ref().map(|v| v.id) != Some(video.id) {
+				video.save_to(sink).await?;
+			}
+		}
+
+		self.video = video;
+		self.looked_up_at = Some(Instant::now());
+
+		Ok(())
+	}
+
+	/// Saves the comments under the current broadcast, with their offsets from its start
+	async fn save<S: Sink + ?Sized>(
+		&mut self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		verbose: bool,
+	) -> Result<(), S::Error> {
+		if self.comments.is_empty() {
+			return Ok(());
+		}
+
+		self.refresh(gql, retry, sink).await?;
+
+		let Some(video) = &self.video else {
+			// Chat sent while the channel is offline is not part of any broadcast
+			if verbose {
+				println!(
+					"Skipped {} comments sent while {} had no archived broadcast",
+					self.comments.len(),
+					self.channel
+				);
+			}
+
+			self.comments.clear();
+
+			return Ok(());
+		};
+
+		for edge in &mut self.comments {
+			edge.node.offset =
+				u32::try_from((edge.node.created_at - video.created_at).num_seconds()).unwrap_or(0);
+		}
+
+		save_page(sink, video, &self.comments, verbose).await?;
+		self.comments.clear();
+
+		sink.flush().await
+	}
+
+	/// Saves the comments not saved yet, then marks the saved comments
+	/// that a ban, timeout or cleared chat removes as deleted
+	async fn save_clear<S: ModerationSink + ?Sized>(
+		&mut self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		verbose: bool,
+		clear: &ClearChat,
+	) -> Result<(), S::Error> {
+		self.save(gql, retry, sink, verbose).await?;
+		self.refresh(gql, retry, sink).await?;
+
+		match &self.video {
+			Some(video) => sink.clear_comments(video, clear).await,
+			None => Ok(()),
+		}
+	}
+}
+
+/// The comments of each channel that have not been saved yet.
+/// Passing the same batches to every [`capture`] keeps them across reconnects.
+#[derive(Default)]
+pub struct Batches(HashMap<String, Batch>);
+
+impl Batches {
+	fn get(&mut self, channel: String, channel_id: i64) -> &mut Batch {
+		self.0
+			.entry(channel)
+			.or_insert_with_key(|channel| Batch::new(channel, channel_id))
+	}
+
+	/// Saves the comments of every channel, keeping those of a channel that fails
+	async fn save<S: Sink + ?Sized>(
+		&mut self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		verbose: bool,
+	) {
+		for batch in self.0.values_mut() {
+			if let Err(e) = batch.save(gql, retry, sink, verbose).await {
+				report(&batch.channel, &e);
+			}
+		}
+	}
+}
+
+/// Logs an error saving the chat of one channel, which does not stop the others from being saved
+fn report(channel: &str, error: &dyn std::error::Error) {
+	let mut message = format!("Failed to save the live chat of {channel}: {error}");
+	let mut source = error.source();
+
+	while let Some(cause) = source {
+		message.push_str(": ");
+		message.push_str(&cause.to_string());
+		source = cause.source();
+	}
+
+	eprintln!("{message}");
+}
+
+/// Captures the chat of the channels as it is sent, saving it to `sink`.
+/// Returns once the server closes the connection or asks for a reconnect,
+/// leaving comments that could not be saved in `batches`.
+#[allow(clippy::missing_errors_doc)]
+pub async fn capture<S: ModerationSink + ?Sized>(
+	endpoint: &str,
+	gql: &GqlClient,
+	retry: &RetryPolicy,
+	channels: &[String],
+	sink: &S,
+	batches: &mut Batches,
+	verbose: bool,
+) -> Result<(), LiveError> {
+	let (mut socket, _) = connect_async(endpoint).await?;
+	let nick = format!("justinfan{}", rand::thread_rng().gen_range(10_000..100_000));
+	let mut login = vec![
+		"CAP REQ :twitch.tv/tags twitch.tv/commands".to_string(),
+		// Anonymous logins can read chat with any password
+		"PASS SCHMOOPIIE".to_string(),
+		format!("NICK {nick}"),
+	];
+
+	login.extend(
+		channels
+			.iter()
+			.map(|channel| format!("JOIN #{}", channel.to_lowercase())),
+	);
+
+	for line in login {
+		socket.send(tungstenite::Message::Text(line)).await?;
+	}
+
+	let mut deadline = Instant::now() + BATCH_INTERVAL;
+
+	'read: loop {
+		let frame = match tokio::time::timeout_at(deadline, socket.next()).await {
+			Ok(Some(frame)) => frame?,
+			Ok(None) => break,
+			Err(_) => {
+				batches.save(gql, retry, sink, verbose).await;
+
+				deadline = Instant::now() + BATCH_INTERVAL;
+				continue;
+			}
+		};
+
+		let text = match frame {
+			tungstenite::Message::Text(text) => text,
+			tungstenite::Message::Close(_) => break,
+			_ => continue,
+		};
+
+		for message in text.split("\r\n").filter_map(IrcMessage::parse) {
+			match message.command.as_str() {
+				"PING" => {
+					let token = message.params.first().map_or("", String::as_str);
+
+					socket
+						.send(tungstenite::Message::Text(format!("PONG :{token}")))
+						.await?;
+				}
+				"RECONNECT" => break 'read,
+				_ => {}
+			}
+
+			let Some(event) = ChatEvent::from_irc(&message) else {
+				continue;
+			};
+
+			match event {
+				ChatEvent::Comment {
+					channel,
+					channel_id,
+					comment,
+				} => {
+					let batch = batches.get(channel, channel_id);
+
+					batch.comments.push(GqlEdge {
+						cursor: None,
+						node: comment,
+					});
+
+					if batch.comments.len() >= BATCH_SIZE {
+						if let Err(e) = batch.save(gql, retry, sink, verbose).await {
+							report(&batch.channel, &e);
+						}
+					}
+				}
+				ChatEvent::Clear(clear) => {
+					let batch = batches.get(clear.channel.clone(), clear.channel_id);
+
+					batch.clear(&clear);
+
+					// Comments already saved are marked as deleted where they were saved
+					if let Err(e) = batch.save_clear(gql, retry, sink, verbose, &clear).await {
+						report(&batch.channel, &e);
+					}
+				}
+				ChatEvent::Delete(delete) => {
+					let pending = batches.0.get_mut(&delete.channel).and_then(|batch| {
+						batch
+							.comments
+							.iter_mut()
+							.find(|edge| edge.node.id == delete.id)
+					});
+
+					// Comments already saved are marked as deleted where they were saved
+					match pending {
+						Some(edge) => edge.node.deleted = true,
+						None => {
+							if let Err(e) = sink.delete_comment(&delete).await {
+								report(&delete.channel, &e);
+							}
+						}
+					}
+				}
+			}
+		}
+	}
+
+	batches.save(gql, retry, sink, verbose).await;
+
+	Ok(())
+}
+
+/// An error that occurred while capturing live chat
+#[derive(Clone, Debug)]
+pub enum LiveError {
+	/// The connection to the chat server failed
+	WebSocket(Arc<tungstenite::Error>),
+}
+
+impl fmt::Display for LiveError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::WebSocket(_) => f.write_str("failed to read from the chat server"),
+		}
+	}
+}
+
+impl std::error::Error for LiveError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::WebSocket(source) => Some(source.as_ref()),
+		}
+	}
+}
+
+impl From<tungstenite::Error> for LiveError {
+	fn from(error: tungstenite::Error) -> Self {
+		Self::WebSocket(Arc::new(error))
+	}
+}
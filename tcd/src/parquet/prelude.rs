@@ -0,0 +1,395 @@
+use std::{
+	fmt,
+	io::Write,
+	mem,
+	sync::{Arc, Mutex},
+};
+
+use ::parquet::{
+	basic::Compression,
+	data_type::{BoolType, ByteArray, ByteArrayType, Int32Type, Int64Type},
+	errors::ParquetError,
+	file::{properties::WriterProperties, writer::SerializedFileWriter},
+	schema::parser::parse_message_type,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+
+use crate::{
+	checkpoint::StoreError,
+	clip::Clip,
+	gql::{
+		prelude::ChunkError,
+		structs::{GqlComment, GqlCommenter, GqlEdge},
+	},
+	sink::Sink,
+	video::Video,
+};
+
+/// The number of comments buffered before they are written as a row group
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 100_000;
+
+const SCHEMA: &str = "
+	message comment {
+		REQUIRED BYTE_ARRAY channel (UTF8);
+		REQUIRED INT64 video_id;
+		REQUIRED BYTE_ARRAY comment_id (UTF8);
+		REQUIRED INT64 commenter_id;
+		REQUIRED BYTE_ARRAY commenter (UTF8);
+		REQUIRED INT64 created_at (TIMESTAMP(MILLIS,true));
+		REQUIRED INT32 offset;
+		REQUIRED BYTE_ARRAY text (UTF8);
+		REQUIRED BYTE_ARRAY kind (UTF8);
+		REQUIRED INT32 bits;
+		OPTIONAL BYTE_ARRAY sub_tier (UTF8);
+		OPTIONAL INT32 sub_months;
+		OPTIONAL INT32 gift_count;
+		OPTIONAL BYTE_ARRAY gift_recipient (UTF8);
+		OPTIONAL INT32 raid_viewers;
+		REQUIRED BOOLEAN inferred;
+		REQUIRED BOOLEAN deleted;
+	}
+";
+
+/// The buffered rows of a row group, one vector per column in schema order
+#[derive(Default)]
+struct CommentColumns {
+	channel: Vec<ByteArray>,
+	video_id: Vec<i64>,
+	comment_id: Vec<ByteArray>,
+	commenter_id: Vec<i64>,
+	commenter: Vec<ByteArray>,
+	created_at: Vec<i64>,
+	offset: Vec<i32>,
+	text: Vec<ByteArray>,
+	kind: Vec<ByteArray>,
+	bits: Vec<i32>,
+	sub_tier: Vec<Option<ByteArray>>,
+	sub_months: Vec<Option<i32>>,
+	gift_count: Vec<Option<i32>>,
+	gift_recipient: Vec<Option<ByteArray>>,
+	raid_viewers: Vec<Option<i32>>,
+	inferred: Vec<bool>,
+	deleted: Vec<bool>,
+}
+
+impl CommentColumns {
+	fn len(&self) -> usize {
+		self.video_id.len()
+	}
+
+	fn push(
+		&mut self,
+		channel: &str,
+		video_id: i64,
+		comment: &GqlComment,
+		commenter: &GqlCommenter,
+	) {
+		self.channel.push(channel.into());
+		self.video_id.push(video_id);
+		self.comment_id.push(comment.id.as_str().into());
+		self.commenter_id.push(commenter.id);
+		self.commenter.push(commenter.username.as_str().into());
+		self.created_at.push(comment.created_at.timestamp_millis());
+		self.offset.push(to_i32(comment.offset));
+		self.text.push(comment.message.text().into_bytes().into());
+
+		let details = comment.details();
+
+		self.kind.push(details.kind.name().into());
+		self.bits.push(to_i32(details.bits));
+		self.sub_tier
+			.push(details.sub_tier.map(|tier| tier.into_bytes().into()));
+		self.sub_months.push(details.sub_months.map(to_i32));
+		self.gift_count.push(details.gift_count.map(to_i32));
+		self.gift_recipient.push(
+			details
+				.gift_recipient
+				.map(|recipient| recipient.into_bytes().into()),
+		);
+		self.raid_viewers.push(details.raid_viewers.map(to_i32));
+		self.inferred.push(details.inferred);
+		self.deleted.push(comment.deleted);
+	}
+
+	/// Writes the buffered rows as a row group and clears the buffer
+	fn write_row_group<W: Write + Send>(
+		&mut self,
+		writer: &mut SerializedFileWriter<W>,
+	) -> Result<(), ParquetError> {
+		if self.len() == 0 {
+			return Ok(());
+		}
+
+		let columns = mem::take(self);
+		let mut row_group = writer.next_row_group()?;
+		let mut index = 0;
+
+		while let Some(mut column) = row_group.next_column()? {
+			match index {
+				0 => column
+					.typed::<ByteArrayType>()
+					.write_batch(&columns.channel, None, None),
+				1 => column
+					.typed::<Int64Type>()
+					.write_batch(&columns.video_id, None, None),
+				2 => column
+					.typed::<ByteArrayType>()
+					.write_batch(&columns.comment_id, None, None),
+				3 => column
+					.typed::<Int64Type>()
+					.write_batch(&columns.commenter_id, None, None),
+				4 => column
+					.typed::<ByteArrayType>()
+					.write_batch(&columns.commenter, None, None),
+				5 => column
+					.typed::<Int64Type>()
+					.write_batch(&columns.created_at, None, None),
+				6 => column
+					.typed::<Int32Type>()
+					.write_batch(&columns.offset, None, None),
+				7 => column
+					.typed::<ByteArrayType>()
+					.write_batch(&columns.text, None, None),
+				8 => column
+					.typed::<ByteArrayType>()
+					.write_batch(&columns.kind, None, None),
+				9 => column
+					.typed::<Int32Type>()
+					.write_batch(&columns.bits, None, None),
+				10 => {
+					let (values, levels) = optional(&columns.sub_tier);
+
+					column
+						.typed::<ByteArrayType>()
+						.write_batch(&values, Some(&levels), None)
+				}
+				11 => {
+					let (values, levels) = optional(&columns.sub_months);
+
+					column
+						.typed::<Int32Type>()
+						.write_batch(&values, Some(&levels), None)
+				}
+				12 => {
+					let (values, levels) = optional(&columns.gift_count);
+
+					column
+						.typed::<Int32Type>()
+						.write_batch(&values, Some(&levels), None)
+				}
+				13 => {
+					let (values, levels) = optional(&columns.gift_recipient);
+
+					column
+						.typed::<ByteArrayType>()
+						.write_batch(&values, Some(&levels), None)
+				}
+				14 => {
+					let (values, levels) = optional(&columns.raid_viewers);
+
+					column
+						.typed::<Int32Type>()
+						.write_batch(&values, Some(&levels), None)
+				}
+				15 => column
+					.typed::<BoolType>()
+					.write_batch(&columns.inferred, None, None),
+				_ => column
+					.typed::<BoolType>()
+					.write_batch(&columns.deleted, None, None),
+			}?;
+
+			column.close()?;
+			index += 1;
+		}
+
+		row_group.close()?;
+
+		Ok(())
+	}
+}
+
+struct State<W: Write + Send> {
+	writer: SerializedFileWriter<W>,
+	columns: CommentColumns,
+}
+
+/// Writes comments to a Parquet file. Rows from every concurrent download
+/// are buffered together and written as a row group once enough have been buffered.
+pub struct ParquetWriter<W: Write + Send> {
+	state: Mutex<State<W>>,
+	row_group_size: usize,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+	#[allow(clippy::missing_errors_doc)]
+	pub fn new(out: W, row_group_size: usize) -> Result<Self, ParquetError> {
+		let schema = Arc::new(parse_message_type(SCHEMA)?);
+		let properties = Arc::new(
+			WriterProperties::builder()
+				.set_compression(Compression::SNAPPY)
+				.build(),
+		);
+
+		Ok(Self {
+			state: Mutex::new(State {
+				writer: SerializedFileWriter::new(out, schema, properties)?,
+				columns: CommentColumns::default(),
+			}),
+			row_group_size: row_group_size.max(1),
+		})
+	}
+
+	/// Buffers a page of comments, skipping those without a commenter,
+	/// and returns the number of comments buffered
+	#[allow(clippy::missing_errors_doc)]
+	pub fn write(
+		&self,
+		channel: &str,
+		video_id: i64,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, ParquetError> {
+		let mut state = self.state.lock().map_err(|_| poisoned())?;
+		let State { writer, columns } = &mut *state;
+		let mut count = 0;
+
+		for comment in comments {
+			if let Some(commenter) = &comment.node.commenter {
+				columns.push(channel, video_id, &comment.node, commenter);
+				count += 1;
+			}
+		}
+
+		if columns.len() >= self.row_group_size {
+			columns.write_row_group(writer)?;
+		}
+
+		Ok(count)
+	}
+
+	/// Writes the remaining comments and the file footer, returning the underlying writer.
+	/// The file cannot be read until this is called.
+	#[allow(clippy::missing_errors_doc)]
+	pub fn finish(self) -> Result<W, ParquetError> {
+		let State {
+			mut writer,
+			mut columns,
+		} = self.state.into_inner().map_err(|_| poisoned())?;
+
+		columns.write_row_group(&mut writer)?;
+		writer.into_inner()
+	}
+}
+
+#[async_trait]
+impl<W: Write + Send> Sink for ParquetWriter<W> {
+	type Error = ParquetWriterError;
+
+	/// Writes nothing, since every row has the id and username of its commenter
+	async fn save_users(
+		&self,
+		_users: &[&GqlCommenter],
+		_seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, ParquetWriterError> {
+		Ok(0)
+	}
+
+	/// Writes nothing, since every row has its video and channel
+	async fn save_video(&self, _video: &Video) -> Result<(), ParquetWriterError> {
+		Ok(())
+	}
+
+	/// Writes nothing, since the chat of a clip is written as the rows of its video
+	async fn save_clip(&self, _clip: &Clip) -> Result<(), ParquetWriterError> {
+		Ok(())
+	}
+
+	async fn save_comments(
+		&self,
+		video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, ParquetWriterError> {
+		Ok(self.write(&video.author, video.id, comments)?)
+	}
+
+	/// Writes the buffered comments as a row group, even if it is not full yet
+	async fn flush(&self) -> Result<(), ParquetWriterError> {
+		let mut state = self.state.lock().map_err(|_| poisoned())?;
+		let State { writer, columns } = &mut *state;
+
+		columns.write_row_group(writer)?;
+		writer.inner_mut().flush().map_err(ParquetError::from)?;
+
+		Ok(())
+	}
+}
+
+/// An error that occurred while writing comments to a Parquet file
+#[derive(Clone, Debug)]
+pub enum ParquetWriterError {
+	/// The comments could not be downloaded
+	Chunk(ChunkError),
+	/// The checkpoints or watermarks could not be loaded or saved
+	Store(StoreError),
+	/// Encoding or writing the file failed
+	Parquet(Arc<ParquetError>),
+}
+
+impl fmt::Display for ParquetWriterError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Chunk(error) => error.fmt(f),
+			Self::Store(error) => error.fmt(f),
+			Self::Parquet(_) => f.write_str("failed to write to the Parquet file"),
+		}
+	}
+}
+
+impl std::error::Error for ParquetWriterError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Chunk(error) => error.source(),
+			Self::Store(error) => error.source(),
+			Self::Parquet(source) => Some(source.as_ref()),
+		}
+	}
+}
+
+impl From<ChunkError> for ParquetWriterError {
+	fn from(error: ChunkError) -> Self {
+		Self::Chunk(error)
+	}
+}
+
+impl From<StoreError> for ParquetWriterError {
+	fn from(error: StoreError) -> Self {
+		Self::Store(error)
+	}
+}
+
+impl From<ParquetError> for ParquetWriterError {
+	fn from(error: ParquetError) -> Self {
+		Self::Parquet(Arc::new(error))
+	}
+}
+
+/// Splits an optional column into its present values and its definition levels
+fn optional<T: Clone>(column: &[Option<T>]) -> (Vec<T>, Vec<i16>) {
+	let values = column.iter().flatten().cloned().collect();
+	let levels = column
+		.iter()
+		.map(|value| i16::from(value.is_some()))
+		.collect();
+
+	(values, levels)
+}
+
+fn to_i32(value: u32) -> i32 {
+	i32::try_from(value).unwrap_or(i32::MAX)
+}
+
+/// A thread panicked while writing, so the row group may be incomplete
+fn poisoned() -> ParquetError {
+	ParquetError::General("a previous write panicked".to_string())
+}
@@ -0,0 +1,3 @@
+mod prelude;
+
+pub(crate) use prelude::*;
@@ -0,0 +1,144 @@
+use std::{
+	collections::HashMap,
+	ffi::OsString,
+	fs::{self, File},
+	io::{self, BufRead, BufReader, BufWriter, Write},
+	panic,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The number of records appended to a sidecar file before
+/// it is rewritten with only the newest record for each key
+const COMPACT_AFTER: usize = 1000;
+
+/// A record in a sidecar file, such as the checkpoint of a video
+pub(crate) trait Keyed: Clone + Serialize + DeserializeOwned + Send + 'static {
+	/// The id of the video or channel the record is for
+	fn key(&self) -> i64;
+}
+
+/// A JSON Lines file next to the output file that keeps the newest record for each key.
+/// Every save is appended to the file, which is compacted once enough have piled up.
+pub(crate) struct SidecarFile<T> {
+	records: Arc<Mutex<HashMap<i64, T>>>,
+	journal: Arc<Mutex<Journal>>,
+}
+
+/// The open sidecar file, which every saved record is appended to
+struct Journal {
+	path: PathBuf,
+	file: File,
+	appended: usize,
+}
+
+impl Journal {
+	/// Appends the newest record for a key to the file
+	fn append<T: Keyed>(&mut self, records: &Mutex<HashMap<i64, T>>, key: i64) -> io::Result<()> {
+		if self.appended >= COMPACT_AFTER {
+			let records: Vec<_> = records.lock().unwrap().values().cloned().collect();
+
+			self.file = compact(&self.path, &records)?;
+			self.appended = 0;
+
+			return Ok(());
+		}
+
+		// Saves that finish out of order still leave the newest record last
+		let Some(record) = records.lock().unwrap().get(&key).cloned() else {
+			return Ok(());
+		};
+		let mut line = serde_json::to_vec(&record)?;
+		line.push(b'\n');
+
+		self.file.write_all(&line)?;
+		self.appended += 1;
+
+		Ok(())
+	}
+}
+
+impl<T: Keyed> SidecarFile<T> {
+	/// Opens the file at `path`. The records already in it are kept if `resume`
+	/// is set, and are otherwise thrown away so that the run starts over.
+	pub fn open(path: PathBuf, resume: bool) -> io::Result<Self> {
+		let mut records = HashMap::new();
+
+		if resume && path.exists() {
+			let mut lines = BufReader::new(File::open(&path)?).lines().peekable();
+
+			while let Some(line) = lines.next() {
+				match serde_json::from_str::<T>(&line?) {
+					Ok(record) => {
+						records.insert(record.key(), record);
+					}
+					// The last append may have been cut off by a crash
+					Err(_) if lines.peek().is_none() => {}
+					Err(e) => return Err(e.into()),
+				}
+			}
+		}
+
+		let file = compact(&path, &records.values().cloned().collect::<Vec<_>>())?;
+
+		Ok(Self {
+			records: Arc::new(Mutex::new(records)),
+			journal: Arc::new(Mutex::new(Journal {
+				path,
+				file,
+				appended: 0,
+			})),
+		})
+	}
+
+	/// Gets the newest record for a key
+	pub fn get(&self, key: i64) -> Option<T> {
+		self.records.lock().unwrap().get(&key).cloned()
+	}
+
+	/// Saves a record, replacing the one with the same key.
+	/// The file is written on a blocking thread, without holding the records.
+	pub async fn save(&self, record: &T) -> io::Result<()> {
+		let key = record.key();
+
+		self.records.lock().unwrap().insert(key, record.clone());
+
+		let records = Arc::clone(&self.records);
+		let journal = Arc::clone(&self.journal);
+
+		tokio::task::spawn_blocking(move || journal.lock().unwrap().append(&records, key))
+			.await
+			.unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+	}
+}
+
+/// The path of a sidecar file for an output file, such as `out.csv.state`
+pub(crate) fn sidecar_path(output: &Path, extension: &str) -> PathBuf {
+	let mut path = OsString::from(output.as_os_str());
+	path.push(".");
+	path.push(extension);
+
+	PathBuf::from(path)
+}
+
+/// Rewrites the file at `path` with `records`, in one step
+/// so a crash never leaves it half-written, and opens it for appending
+fn compact<T: Serialize>(path: &Path, records: &[T]) -> io::Result<File> {
+	let mut temp = OsString::from(path.as_os_str());
+	temp.push(".tmp");
+
+	let mut writer = BufWriter::new(File::create(&temp)?);
+
+	for record in records {
+		serde_json::to_writer(&mut writer, record)?;
+		writer.write_all(b"\n")?;
+	}
+
+	writer.flush()?;
+	drop(writer);
+	fs::rename(temp, path)?;
+
+	File::options().append(true).open(path)
+}
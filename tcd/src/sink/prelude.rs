@@ -0,0 +1,753 @@
+use std::{
+	fmt,
+	io::{self, BufWriter, Write},
+	sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+#[cfg(feature = "postgres")]
+use chrono::Utc;
+use chrono::{DateTime, FixedOffset};
+#[cfg(feature = "postgres")]
+use futures::join;
+#[cfg(feature = "postgres")]
+use prisma_client_rust::{Direction, QueryError};
+
+use crate::{
+	checkpoint::StoreError,
+	clip::Clip,
+	gql::{
+		prelude::{ChunkError, Format},
+		structs::{GqlComment, GqlCommenter, GqlEdge},
+	},
+	user::{EnrichUsers, User},
+	video::{format_data, Video},
+};
+#[cfg(feature = "postgres")]
+use crate::{
+	gql::prelude::Save,
+	prisma::{self, PrismaClient},
+	video::fragment_entries,
+};
+
+/// A destination for downloaded commenters, videos and comments.
+/// Every method is called concurrently for different videos.
+#[async_trait]
+pub trait Sink: Send + Sync {
+	/// The error of the sink, which also carries the errors of the requests
+	/// for what it saves and of the checkpoints saved with it
+	type Error: std::error::Error + From<ChunkError> + From<StoreError> + Send + Sync + 'static;
+
+	/// Saves the distinct commenters of a page of comments, before the comments
+	/// themselves, returning the number of new users. `seen_at` is when the users
+	/// had their usernames, which renames the users whose newest username changed.
+	async fn save_users(
+		&self,
+		users: &[&GqlCommenter],
+		seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, Self::Error>;
+
+	/// Saves a video, ignoring it if it already exists
+	async fn save_video(&self, video: &Video) -> Result<(), Self::Error>;
+
+	/// Saves a clip after the video it was cut from, ignoring it if it already exists
+	async fn save_clip(&self, clip: &Clip) -> Result<(), Self::Error>;
+
+	/// Saves a page of comments for a video, skipping those without a commenter,
+	/// and returns the number of new comments
+	async fn save_comments(
+		&self,
+		video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, Self::Error>;
+
+	/// Makes everything saved so far durable, so that a checkpoint can be saved.
+	/// A sink that saves checkpoints with its comments may wait for a full batch instead.
+	async fn flush(&self) -> Result<(), Self::Error>;
+}
+
+/// A sink that keeps the users it saves, so that their details can be looked up later
+#[async_trait]
+pub trait UserSink: Sink {
+	/// Saves the details of users looked up from their viewer cards,
+	/// creating the users that do not exist yet
+	async fn save_user_details(&self, users: &[User]) -> Result<(), Self::Error>;
+
+	/// Gets up to `limit` saved users without details in the order of their ids,
+	/// starting after the id `after`, as pairs of ids and usernames
+	async fn users_without_details(
+		&self,
+		users: EnrichUsers,
+		after: i64,
+		limit: usize,
+	) -> Result<Vec<(i64, String)>, Self::Error>;
+}
+
+/// A sink for live chat, which hears about the comments that moderators remove
+#[async_trait]
+pub trait ModerationSink: Sink {
+	/// Marks the saved comments of a video removed by a ban, a timeout or the chat being
+	/// cleared while capturing live chat as deleted, which are the comments of the target
+	/// sent before it, or every comment sent before it if the chat was cleared
+	async fn clear_comments(&self, video: &Video, clear: &ClearChat) -> Result<(), Self::Error>;
+
+	/// Marks a saved comment as deleted by a moderator while capturing live chat
+	async fn delete_comment(&self, delete: &ClearMessage) -> Result<(), Self::Error>;
+}
+
+/// A ban, a timeout, or the whole chat being cleared
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClearChat {
+	pub channel: String,
+	pub channel_id: i64,
+	/// The user whose messages were removed, or `None` if the chat was cleared
+	pub target: Option<(i64, String)>,
+	/// The length of the timeout in seconds, or `None` for a ban
+	pub duration: Option<u32>,
+	pub created_at: DateTime<FixedOffset>,
+}
+
+/// A single message deleted by a moderator
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClearMessage {
+	pub channel: String,
+	pub channel_id: i64,
+	/// The id of the deleted comment
+	pub id: String,
+}
+
+/// Saves the commenters of a page of comments, then the comments themselves
+pub(crate) async fn save_page<S: Sink + ?Sized>(
+	sink: &S,
+	video: &Video,
+	comments: &[GqlEdge<GqlComment>],
+	verbose: bool,
+) -> Result<(), S::Error> {
+	let mut users = comments
+		.iter()
+		.filter_map(|edge| edge.node.commenter.as_ref())
+		.collect::<Vec<_>>();
+
+	users.sort_unstable_by_key(|user| user.id);
+	users.dedup_by_key(|user| user.id);
+
+	// Every commenter of the page had their username by its newest comment
+	let seen_at = comments
+		.iter()
+		.map(|edge| edge.node.created_at)
+		.max()
+		.unwrap_or(video.created_at);
+	let users = sink.save_users(&users, seen_at).await?;
+	let comments = sink.save_comments(video, comments).await?;
+
+	if verbose && (users != 0 || comments != 0) {
+		println!("Saved {users} users, {comments} comments");
+	}
+
+	Ok(())
+}
+
+/// Writes comments to a file or stdout, one line per comment
+pub struct StreamSink<'a, W: Write + Send> {
+	stream: &'a Mutex<BufWriter<W>>,
+	format: &'a Format,
+}
+
+impl<W: Write + Send> StreamSink<'_, W> {
+	#[must_use]
+	pub fn new<'a>(stream: &'a Mutex<BufWriter<W>>, format: &'a Format) -> StreamSink<'a, W> {
+		StreamSink { stream, format }
+	}
+}
+
+#[async_trait]
+impl<W: Write + Send> Sink for StreamSink<'_, W> {
+	type Error = StreamError;
+
+	/// Writes nothing, since every comment is written with its commenter
+	async fn save_users(
+		&self,
+		_users: &[&GqlCommenter],
+		_seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, StreamError> {
+		Ok(0)
+	}
+
+	/// Writes nothing, since every comment is written with its video and channel
+	async fn save_video(&self, _video: &Video) -> Result<(), StreamError> {
+		Ok(())
+	}
+
+	/// Writes nothing, since the chat of a clip is written as the comments of its video
+	async fn save_clip(&self, _clip: &Clip) -> Result<(), StreamError> {
+		Ok(())
+	}
+
+	async fn save_comments(
+		&self,
+		video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, StreamError> {
+		let mut data = String::new();
+		let mut count = 0;
+
+		for comment in comments {
+			if let Some(commenter) = &comment.node.commenter {
+				data.push_str(&format_data(
+					self.format,
+					&video.author,
+					video.id,
+					&comment.node,
+					commenter,
+				)?);
+				data.push('\n');
+				count += 1;
+			}
+		}
+
+		self.stream.lock().unwrap().write_all(data.as_bytes())?;
+
+		Ok(count)
+	}
+
+	async fn flush(&self) -> Result<(), StreamError> {
+		Ok(self.stream.lock().unwrap().flush()?)
+	}
+}
+
+/// Comments cannot be changed once they are written to a stream, so only the comments
+/// that are removed before they are written are marked as deleted
+#[async_trait]
+impl<W: Write + Send> ModerationSink for StreamSink<'_, W> {
+	async fn clear_comments(&self, _video: &Video, _clear: &ClearChat) -> Result<(), StreamError> {
+		Ok(())
+	}
+
+	async fn delete_comment(&self, _delete: &ClearMessage) -> Result<(), StreamError> {
+		Ok(())
+	}
+}
+
+/// An error that occurred while writing comments to a stream
+#[derive(Clone, Debug)]
+pub enum StreamError {
+	/// The comments could not be downloaded
+	Chunk(ChunkError),
+	/// The checkpoints or watermarks could not be loaded or saved
+	Store(StoreError),
+	/// Writing to the stream failed
+	Io(Arc<io::Error>),
+}
+
+impl fmt::Display for StreamError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Chunk(error) => error.fmt(f),
+			Self::Store(error) => error.fmt(f),
+			Self::Io(_) => f.write_str("failed to write to the output stream"),
+		}
+	}
+}
+
+impl std::error::Error for StreamError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Chunk(error) => error.source(),
+			Self::Store(error) => error.source(),
+			Self::Io(source) => Some(source.as_ref()),
+		}
+	}
+}
+
+impl From<ChunkError> for StreamError {
+	fn from(error: ChunkError) -> Self {
+		Self::Chunk(error)
+	}
+}
+
+impl From<StoreError> for StreamError {
+	fn from(error: StoreError) -> Self {
+		Self::Store(error)
+	}
+}
+
+impl From<io::Error> for StreamError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(Arc::new(error))
+	}
+}
+
+/// Saves users, videos and comments to the database through Prisma
+#[cfg(feature = "postgres")]
+pub struct PrismaSink<'a> {
+	client: &'a PrismaClient,
+}
+
+#[cfg(feature = "postgres")]
+impl PrismaSink<'_> {
+	#[must_use]
+	pub fn new(client: &PrismaClient) -> PrismaSink<'_> {
+		PrismaSink { client }
+	}
+
+	/// Records when users were seen with their usernames in the `UsernameHistory`
+	/// table, and renames the users whose newest username changed
+	async fn save_usernames(
+		&self,
+		users: &[&GqlCommenter],
+		seen_at: DateTime<FixedOffset>,
+	) -> Result<(), QueryError> {
+		let ids = users.iter().map(|user| user.id).collect::<Vec<_>>();
+		let (history, saved) = join!(
+			self.client
+				.username_history()
+				.find_many(vec![prisma::username_history::user_id::in_vec(ids.clone())])
+				.exec(),
+			self.client
+				.user()
+				.find_many(vec![prisma::user::id::in_vec(ids)])
+				.exec()
+		);
+		let (history, saved) = (history?, saved?);
+		let mut created = Vec::new();
+		let mut widened = Vec::new();
+		let mut renamed = Vec::new();
+
+		for user in users {
+			let rows = history.iter().filter(|row| row.user_id == user.id);
+			let filter = || {
+				vec![
+					prisma::username_history::user_id::equals(user.id),
+					prisma::username_history::username::equals(user.username.clone()),
+				]
+			};
+
+			match rows.clone().find(|row| row.username == user.username) {
+				None => created.push(prisma::username_history::create_unchecked(
+					user.id,
+					user.username.clone(),
+					seen_at,
+					seen_at,
+					vec![],
+				)),
+				// Pages are saved out of order, so the range only ever widens
+				Some(row) => {
+					if seen_at < row.first_seen {
+						let mut filter = filter();
+
+						filter.push(prisma::username_history::first_seen::gt(seen_at));
+						widened.push((filter, prisma::username_history::first_seen::set(seen_at)));
+					}
+
+					if seen_at > row.last_seen {
+						let mut filter = filter();
+
+						filter.push(prisma::username_history::last_seen::lt(seen_at));
+						widened.push((filter, prisma::username_history::last_seen::set(seen_at)));
+					}
+				}
+			}
+
+			let newest = rows
+				.filter(|row| row.username != user.username)
+				.all(|row| row.last_seen <= seen_at);
+			let changed = saved.iter().any(|saved| {
+				saved.id == user.id
+					&& (saved.username != user.username
+						|| saved.display_name.as_deref() != Some(user.display_name.as_str()))
+			});
+
+			if newest && changed {
+				renamed.push(user);
+			}
+		}
+
+		self.client
+			.username_history()
+			.create_many(created)
+			.skip_duplicates()
+			.exec()
+			.await?;
+		futures::future::try_join_all(widened.into_iter().map(|(filter, set)| {
+			self.client
+				.username_history()
+				.update_many(filter, vec![set])
+				.exec()
+		}))
+		.await?;
+		futures::future::try_join_all(renamed.into_iter().map(|user| {
+			self.client
+				.user()
+				.update(
+					prisma::user::UniqueWhereParam::IdEquals(user.id),
+					vec![
+						prisma::user::username::set(user.username.clone()),
+						prisma::user::display_name::set(Some(user.display_name.clone())),
+					],
+				)
+				.exec()
+		}))
+		.await?;
+
+		Ok(())
+	}
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl Sink for PrismaSink<'_> {
+	type Error = PrismaError;
+
+	async fn save_users(
+		&self,
+		users: &[&GqlCommenter],
+		seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, PrismaError> {
+		let count = self
+			.client
+			.user()
+			.create_many(
+				users
+					.iter()
+					.map(|user| {
+						prisma::user::create_unchecked(
+							user.id,
+							user.username.clone(),
+							vec![prisma::user::display_name::set(Some(
+								user.display_name.clone(),
+							))],
+						)
+					})
+					.collect(),
+			)
+			.skip_duplicates()
+			.exec()
+			.await?;
+
+		self.save_usernames(users, seen_at).await?;
+
+		Ok(usize::try_from(count).unwrap_or(0))
+	}
+
+	async fn save_video(&self, video: &Video) -> Result<(), PrismaError> {
+		self.client
+			.video()
+			.upsert(
+				prisma::video::UniqueWhereParam::IdEquals(video.id),
+				prisma::video::create(
+					video.id,
+					prisma::user::UniqueWhereParam::IdEquals(video.author_id),
+					video.created_at,
+					video.prisma_params(),
+				),
+				// The metadata of a video changes while it is recording
+				video.prisma_params(),
+			)
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+
+	async fn save_clip(&self, clip: &Clip) -> Result<(), PrismaError> {
+		self.client
+			.clip()
+			.create_many(vec![prisma::clip::create_unchecked(
+				clip.id,
+				clip.slug.clone(),
+				clip.title.clone(),
+				clip.broadcaster_id,
+				clip.created_at,
+				i32::try_from(clip.duration).unwrap_or(i32::MAX),
+				vec![
+					prisma::clip::video_id::set(clip.video_id),
+					prisma::clip::offset::set(
+						clip.offset
+							.map(|offset| i32::try_from(offset).unwrap_or(i32::MAX)),
+					),
+				],
+			)])
+			.skip_duplicates()
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+
+	async fn save_comments(
+		&self,
+		video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, PrismaError> {
+		let count = CommentRows::new(video.id, comments)
+			.save(self.client)
+			.await?;
+
+		Ok(usize::try_from(count).unwrap_or(0))
+	}
+
+	/// Does nothing, since every query is committed as soon as it is made
+	async fn flush(&self) -> Result<(), PrismaError> {
+		Ok(())
+	}
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl UserSink for PrismaSink<'_> {
+	async fn save_user_details(&self, users: &[User]) -> Result<(), PrismaError> {
+		futures::future::try_join_all(users.iter().map(|user| user.save(self.client))).await?;
+
+		// Viewer cards are looked up now, so they always have the newest usernames
+		let commenters = users.iter().map(GqlCommenter::from).collect::<Vec<_>>();
+
+		self.save_usernames(&commenters.iter().collect::<Vec<_>>(), Utc::now().into())
+			.await?;
+
+		Ok(())
+	}
+
+	async fn users_without_details(
+		&self,
+		users: EnrichUsers,
+		after: i64,
+		limit: usize,
+	) -> Result<Vec<(i64, String)>, PrismaError> {
+		let mut filters = vec![
+			prisma::user::created_at::equals(None),
+			prisma::user::id::gt(after),
+		];
+
+		if users == EnrichUsers::Channels {
+			filters.push(prisma::user::videos::some(vec![]));
+		}
+
+		let users = self
+			.client
+			.user()
+			.find_many(filters)
+			.order_by(prisma::user::OrderByParam::Id(Direction::Asc))
+			.take(i64::try_from(limit).unwrap_or(i64::MAX))
+			.exec()
+			.await?;
+
+		Ok(users
+			.into_iter()
+			.map(|user| (user.id, user.username))
+			.collect())
+	}
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl ModerationSink for PrismaSink<'_> {
+	async fn clear_comments(&self, video: &Video, clear: &ClearChat) -> Result<(), PrismaError> {
+		let mut filters = vec![
+			prisma::comment::video_id::equals(video.id),
+			prisma::comment::created_at::lte(clear.created_at),
+		];
+
+		if let Some((user_id, _)) = clear.target {
+			filters.push(prisma::comment::user_id::equals(user_id));
+		}
+
+		self.client
+			.comment()
+			.update_many(filters, vec![prisma::comment::deleted::set(true)])
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+
+	async fn delete_comment(&self, delete: &ClearMessage) -> Result<(), PrismaError> {
+		self.client
+			.comment()
+			.update_many(
+				vec![prisma::comment::id::equals(delete.id.clone())],
+				vec![prisma::comment::deleted::set(true)],
+			)
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+}
+
+/// An error that occurred while saving comments to the database through Prisma
+#[cfg(feature = "postgres")]
+#[derive(Clone, Debug)]
+pub enum PrismaError {
+	/// The comments could not be downloaded
+	Chunk(ChunkError),
+	/// The checkpoints or watermarks could not be loaded or saved
+	Store(StoreError),
+	/// A database query failed
+	Query(Arc<QueryError>),
+}
+
+#[cfg(feature = "postgres")]
+impl fmt::Display for PrismaError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Chunk(error) => error.fmt(f),
+			Self::Store(error) => error.fmt(f),
+			Self::Query(_) => f.write_str("failed to query the database"),
+		}
+	}
+}
+
+#[cfg(feature = "postgres")]
+impl std::error::Error for PrismaError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Chunk(error) => error.source(),
+			Self::Store(error) => error.source(),
+			Self::Query(source) => Some(source.as_ref()),
+		}
+	}
+}
+
+#[cfg(feature = "postgres")]
+impl From<ChunkError> for PrismaError {
+	fn from(error: ChunkError) -> Self {
+		Self::Chunk(error)
+	}
+}
+
+#[cfg(feature = "postgres")]
+impl From<StoreError> for PrismaError {
+	fn from(error: StoreError) -> Self {
+		Self::Store(error)
+	}
+}
+
+#[cfg(feature = "postgres")]
+impl From<QueryError> for PrismaError {
+	fn from(error: QueryError) -> Self {
+		Self::Query(Arc::new(error))
+	}
+}
+
+/// The arguments of `prisma::comment::create_unchecked`
+#[cfg(feature = "postgres")]
+type CommentRow = (
+	String,
+	i64,
+	i64,
+	String,
+	::prisma_client_rust::chrono::DateTime<::prisma_client_rust::chrono::FixedOffset>,
+	i32,
+	Vec<prisma::comment::SetParam>,
+);
+
+/// The rows saved to the database for a page of comments
+#[cfg(feature = "postgres")]
+#[derive(Default)]
+struct CommentRows {
+	comments: Vec<CommentRow>,
+	fragments: Vec<(String, i32, String, Vec<prisma::comment_fragment::SetParam>)>,
+	badges: Vec<(String, String, String, Vec<prisma::comment_badge::SetParam>)>,
+}
+
+#[cfg(feature = "postgres")]
+impl CommentRows {
+	fn new(video_id: i64, edges: &[GqlEdge<GqlComment>]) -> Self {
+		let mut rows = Self::default();
+
+		for comment in edges {
+			let Some(commenter) = &comment.node.commenter else {
+				continue;
+			};
+
+			for (position, fragment) in fragment_entries(&comment.node.message.fragments)
+				.into_iter()
+				.enumerate()
+			{
+				let emote = fragment.emote.map_or_else(Vec::new, |emote| {
+					vec![
+						prisma::comment_fragment::emote_id::set(Some(emote.id.to_string())),
+						prisma::comment_fragment::emote_start::set(Some(to_i32(emote.start))),
+						prisma::comment_fragment::emote_end::set(Some(to_i32(emote.end))),
+					]
+				});
+
+				rows.fragments
+					.push(prisma::comment_fragment::create_unchecked(
+						comment.node.id.clone(),
+						to_i32(u32::try_from(position).unwrap_or(u32::MAX)),
+						fragment.text.to_string(),
+						emote,
+					));
+			}
+
+			for badge in &comment.node.message.badges {
+				rows.badges.push(prisma::comment_badge::create_unchecked(
+					comment.node.id.clone(),
+					badge.set_id.clone(),
+					badge.version.clone(),
+					vec![],
+				));
+			}
+
+			let details = comment.node.details();
+
+			rows.comments.push(prisma::comment::create_unchecked(
+				comment.node.id.clone(),
+				commenter.id,
+				video_id,
+				comment.node.message.text(),
+				comment.node.created_at,
+				to_i32(comment.node.offset),
+				vec![
+					prisma::comment::color::set(comment.node.message.color.clone()),
+					prisma::comment::kind::set(details.kind.name().to_string()),
+					prisma::comment::bits::set(to_i32(details.bits)),
+					prisma::comment::sub_tier::set(details.sub_tier),
+					prisma::comment::sub_months::set(details.sub_months.map(to_i32)),
+					prisma::comment::gift_count::set(details.gift_count.map(to_i32)),
+					prisma::comment::gift_recipient::set(details.gift_recipient),
+					prisma::comment::raid_viewers::set(details.raid_viewers.map(to_i32)),
+					prisma::comment::inferred::set(details.inferred),
+					prisma::comment::deleted::set(comment.node.deleted),
+				],
+			));
+		}
+
+		rows
+	}
+
+	/// Saves the rows, returning the number of new comments
+	async fn save(self, client: &PrismaClient) -> Result<i64, QueryError> {
+		let comments = client
+			.comment()
+			.create_many(self.comments)
+			.skip_duplicates()
+			.exec()
+			.await?;
+
+		// Fragments and badges reference their comment, so they are saved afterwards
+		let (fragments, badges) = join!(
+			client
+				.comment_fragment()
+				.create_many(self.fragments)
+				.skip_duplicates()
+				.exec(),
+			client
+				.comment_badge()
+				.create_many(self.badges)
+				.skip_duplicates()
+				.exec()
+		);
+
+		fragments?;
+		badges?;
+
+		Ok(comments)
+	}
+}
+
+#[cfg(feature = "postgres")]
+fn to_i32(value: u32) -> i32 {
+	i32::try_from(value).unwrap_or(i32::MAX)
+}
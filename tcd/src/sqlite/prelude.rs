@@ -0,0 +1,651 @@
+use std::{
+	collections::BTreeMap,
+	fmt, panic,
+	path::Path,
+	sync::{Arc, Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::{
+	checkpoint::{Checkpoint, StoreError},
+	clip::Clip,
+	gql::{
+		prelude::ChunkError,
+		structs::{GqlComment, GqlCommenter, GqlEdge},
+	},
+	sink::{ClearChat, ClearMessage, ModerationSink, Sink, UserSink},
+	user::{EnrichUsers, User},
+	video::{fragment_entries, Video},
+	watermark::Watermark,
+};
+
+const SCHEMA: &str = include_str!("schema.sql");
+
+/// An embedded database with the same tables as `prisma/schema.prisma`,
+/// so that downloads can be archived without running a database server
+pub struct SqliteStore {
+	connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+	/// Opens the database at `path`, creating it and its tables if needed
+	#[allow(clippy::missing_errors_doc)]
+	pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+		let connection = Connection::open(path)?;
+
+		// Write-ahead logging lets the file be read while a download is running
+		connection.pragma_update(None, "journal_mode", "WAL")?;
+
+		Self::new(connection)
+	}
+
+	/// Opens a database that only lives as long as the store
+	#[allow(clippy::missing_errors_doc)]
+	pub fn open_in_memory() -> rusqlite::Result<Self> {
+		Self::new(Connection::open_in_memory()?)
+	}
+
+	fn new(connection: Connection) -> rusqlite::Result<Self> {
+		connection.execute_batch(SCHEMA)?;
+
+		Ok(Self {
+			connection: Arc::new(Mutex::new(connection)),
+		})
+	}
+
+	/// Runs `query` on the blocking thread pool, since every query blocks the thread it runs on
+	async fn run<T, F>(&self, query: F) -> rusqlite::Result<T>
+	where
+		T: Send + 'static,
+		F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+	{
+		let connection = Arc::clone(&self.connection);
+
+		tokio::task::spawn_blocking(move || {
+			// Every write happens in a transaction, so a poisoned lock can be reused
+			query(&mut connection.lock().unwrap_or_else(PoisonError::into_inner))
+		})
+		.await
+		.unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+	}
+
+	/// Gets the creation date of the oldest saved video of a channel
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn oldest_video(
+		&self,
+		author_id: i64,
+	) -> rusqlite::Result<Option<DateTime<FixedOffset>>> {
+		let created_at: Option<String> = self
+			.run(move |connection| {
+				connection
+					.query_row(
+						r#"SELECT "createdAt" FROM "Video" WHERE "authorId" = ?1 ORDER BY "createdAt" ASC LIMIT 1"#,
+						params![author_id],
+						|row| row.get(0),
+					)
+					.optional()
+			})
+			.await?;
+
+		created_at
+			.map(|created_at| parse_timestamp(0, &created_at))
+			.transpose()
+	}
+
+	/// Gets the checkpoint saved for a video
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn load_checkpoint(&self, video_id: i64) -> rusqlite::Result<Option<Checkpoint>> {
+		self.run(move |connection| {
+			connection
+				.query_row(
+					r#"SELECT "cursor", "offset", "completed" FROM "Checkpoint" WHERE "videoId" = ?1"#,
+					params![video_id],
+					|row| {
+						Ok(Checkpoint {
+							video_id,
+							cursor: row.get(0)?,
+							offset: row.get(1)?,
+							completed: row.get(2)?,
+						})
+					},
+				)
+				.optional()
+		})
+		.await
+	}
+
+	/// Saves the checkpoint for a video, replacing the previous one
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn save_checkpoint(&self, checkpoint: &Checkpoint) -> rusqlite::Result<()> {
+		let checkpoint = checkpoint.clone();
+
+		self.run(move |connection| {
+			connection.execute(
+				r#"INSERT INTO "Checkpoint" ("videoId", "cursor", "offset", "completed", "updatedAt")
+				VALUES (?1, ?2, ?3, ?4, ?5)
+				ON CONFLICT ("videoId") DO UPDATE SET
+					"cursor" = excluded."cursor",
+					"offset" = excluded."offset",
+					"completed" = excluded."completed",
+					"updatedAt" = excluded."updatedAt""#,
+				params![
+					checkpoint.video_id,
+					checkpoint.cursor,
+					checkpoint.offset,
+					checkpoint.completed,
+					timestamp(Utc::now().into())
+				],
+			)?;
+
+			Ok(())
+		})
+		.await
+	}
+
+	/// Gets the newest video seen for a channel
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn load_watermark(&self, channel_id: i64) -> rusqlite::Result<Option<Watermark>> {
+		let watermark: Option<(i64, String)> = self
+			.run(move |connection| {
+				connection
+					.query_row(
+						r#"SELECT "videoId", "createdAt" FROM "Watermark" WHERE "channelId" = ?1"#,
+						params![channel_id],
+						|row| Ok((row.get(0)?, row.get(1)?)),
+					)
+					.optional()
+			})
+			.await?;
+
+		watermark
+			.map(|(video_id, created_at)| {
+				Ok(Watermark {
+					channel_id,
+					video_id,
+					created_at: parse_timestamp(1, &created_at)?,
+				})
+			})
+			.transpose()
+	}
+
+	/// Saves the newest video seen for a channel, replacing the previous one
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn save_watermark(&self, watermark: &Watermark) -> rusqlite::Result<()> {
+		let watermark = watermark.clone();
+
+		self.run(move |connection| {
+			connection.execute(
+				r#"INSERT INTO "Watermark" ("channelId", "videoId", "createdAt", "updatedAt")
+				VALUES (?1, ?2, ?3, ?4)
+				ON CONFLICT ("channelId") DO UPDATE SET
+					"videoId" = excluded."videoId",
+					"createdAt" = excluded."createdAt",
+					"updatedAt" = excluded."updatedAt""#,
+				params![
+					watermark.channel_id,
+					watermark.video_id,
+					timestamp(watermark.created_at),
+					timestamp(Utc::now().into())
+				],
+			)?;
+
+			Ok(())
+		})
+		.await
+	}
+
+	/// Gets the videos of a channel that may still be recording, with their lengths
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn load_growing(&self, channel_id: i64) -> rusqlite::Result<BTreeMap<i64, u32>> {
+		self.run(move |connection| {
+			connection
+				.prepare(
+					r#"SELECT "videoId", "length" FROM "GrowingVideo" WHERE "channelId" = ?1"#,
+				)?
+				.query_map(params![channel_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+				.collect()
+		})
+		.await
+	}
+
+	/// Replaces the videos of a channel that may still be recording
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn save_growing(
+		&self,
+		channel_id: i64,
+		growing: &BTreeMap<i64, u32>,
+	) -> rusqlite::Result<()> {
+		let growing = growing.clone();
+
+		self.run(move |connection| {
+			let transaction = connection.transaction()?;
+
+			transaction.execute(
+				r#"DELETE FROM "GrowingVideo" WHERE "channelId" = ?1"#,
+				params![channel_id],
+			)?;
+
+			for (video_id, length) in growing {
+				transaction.execute(
+					r#"INSERT INTO "GrowingVideo" ("videoId", "channelId", "length") VALUES (?1, ?2, ?3)"#,
+					params![video_id, channel_id, length],
+				)?;
+			}
+
+			transaction.commit()
+		})
+		.await
+	}
+}
+
+#[async_trait]
+impl Sink for SqliteStore {
+	type Error = SqliteError;
+
+	async fn save_users(
+		&self,
+		users: &[&GqlCommenter],
+		seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, SqliteError> {
+		let users: Vec<GqlCommenter> = users.iter().map(|&user| user.clone()).collect();
+		let seen_at = timestamp(seen_at);
+
+		let count = self
+			.run(move |connection| {
+				let transaction = connection.transaction()?;
+				let mut count = 0;
+
+				for user in &users {
+					count += save_username(
+						&transaction,
+						user.id,
+						&user.username,
+						Some(&user.display_name),
+						&seen_at,
+					)?;
+				}
+
+				transaction.commit()?;
+
+				Ok(count)
+			})
+			.await?;
+
+		Ok(count)
+	}
+
+	/// Saves a video and its author
+	async fn save_video(&self, video: &Video) -> Result<(), SqliteError> {
+		let video = video.clone();
+
+		Ok(self
+			.run(move |connection| {
+				let transaction = connection.transaction()?;
+
+				// The author of a video is looked up with the video, so it has the newest username
+				save_username(
+					&transaction,
+					video.author_id,
+					&video.author,
+					None,
+					&timestamp(Utc::now().into()),
+				)?;
+				// The metadata of a video changes while it is recording, so it is always replaced
+				transaction.execute(
+					r#"INSERT INTO "Video" ("id", "authorId", "createdAt", "title", "length", "thumbnailUrl", "game", "viewCount", "language")
+					VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+					ON CONFLICT ("id") DO UPDATE SET
+						"title" = excluded."title",
+						"length" = excluded."length",
+						"thumbnailUrl" = excluded."thumbnailUrl",
+						"game" = excluded."game",
+						"viewCount" = excluded."viewCount",
+						"language" = excluded."language""#,
+					params![
+						video.id,
+						video.author_id,
+						timestamp(video.created_at),
+						video.title,
+						video.length,
+						video.thumbnail_url,
+						video.game,
+						video.view_count,
+						video.language
+					],
+				)?;
+
+				transaction.commit()
+			})
+			.await?)
+	}
+
+	async fn save_clip(&self, clip: &Clip) -> Result<(), SqliteError> {
+		let clip = clip.clone();
+
+		self.run(move |connection| {
+			connection.execute(
+				r#"INSERT OR IGNORE INTO "Clip" ("id", "slug", "title", "broadcasterId", "videoId", "createdAt", "duration", "offset")
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"#,
+				params![
+					clip.id,
+					clip.slug,
+					clip.title,
+					clip.broadcaster_id,
+					clip.video_id,
+					timestamp(clip.created_at),
+					clip.duration,
+					clip.offset
+				],
+			)
+		})
+		.await?;
+
+		Ok(())
+	}
+
+	/// Saves a page of comments along with their fragments and badges
+	async fn save_comments(
+		&self,
+		video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, SqliteError> {
+		let video_id = video.id;
+		let comments = comments.to_vec();
+
+		let count = self
+			.run(move |connection| {
+				let transaction = connection.transaction()?;
+				let mut count = 0;
+
+				{
+					let mut insert_comment = transaction.prepare_cached(
+						r#"INSERT OR IGNORE INTO "Comment" ("id", "userId", "videoId", "createdAt", "text", "offset", "color",
+							"kind", "bits", "subTier", "subMonths", "giftCount", "giftRecipient", "raidViewers", "inferred", "deleted")
+						VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)"#,
+					)?;
+					let mut insert_fragment = transaction.prepare_cached(
+						r#"INSERT OR IGNORE INTO "CommentFragment" ("commentId", "position", "text", "emoteId", "emoteStart", "emoteEnd")
+						VALUES (?1, ?2, ?3, ?4, ?5, ?6)"#,
+					)?;
+					let mut insert_badge = transaction.prepare_cached(
+						r#"INSERT OR IGNORE INTO "CommentBadge" ("commentId", "setId", "version") VALUES (?1, ?2, ?3)"#,
+					)?;
+
+					for edge in &comments {
+						let comment = &edge.node;
+						let Some(commenter) = &comment.commenter else {
+							continue;
+						};
+						let details = comment.details();
+
+						count += insert_comment.execute(params![
+							comment.id,
+							commenter.id,
+							video_id,
+							timestamp(comment.created_at),
+							comment.message.text(),
+							comment.offset,
+							comment.message.color,
+							details.kind.name(),
+							details.bits,
+							details.sub_tier,
+							details.sub_months,
+							details.gift_count,
+							details.gift_recipient,
+							details.raid_viewers,
+							details.inferred,
+							comment.deleted
+						])?;
+
+						for (position, fragment) in fragment_entries(&comment.message.fragments)
+							.into_iter()
+							.enumerate()
+						{
+							let emote = fragment.emote.as_ref();
+
+							insert_fragment.execute(params![
+								comment.id,
+								position,
+								fragment.text,
+								emote.map(|emote| emote.id),
+								emote.map(|emote| emote.start),
+								emote.map(|emote| emote.end)
+							])?;
+						}
+
+						for badge in &comment.message.badges {
+							insert_badge.execute(params![comment.id, badge.set_id, badge.version])?;
+						}
+					}
+				}
+
+				transaction.commit()?;
+
+				Ok(count)
+			})
+			.await?;
+
+		Ok(count)
+	}
+
+	/// Does nothing, since every statement is committed as soon as it is run
+	async fn flush(&self) -> Result<(), SqliteError> {
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl UserSink for SqliteStore {
+	async fn save_user_details(&self, users: &[User]) -> Result<(), SqliteError> {
+		let users = users.to_vec();
+
+		Ok(self
+			.run(move |connection| {
+				let transaction = connection.transaction()?;
+				// Viewer cards are looked up now, so they always have the newest usernames
+				let seen_at = timestamp(Utc::now().into());
+
+				{
+					let mut update = transaction.prepare_cached(
+						r#"UPDATE "User" SET "createdAt" = ?2, "profileImageUrl" = ?3, "description" = ?4 WHERE "id" = ?1"#,
+					)?;
+
+					for user in &users {
+						save_username(
+							&transaction,
+							user.id,
+							&user.username,
+							Some(&user.display_name),
+							&seen_at,
+						)?;
+						update.execute(params![
+							user.id,
+							timestamp(user.created_at),
+							user.profile_image_url,
+							user.description
+						])?;
+					}
+				}
+
+				transaction.commit()
+			})
+			.await?)
+	}
+
+	async fn users_without_details(
+		&self,
+		users: EnrichUsers,
+		after: i64,
+		limit: usize,
+	) -> Result<Vec<(i64, String)>, SqliteError> {
+		let users = self
+			.run(move |connection| {
+				let mut select = connection.prepare_cached(
+					r#"SELECT "id", "username" FROM "User"
+					WHERE "createdAt" IS NULL AND "id" > ?1
+						AND (?2 OR "id" IN (SELECT "authorId" FROM "Video"))
+					ORDER BY "id" LIMIT ?3"#,
+				)?;
+				let users = select
+					.query_map(
+						params![
+							after,
+							users == EnrichUsers::Commenters,
+							i64::try_from(limit).unwrap_or(i64::MAX)
+						],
+						|row| Ok((row.get(0)?, row.get(1)?)),
+					)?
+					.collect::<rusqlite::Result<_>>()?;
+
+				Ok(users)
+			})
+			.await?;
+
+		Ok(users)
+	}
+}
+
+#[async_trait]
+impl ModerationSink for SqliteStore {
+	async fn clear_comments(&self, video: &Video, clear: &ClearChat) -> Result<(), SqliteError> {
+		let video_id = video.id;
+		let created_at = timestamp(clear.created_at);
+		let user_id = clear.target.as_ref().map(|&(id, _)| id);
+
+		self.run(move |connection| {
+			connection.execute(
+				r#"UPDATE "Comment" SET "deleted" = 1
+				WHERE "videoId" = ?1 AND "createdAt" <= ?2 AND (?3 IS NULL OR "userId" = ?3)"#,
+				params![video_id, created_at, user_id],
+			)
+		})
+		.await?;
+
+		Ok(())
+	}
+
+	async fn delete_comment(&self, delete: &ClearMessage) -> Result<(), SqliteError> {
+		let id = delete.id.clone();
+
+		self.run(move |connection| {
+			connection.execute(
+				r#"UPDATE "Comment" SET "deleted" = 1 WHERE "id" = ?1"#,
+				[id],
+			)
+		})
+		.await?;
+
+		Ok(())
+	}
+}
+
+/// An error that occurred while saving comments to a [`SqliteStore`]
+#[derive(Clone, Debug)]
+pub enum SqliteError {
+	/// The comments could not be downloaded
+	Chunk(ChunkError),
+	/// The checkpoints or watermarks could not be loaded or saved
+	Store(StoreError),
+	/// A query to the database failed
+	Sqlite(Arc<rusqlite::Error>),
+}
+
+impl fmt::Display for SqliteError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Chunk(error) => error.fmt(f),
+			Self::Store(error) => error.fmt(f),
+			Self::Sqlite(_) => f.write_str("failed to query the SQLite database"),
+		}
+	}
+}
+
+impl std::error::Error for SqliteError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Chunk(error) => error.source(),
+			Self::Store(error) => error.source(),
+			Self::Sqlite(source) => Some(source.as_ref()),
+		}
+	}
+}
+
+impl From<ChunkError> for SqliteError {
+	fn from(error: ChunkError) -> Self {
+		Self::Chunk(error)
+	}
+}
+
+impl From<StoreError> for SqliteError {
+	fn from(error: StoreError) -> Self {
+		Self::Store(error)
+	}
+}
+
+impl From<rusqlite::Error> for SqliteError {
+	fn from(error: rusqlite::Error) -> Self {
+		Self::Sqlite(Arc::new(error))
+	}
+}
+
+impl From<rusqlite::Error> for StoreError {
+	fn from(error: rusqlite::Error) -> Self {
+		Self::new(error)
+	}
+}
+
+/// Saves a user if it does not exist and records when it was seen with its username,
+/// renaming it if the username is its newest one. Returns the number of new users.
+fn save_username(
+	transaction: &Transaction<'_>,
+	id: i64,
+	username: &str,
+	display_name: Option<&str>,
+	seen_at: &str,
+) -> rusqlite::Result<usize> {
+	let count = transaction
+		.prepare_cached(
+			r#"INSERT OR IGNORE INTO "User" ("id", "username", "displayName") VALUES (?1, ?2, ?3)"#,
+		)?
+		.execute(params![id, username, display_name])?;
+
+	// Pages are saved out of order, so the range only ever widens
+	transaction
+		.prepare_cached(
+			r#"INSERT INTO "UsernameHistory" ("userId", "username", "firstSeen", "lastSeen")
+			VALUES (?1, ?2, ?3, ?3)
+			ON CONFLICT ("userId", "username") DO UPDATE SET
+				"firstSeen" = MIN("firstSeen", excluded."firstSeen"),
+				"lastSeen" = MAX("lastSeen", excluded."lastSeen")"#,
+		)?
+		.execute(params![id, username, seen_at])?;
+	transaction
+		.prepare_cached(
+			r#"UPDATE "User" SET "username" = ?2, "displayName" = COALESCE(?3, "displayName")
+			WHERE "id" = ?1
+				AND ("username" IS NOT ?2 OR "displayName" IS NOT COALESCE(?3, "displayName"))
+				AND ?2 = (
+					SELECT "username" FROM "UsernameHistory" WHERE "userId" = ?1
+					ORDER BY "lastSeen" DESC LIMIT 1
+				)"#,
+		)?
+		.execute(params![id, username, display_name])?;
+
+	Ok(count)
+}
+
+/// Parses a timestamp written by [`timestamp`] from a column
+fn parse_timestamp(column: usize, value: &str) -> rusqlite::Result<DateTime<FixedOffset>> {
+	DateTime::parse_from_rfc3339(value).map_err(|e| {
+		rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(e))
+	})
+}
+
+/// Formats a timestamp in UTC so that timestamps sort in chronological order
+fn timestamp(time: DateTime<FixedOffset>) -> String {
+	time.with_timezone(&Utc)
+		.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
@@ -0,0 +1,101 @@
+-- The same tables as `prisma/schema.prisma`, created when the database is opened.
+-- Timestamps are stored as RFC 3339 strings in UTC so that they sort correctly.
+CREATE TABLE IF NOT EXISTS "User" (
+	"id" INTEGER NOT NULL PRIMARY KEY,
+	"username" TEXT NOT NULL,
+	"displayName" TEXT,
+	"createdAt" TEXT,
+	"profileImageUrl" TEXT,
+	"description" TEXT
+);
+
+CREATE TABLE IF NOT EXISTS "UsernameHistory" (
+	"userId" INTEGER NOT NULL REFERENCES "User"("id"),
+	"username" TEXT NOT NULL,
+	"firstSeen" TEXT NOT NULL,
+	"lastSeen" TEXT NOT NULL,
+	PRIMARY KEY ("userId", "username")
+);
+
+CREATE TABLE IF NOT EXISTS "Video" (
+	"id" INTEGER NOT NULL PRIMARY KEY,
+	"authorId" INTEGER NOT NULL REFERENCES "User"("id"),
+	"createdAt" TEXT NOT NULL,
+	"title" TEXT,
+	"length" INTEGER,
+	"thumbnailUrl" TEXT,
+	"game" TEXT,
+	"viewCount" INTEGER,
+	"language" TEXT
+);
+
+CREATE TABLE IF NOT EXISTS "Clip" (
+	"id" INTEGER NOT NULL PRIMARY KEY,
+	"slug" TEXT NOT NULL,
+	"title" TEXT NOT NULL,
+	"broadcasterId" INTEGER NOT NULL REFERENCES "User"("id"),
+	"videoId" INTEGER REFERENCES "Video"("id"),
+	"createdAt" TEXT NOT NULL,
+	"duration" INTEGER NOT NULL,
+	"offset" INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS "Comment" (
+	"id" TEXT NOT NULL PRIMARY KEY,
+	"userId" INTEGER NOT NULL REFERENCES "User"("id"),
+	"videoId" INTEGER NOT NULL REFERENCES "Video"("id"),
+	"createdAt" TEXT NOT NULL,
+	"text" TEXT NOT NULL,
+	"offset" INTEGER NOT NULL,
+	"color" TEXT,
+	"kind" TEXT NOT NULL DEFAULT 'chat',
+	"bits" INTEGER NOT NULL DEFAULT 0,
+	"subTier" TEXT,
+	"subMonths" INTEGER,
+	"giftCount" INTEGER,
+	"giftRecipient" TEXT,
+	"raidViewers" INTEGER,
+	"inferred" INTEGER NOT NULL DEFAULT 0,
+	"deleted" INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS "CommentFragment" (
+	"commentId" TEXT NOT NULL REFERENCES "Comment"("id"),
+	"position" INTEGER NOT NULL,
+	"text" TEXT NOT NULL,
+	"emoteId" TEXT,
+	"emoteStart" INTEGER,
+	"emoteEnd" INTEGER,
+	PRIMARY KEY ("commentId", "position")
+);
+
+CREATE TABLE IF NOT EXISTS "CommentBadge" (
+	"commentId" TEXT NOT NULL REFERENCES "Comment"("id"),
+	"setId" TEXT NOT NULL,
+	"version" TEXT NOT NULL,
+	PRIMARY KEY ("commentId", "setId")
+);
+
+CREATE TABLE IF NOT EXISTS "Checkpoint" (
+	"videoId" INTEGER NOT NULL PRIMARY KEY,
+	"cursor" TEXT,
+	"offset" INTEGER NOT NULL,
+	"completed" INTEGER NOT NULL DEFAULT 0,
+	"updatedAt" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "Watermark" (
+	"channelId" INTEGER NOT NULL PRIMARY KEY,
+	"videoId" INTEGER NOT NULL,
+	"createdAt" TEXT NOT NULL,
+	"updatedAt" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "GrowingVideo" (
+	"videoId" INTEGER NOT NULL PRIMARY KEY,
+	"channelId" INTEGER NOT NULL,
+	"length" INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "Video_authorId_createdAt_idx" ON "Video"("authorId", "createdAt");
+CREATE INDEX IF NOT EXISTS "Comment_videoId_idx" ON "Comment"("videoId");
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+
+#[cfg(feature = "postgres")]
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use futures::StreamExt;
+#[cfg(feature = "postgres")]
+use prisma_client_rust::QueryError;
+
+use crate::{
+	channel::ChannelError,
+	gql::{
+		client::GqlClient,
+		prelude::{ChunkError, RequestError, ResponseError},
+		request::{GqlPlayerContextVariables, GqlViewerCardVariables, Operation},
+		retry::RetryPolicy,
+		structs::{GqlChannelResponse, GqlCommenter, GqlResponse, GqlUser, GqlUserResponse},
+	},
+	sink::UserSink,
+};
+#[cfg(feature = "postgres")]
+use crate::{
+	gql::prelude::Save,
+	prisma::{self, PrismaClient},
+};
+
+/// The number of users without details to get from a sink at once
+const PAGE_SIZE: usize = 100;
+
+/// The details of an account, as shown on its viewer card
+#[derive(Clone, Debug, PartialEq)]
+pub struct User {
+	pub id: i64,
+	pub username: String,
+	pub display_name: String,
+	/// When the account was created
+	pub created_at: DateTime<FixedOffset>,
+	pub profile_image_url: Option<String>,
+	pub description: Option<String>,
+}
+
+impl User {
+	/// Gets a user from a username
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn from_username<S: Into<String>>(
+		gql: &GqlClient,
+		username: S,
+	) -> Result<Option<Self>, ChannelError> {
+		let username = username.into();
+		let user: GqlResponse<GqlChannelResponse> = gql
+			.request(
+				Operation::PlayerTrackingContextQuery,
+				GqlPlayerContextVariables {
+					channel: username.as_str(),
+					is_live: true,
+					has_collection: false,
+					collection_id: "",
+					video_id: "",
+					has_video: false,
+					slug: "",
+					has_clip: false,
+				},
+			)
+			.await
+			.map_err(|e| {
+				RequestError::from_response(
+					e,
+					Operation::PlayerTrackingContextQuery,
+					username.clone(),
+				)
+			})?;
+		let Some(user) = user.data.user else {
+			return Ok(None);
+		};
+
+		let user = Self::viewer_card(gql, user.id, &user.username)
+			.await
+			.map_err(|e| RequestError::from_response(e, Operation::ViewerCard, username.clone()))?;
+
+		Ok(user.data.user.map(Self::from))
+	}
+
+	/// Gets a user whose id and username are already known, such as a commenter.
+	/// Returns `None` if the account no longer exists.
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn from_id(
+		gql: &GqlClient,
+		id: i64,
+		username: &str,
+	) -> Result<Option<Self>, ChunkError> {
+		let user = Self::viewer_card(gql, id, username)
+			.await
+			.map_err(|e| ChunkError::from_response(e, Operation::ViewerCard, id.to_string()))?;
+
+		Ok(user.data.user.map(Self::from))
+	}
+
+	/// Gets the viewer card of a user as shown in their own channel
+	async fn viewer_card(
+		gql: &GqlClient,
+		id: i64,
+		username: &str,
+	) -> Result<GqlResponse<GqlUserResponse>, ResponseError> {
+		gql.request(
+			Operation::ViewerCard,
+			GqlViewerCardVariables {
+				channel_id: id,
+				channel_name: username,
+				has_channel_id: true,
+				username,
+				badge_collection: true,
+				standard_gifting: false,
+			},
+		)
+		.await
+	}
+
+	/// The columns of the user besides its id and username
+	#[cfg(feature = "postgres")]
+	pub(crate) fn prisma_params(&self) -> Vec<prisma::user::SetParam> {
+		vec![
+			prisma::user::display_name::set(Some(self.display_name.clone())),
+			prisma::user::created_at::set(Some(self.created_at)),
+			prisma::user::profile_image_url::set(self.profile_image_url.clone()),
+			prisma::user::description::set(self.description.clone()),
+		]
+	}
+}
+
+impl From<GqlUser> for User {
+	fn from(user: GqlUser) -> Self {
+		Self {
+			id: user.id,
+			username: user.username,
+			display_name: user.display_name,
+			created_at: user.created_at.into(),
+			profile_image_url: user.profile_image_url,
+			description: user.description,
+		}
+	}
+}
+
+impl From<&User> for GqlCommenter {
+	fn from(user: &User) -> Self {
+		Self {
+			id: user.id,
+			username: user.username.clone(),
+			display_name: user.display_name.clone(),
+		}
+	}
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl Save for User {
+	/// Saves the user, replacing the details of an existing one
+	async fn save(&self, client: &PrismaClient) -> Result<(), QueryError> {
+		client
+			.user()
+			.upsert(
+				prisma::user::UniqueWhereParam::IdEquals(self.id),
+				prisma::user::create(self.id, self.username.clone(), self.prisma_params()),
+				self.prisma_params(),
+			)
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+}
+
+/// The saved users to look up the details of
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnrichUsers {
+	/// The authors of saved videos
+	#[default]
+	Channels,
+	/// Every saved user, including commenters
+	Commenters,
+}
+
+/// The result of a pass of a [`UserEnricher`]
+#[derive(Debug, Default)]
+pub struct Enriched {
+	/// The number of users whose details were saved
+	pub count: usize,
+	/// The lookups that failed, which are tried again by the next pass
+	pub failed: Vec<ChunkError>,
+}
+
+/// Fills in the details of saved users from their viewer cards. Users with
+/// details are skipped, so the sink doubles as a cache, and accounts that
+/// no longer exist are remembered so that later passes skip them too.
+pub struct UserEnricher {
+	users: EnrichUsers,
+	missing: HashSet<i64>,
+}
+
+impl UserEnricher {
+	#[must_use]
+	pub fn new(users: EnrichUsers) -> Self {
+		Self {
+			users,
+			missing: HashSet::new(),
+		}
+	}
+
+	/// Looks up the saved users without details, `concurrency` at a time.
+	/// A failed lookup does not stop the pass, and is returned with the number of saved users.
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn enrich<S: UserSink + ?Sized>(
+		&mut self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		concurrency: usize,
+	) -> Result<Enriched, S::Error> {
+		let mut after = i64::MIN;
+		let mut enriched = Enriched::default();
+
+		loop {
+			let page = sink
+				.users_without_details(self.users, after, PAGE_SIZE)
+				.await?;
+			let Some(&(last, _)) = page.last() else {
+				break;
+			};
+			let missing = &self.missing;
+			let lookups = page.iter().filter(|(id, _)| !missing.contains(id)).map(
+				|(id, username)| async move {
+					(*id, retry.run(|| User::from_id(gql, *id, username)).await)
+				},
+			);
+			let lookups = futures::stream::iter(lookups)
+				.buffer_unordered(concurrency)
+				.collect::<Vec<_>>()
+				.await;
+			let mut users = Vec::with_capacity(lookups.len());
+
+			for (id, user) in lookups {
+				match user {
+					Ok(Some(user)) => users.push(user),
+					Ok(None) => {
+						self.missing.insert(id);
+					}
+					Err(e) => enriched.failed.push(e),
+				}
+			}
+
+			sink.save_user_details(&users).await?;
+			enriched.count += users.len();
+
+			if page.len() < PAGE_SIZE {
+				break;
+			}
+
+			after = last;
+		}
+
+		Ok(enriched)
+	}
+}
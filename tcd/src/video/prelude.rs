@@ -1,33 +1,63 @@
 use std::{
-	collections::HashMap,
-	io::{BufWriter, Write},
-	pin::Pin,
+	collections::{HashSet, VecDeque},
+	io::{self, BufWriter, Write},
+	ops::RangeInclusive,
 	sync::Mutex,
 };
 
 use async_stream::try_stream;
 use async_trait::async_trait;
-use chrono::{DateTime, FixedOffset};
-use futures::{join, stream::BoxStream, Stream, StreamExt};
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::{
+	stream::{BoxStream, FuturesUnordered},
+	Future, FutureExt, StreamExt,
+};
+#[cfg(feature = "postgres")]
 use prisma_client_rust::QueryError;
 use serde::Serialize;
 
 use crate::{
+	checkpoint::{resume_from, Checkpoint, CheckpointStore},
 	gql::{
-		prelude::{Chunk, ChunkError, Format, Paginate, PaginateFilter, Save, WriteChunk},
+		client::GqlClient,
+		prelude::{Chunk, ChunkError, Format, Paginate, PaginateFilter, StartAt, WriteChunk},
 		request::{
-			GqlRequest, GqlRequestExtensions, GqlRequestPersistedQuery,
 			GqlVideoCommentsByCursorVariables, GqlVideoCommentsByOffsetVariables,
-			GqlVideoMetadataVariables,
+			GqlVideoMetadataVariables, Operation,
 		},
+		retry::RetryPolicy,
 		structs::{
-			GqlComment, GqlEdge, GqlEdgeContainer, GqlResponse, GqlVideo, GqlVideoContentResponse,
-			GqlVideoMetadataResponse,
+			GqlComment, GqlCommentMessageFragment, GqlCommenter, GqlEdge, GqlEdgeContainer,
+			GqlResponse, GqlVideo, GqlVideoContentResponse, GqlVideoMetadataResponse,
+			MessageDetails,
 		},
 	},
+	sink::{save_page, Sink, StreamError, StreamSink},
+};
+#[cfg(feature = "postgres")]
+use crate::{
+	gql::prelude::Save,
 	prisma::{self, PrismaClient},
+	sink::{PrismaError, PrismaSink},
 };
 
+/// The number of pages of comments saved between checkpoints
+const CHECKPOINT_INTERVAL: usize = 5;
+
+/// The number of pages a part of a video downloaded by [`Video::paginate_segments`]
+/// is allowed to get ahead of the part being iterated
+const PAGES_AHEAD: usize = 4;
+
+/// A stream of the pages of comments of a video
+type Pages<'a> = BoxStream<'a, Result<GqlEdgeContainer<GqlComment>, ChunkError>>;
+
+/// The offsets of every comment in a video
+pub const WHOLE_VIDEO: RangeInclusive<u32> = 0..=u32::MAX;
+
+/// The header of CSV output, with a column for each field of a row
+pub const CSV_HEADER: &str =
+	"channel,video_id,comment_id,commenter,created_at,offset,text,kind,bits,inferred";
+
 /// A video on Twitch
 #[derive(Clone, Debug, PartialEq)]
 pub struct Video {
@@ -37,8 +67,14 @@ pub struct Video {
 	pub author_id: i64,
 	pub cursor: Option<String>,
 	pub created_at: DateTime<FixedOffset>,
+	/// The length of the video in seconds
+	pub length: u32,
 	pub thumbnail_url: String,
 	pub thumbnail: Option<Vec<u8>>,
+	/// The name of the game or category the video was streamed under
+	pub game: Option<String>,
+	pub view_count: Option<i64>,
+	pub language: Option<String>,
 }
 
 impl Video {
@@ -59,6 +95,310 @@ impl Video {
 		Ok(self.thumbnail.as_ref())
 	}
 
+	/// Gets the comments for the video starting at an offset, in seconds
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn chunk_by_offset(
+		&self,
+		gql: &GqlClient,
+		offset: u32,
+	) -> Result<GqlEdgeContainer<GqlComment>, ChunkError> {
+		let body: GqlResponse<GqlVideoContentResponse> = gql
+			.request(
+				Operation::VideoCommentsByOffsetOrCursor,
+				GqlVideoCommentsByOffsetVariables {
+					video_id: self.id,
+					offset: i64::from(offset),
+				},
+			)
+			.await
+			.map_err(|e| {
+				ChunkError::from_response(
+					e,
+					Operation::VideoCommentsByOffsetOrCursor,
+					self.id.to_string(),
+				)
+			})?;
+
+		if let Some(video) = body.data.video {
+			Ok(video.comments)
+		} else {
+			Err(ChunkError::DataMissing {
+				operation: Operation::VideoCommentsByOffsetOrCursor.name(),
+				id: self.id,
+			})
+		}
+	}
+
+	/// Gets the metadata for a video
+	async fn metadata(
+		gql: &GqlClient,
+		id: i64,
+	) -> Result<GqlResponse<GqlVideoMetadataResponse>, ChunkError> {
+		gql.request(
+			Operation::VideoMetadata,
+			GqlVideoMetadataVariables {
+				username: "",
+				video_id: &id,
+			},
+		)
+		.await
+		.map_err(|e| ChunkError::from_response(e, Operation::VideoMetadata, id.to_string()))
+	}
+
+	/// Iterates the comments sent between two offsets into the video, in seconds,
+	/// starting at the first offset and stopping once a page passes the second
+	#[must_use]
+	pub fn paginate_range<'a>(
+		&'a self,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+		range: RangeInclusive<u32>,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<GqlComment>, ChunkError>> {
+		let start = StartAt::Offset(*range.start());
+
+		self.paginate_range_from(gql, retry, start, range)
+	}
+
+	fn paginate_range_from<'a>(
+		&'a self,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+		start: StartAt,
+		range: RangeInclusive<u32>,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<GqlComment>, ChunkError>> {
+		Box::pin(try_stream! {
+			let mut pages = self.paginate_from(gql, retry, start);
+
+			while let Some(page) = pages.next().await {
+				let mut page = page?;
+				let past_end = page
+					.edges
+					.last()
+					.is_none_or(|edge| edge.node.offset > *range.end());
+
+				page.edges.retain(|edge| range.contains(&edge.node.offset));
+
+				yield page;
+
+				if past_end {
+					break;
+				}
+			}
+		})
+	}
+
+	/// Iterates the comments sent between two offsets into the video, splitting them
+	/// into `segments` parts of the video that are downloaded at once.
+	/// The parts are merged back in order, without comments repeated at their boundaries.
+	#[must_use]
+	pub fn paginate_segments<'a>(
+		&'a self,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+		range: RangeInclusive<u32>,
+		segments: usize,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<GqlComment>, ChunkError>> {
+		let start = *range.start();
+		let end = (*range.end()).min(self.length).max(start);
+		let span = u64::from(end - start);
+		let segments = u64::try_from(segments)
+			.unwrap_or(u64::MAX)
+			.clamp(1, span.max(1));
+		// Each part starts at an even share of the video, and the last one
+		// continues to the end of the range in case the length is out of date
+		let bound = move |part: u64| start + u32::try_from(span * part / segments).unwrap_or(end);
+		let parts = (0..segments).map(move |part| {
+			if part + 1 == segments {
+				bound(part)..=*range.end()
+			} else {
+				bound(part)..=bound(part + 1) - 1
+			}
+		});
+
+		// Every part is downloaded at once, each waiting for its next page
+		let mut pending = parts
+			.enumerate()
+			.map(|(part, range)| next_page(part, self.paginate_range(gql, retry, range)))
+			.collect::<FuturesUnordered<_>>();
+
+		Box::pin(try_stream! {
+			// The pages of the parts after the one being yielded wait until it has ended,
+			// and a part stops downloading once it is `PAGES_AHEAD` pages ahead
+			let mut waiting = (0..pending.len()).map(|_| VecDeque::new()).collect::<Vec<_>>();
+			let mut paused = (0..waiting.len()).map(|_| None).collect::<Vec<_>>();
+			let mut ended = vec![false; waiting.len()];
+			let mut current = 0;
+			let mut boundary = HashSet::new();
+
+			while let Some((part, page, stream)) = pending.next().await {
+				match page {
+					Some(page) => {
+						waiting[part].push_back(page);
+
+						if part == current || waiting[part].len() < PAGES_AHEAD {
+							pending.push(next_page(part, stream));
+						} else {
+							paused[part] = Some(stream);
+						}
+					}
+					None => ended[part] = true,
+				}
+
+				while current < waiting.len() {
+					while let Some(page) = waiting[current].pop_front() {
+						let mut page = page?;
+
+						page.edges.retain(|edge| !boundary.contains(&edge.node.id));
+
+						if !page.edges.is_empty() {
+							boundary = page.edges.iter().map(|edge| edge.node.id.clone()).collect();
+						}
+
+						yield page;
+					}
+
+					if !ended[current] {
+						break;
+					}
+
+					current += 1;
+
+					if let Some(stream) = paused.get_mut(current).and_then(Option::take) {
+						pending.push(next_page(current, stream));
+					}
+				}
+			}
+		})
+	}
+
+	/// Saves the comments for the video to a sink, page by page.
+	/// Checkpoints are saved every few pages, once the sink has been flushed.
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn write_to_sink<S: Sink + ?Sized>(
+		self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		checkpoints: Option<&dyn CheckpointStore>,
+		verbose: bool,
+	) -> Result<(), S::Error> {
+		self.write_range_to_sink(gql, retry, sink, WHOLE_VIDEO, checkpoints, verbose)
+			.await
+	}
+
+	/// Saves the comments sent between two offsets into the video to a sink.
+	/// Checkpoints are only used for the [`WHOLE_VIDEO`], so that a partial
+	/// download never marks the video as completed.
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn write_range_to_sink<S: Sink + ?Sized>(
+		self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		range: RangeInclusive<u32>,
+		checkpoints: Option<&dyn CheckpointStore>,
+		verbose: bool,
+	) -> Result<(), S::Error> {
+		let checkpoints = checkpoints.filter(|_| range == WHOLE_VIDEO);
+		let start = match resume_from(checkpoints, self.id).await? {
+			Some(StartAt::Beginning) => StartAt::Offset(*range.start()),
+			Some(start) => start,
+			None => return Ok(()),
+		};
+
+		let mut chunks = self
+			.paginate_range_from(gql, retry, start, range)
+			.chunks(CHECKPOINT_INTERVAL);
+
+		while let Some(chunk) = chunks.next().await {
+			// Nothing from the group is saved unless every page was downloaded
+			let chunk = chunk.into_iter().collect::<Result<Vec<_>, _>>()?;
+			let position = chunk
+				.iter()
+				.rev()
+				.find_map(|c| c.edges.last())
+				.map(|edge| (edge.cursor.clone(), edge.node.offset));
+
+			for page in chunk.iter().filter(|page| !page.edges.is_empty()) {
+				save_page(sink, &self, &page.edges, verbose).await?;
+			}
+
+			if let (Some(checkpoints), Some((cursor, offset))) = (checkpoints, position) {
+				// Make sure the comments are saved before the checkpoint is
+				sink.flush().await?;
+				checkpoints
+					.save(&Checkpoint {
+						video_id: self.id,
+						cursor,
+						offset,
+						completed: false,
+					})
+					.await?;
+			}
+		}
+
+		if let Some(checkpoints) = checkpoints {
+			sink.flush().await?;
+			checkpoints.complete(self.id).await?;
+		}
+
+		Ok(())
+	}
+
+	/// Saves the comments sent between two offsets into the video to a sink,
+	/// downloading `segments` parts of the video at once. No checkpoints are saved,
+	/// since the parts are not downloaded in order.
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn write_segments_to_sink<S: Sink + ?Sized>(
+		self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		range: RangeInclusive<u32>,
+		segments: usize,
+		verbose: bool,
+	) -> Result<(), S::Error> {
+		let mut pages = self.paginate_segments(gql, retry, range, segments);
+
+		while let Some(page) = pages.next().await {
+			let page = page?;
+
+			if !page.edges.is_empty() {
+				save_page(sink, &self, &page.edges, verbose).await?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Saves the video and its author to a sink, so that its comments can reference them
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn save_to<S: Sink + ?Sized>(&self, sink: &S) -> Result<(), S::Error> {
+		sink.save_users(
+			&[&GqlCommenter {
+				id: self.author_id,
+				username: self.author.clone(),
+				display_name: self.author.clone(),
+			}],
+			Utc::now().into(),
+		)
+		.await?;
+		sink.save_video(self).await
+	}
+
+	/// The columns of the video besides its id, author and creation date
+	#[cfg(feature = "postgres")]
+	pub(crate) fn prisma_params(&self) -> Vec<prisma::video::SetParam> {
+		vec![
+			prisma::video::title::set(Some(self.title.clone())),
+			prisma::video::length::set(Some(i32::try_from(self.length).unwrap_or(i32::MAX))),
+			prisma::video::thumbnail_url::set(Some(self.thumbnail_url.clone())),
+			prisma::video::game::set(self.game.clone()),
+			prisma::video::view_count::set(self.view_count),
+			prisma::video::language::set(self.language.clone()),
+		]
+	}
+
 	#[must_use]
 	pub fn clone_without_thumbnail(&self) -> Self {
 		Self {
@@ -68,12 +408,17 @@ impl Video {
 			author: self.author.clone(),
 			cursor: self.cursor.clone(),
 			created_at: self.created_at,
+			length: self.length,
 			thumbnail_url: self.thumbnail_url.clone(),
 			thumbnail: None,
+			game: self.game.clone(),
+			view_count: self.view_count,
+			language: self.language.clone(),
 		}
 	}
 }
 
+#[cfg(feature = "postgres")]
 #[async_trait]
 impl Save for Video {
 	/// Saves the video to the database
@@ -84,7 +429,7 @@ impl Save for Video {
 				self.id,
 				prisma::user::UniqueWhereParam::IdEquals(self.author_id),
 				self.created_at,
-				vec![],
+				self.prisma_params(),
 			)
 			.exec()
 			.await?;
@@ -103,8 +448,12 @@ impl From<GqlEdge<GqlVideo>> for Video {
 			author_id: video.node.user.id,
 			cursor: video.cursor,
 			created_at: video.node.created_at,
+			length: video.node.length,
 			thumbnail_url: video.node.thumbnail_url,
 			thumbnail: None,
+			game: video.node.game.map(|game| game.name),
+			view_count: video.node.view_count,
+			language: video.node.language,
 		}
 	}
 }
@@ -119,8 +468,12 @@ impl From<GqlVideo> for Video {
 			author_id: video.user.id,
 			cursor: None,
 			created_at: video.created_at,
+			length: video.length,
 			thumbnail_url: video.thumbnail_url,
 			thumbnail: None,
+			game: video.game.map(|game| game.name),
+			view_count: video.view_count,
+			language: video.language,
 		}
 	}
 }
@@ -130,101 +483,71 @@ impl Chunk<GqlEdgeContainer<GqlComment>> for Video {
 	/// Gets the comments for the video from a cursor
 	async fn chunk_by_cursor<'a, S: Into<&'a str> + Send>(
 		&self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
 		cursor: S,
 	) -> Result<GqlEdgeContainer<GqlComment>, ChunkError> {
-		let response = http
-			.post("https://gql.twitch.tv/gql")
-			.json(&GqlRequest {
-				operation_name: "VideoCommentsByOffsetOrCursor",
-				variables: GqlVideoCommentsByCursorVariables {
+		let body: GqlResponse<GqlVideoContentResponse> = gql
+			.request(
+				Operation::VideoCommentsByOffsetOrCursor,
+				GqlVideoCommentsByCursorVariables {
 					video_id: self.id,
 					cursor: cursor.into(),
 				},
-				extensions: GqlRequestExtensions {
-					persisted_query: GqlRequestPersistedQuery {
-						version: 1,
-						sha256_hash:
-							"b70a3591ff0f4e0313d126c6a1502d79a1c02baebb288227c582044aa76adf6a",
-					},
-				},
-			})
-			.send()
+			)
 			.await
-			.map_err(|_| ChunkError::Reqwest)?;
-
-		let body: GqlResponse<GqlVideoContentResponse> =
-			response.json().await.map_err(|_| ChunkError::Reqwest)?;
+			.map_err(|e| {
+				ChunkError::from_response(
+					e,
+					Operation::VideoCommentsByOffsetOrCursor,
+					self.id.to_string(),
+				)
+			})?;
 
 		if let Some(video) = body.data.video {
 			Ok(video.comments)
 		} else {
-			Err(ChunkError::DataMissing)
+			Err(ChunkError::DataMissing {
+				operation: Operation::VideoCommentsByOffsetOrCursor.name(),
+				id: self.id,
+			})
 		}
 	}
 
 	/// Gets the first comments for the video
 	async fn first_chunk(
 		&self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
 	) -> Result<GqlEdgeContainer<GqlComment>, ChunkError> {
-		let response = http
-			.post("https://gql.twitch.tv/gql")
-			.json(&GqlRequest {
-				operation_name: "VideoCommentsByOffsetOrCursor",
-				variables: GqlVideoCommentsByOffsetVariables {
-					video_id: self.id,
-					offset: 0,
-				},
-				extensions: GqlRequestExtensions {
-					persisted_query: GqlRequestPersistedQuery {
-						version: 1,
-						sha256_hash:
-							"b70a3591ff0f4e0313d126c6a1502d79a1c02baebb288227c582044aa76adf6a",
-					},
-				},
-			})
-			.send()
-			.await
-			.map_err(|_| ChunkError::Reqwest)?;
-
-		let body: GqlResponse<GqlVideoContentResponse> =
-			response.json().await.map_err(|_| ChunkError::Reqwest)?;
-
-		if let Some(video) = body.data.video {
-			Ok(video.comments)
-		} else {
-			Err(ChunkError::DataMissing)
-		}
+		self.chunk_by_offset(gql, 0).await
 	}
 }
 
 impl Paginate<GqlComment> for Video {
-	/// Iterates the comments for a video
-	fn paginate<'a>(
+	/// Iterates the comments for a video, starting at `start`
+	fn paginate_from<'a>(
 		&'a self,
-		http: &'a reqwest::Client,
-	) -> Pin<Box<dyn Stream<Item = Result<GqlEdgeContainer<GqlComment>, ChunkError>> + 'a + Send>> {
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
+		start: StartAt,
+	) -> BoxStream<'a, Result<GqlEdgeContainer<GqlComment>, ChunkError>> {
 		Box::pin(try_stream! {
-			let mut cursor: Option<String> = None;
+			let mut data = match &start {
+				StartAt::Beginning => retry.run(|| self.first_chunk(gql)).await?,
+				StartAt::Cursor(cursor) => retry.run(|| self.chunk_by_cursor(gql, cursor.as_str())).await?,
+				StartAt::Offset(offset) => retry.run(|| self.chunk_by_offset(gql, *offset)).await?,
+			};
 
 			loop {
-				let data = match cursor {
-					Some(cursor) => self.chunk_by_cursor(http, cursor.as_str()).await?,
-					None => self.first_chunk(http).await?,
-				};
-
 				let has_next = data.page_info.has_next_page;
-
-				cursor = match data.edges.last() {
-					Some(edge) => edge.cursor.clone(),
-					None => None,
-				};
+				let cursor = data.edges.last().and_then(|edge| edge.cursor.clone());
 
 				yield data;
 
-				if !has_next {
-					break;
+				match cursor {
+					Some(cursor) if has_next => {
+						data = retry.run(|| self.chunk_by_cursor(gql, cursor.as_str())).await?;
+					}
+					_ => break,
 				}
 			}
 		})
@@ -234,133 +557,36 @@ impl Paginate<GqlComment> for Video {
 #[async_trait]
 impl WriteChunk<GqlComment> for Video {
 	/// Saves the comments for a video to the database
+	#[cfg(feature = "postgres")]
 	async fn write_to_pg(
 		self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
 		client: &PrismaClient,
+		checkpoints: Option<&dyn CheckpointStore>,
 		verbose: bool,
-	) -> Result<(), ChunkError> {
-		let mut comment_chunks = self.paginate(http);
-		let video_id = self.id;
-
-		while let Some(Ok(chunk)) = comment_chunks.next().await {
-			let mut comments: Vec<(
-				String,
-				i64,
-				i64,
-				String,
-				::prisma_client_rust::chrono::DateTime<::prisma_client_rust::chrono::FixedOffset>,
-				Vec<prisma::comment::SetParam>,
-			)> = vec![];
-			let mut users: HashMap<i64, (i64, String, Vec<prisma::user::SetParam>)> =
-				HashMap::new();
-
-			for comment in chunk.edges {
-				let commenter = match comment.node.commenter {
-					Some(commenter) => commenter,
-					None => continue,
-				};
-
-				users.entry(commenter.id).or_insert_with(|| {
-					prisma::user::create_unchecked(commenter.id, commenter.username, vec![])
-				});
-
-				comments.push(prisma::comment::create_unchecked(
-					comment.node.id,
-					commenter.id,
-					video_id,
-					comment
-						.node
-						.message
-						.fragments
-						.into_iter()
-						.map(|f| f.text)
-						.collect::<String>(),
-					comment.node.created_at,
-					vec![],
-				));
-			}
-
-			let (users, comments) = join!(
-				client
-					.user()
-					.create_many(users.into_values().into_iter().collect())
-					.skip_duplicates()
-					.exec(),
-				client
-					.comment()
-					.create_many(comments)
-					.skip_duplicates()
-					.exec()
-			);
-
-			if verbose {
-				let users = users.unwrap_or(0);
-				let comments = comments.unwrap_or(0);
-
-				if users != 0 || comments != 0 {
-					println!("Saved {users} users, {comments} comments");
-				}
-			}
-		}
-
-		Ok(())
+	) -> Result<(), PrismaError> {
+		self.write_to_sink(gql, retry, &PrismaSink::new(client), checkpoints, verbose)
+			.await
 	}
 
+	/// Writes the comments for a video to a stream
 	async fn write_to_stream(
 		self,
-		http: &reqwest::Client,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
 		stream: &Mutex<BufWriter<impl Write + Send>>,
 		format: &Format,
-	) -> Result<(), ChunkError> {
-		let join_str = "\n";
-
-		let mut chunks = self
-			.paginate(http)
-			.map(|c| {
-				c.map(|c| {
-					c.edges
-						.into_iter()
-						.filter_map(|c| {
-							if let Some(commenter) = c.node.commenter {
-								Some(format_data(
-									format,
-									&self.author,
-									self.id,
-									&c.node.id,
-									&commenter.username,
-									c.node.created_at,
-									&c.node
-										.message
-										.fragments
-										.into_iter()
-										.map(|f| f.text)
-										.collect::<String>(),
-								))
-							} else {
-								None
-							}
-						})
-						.intersperse(join_str.to_string())
-						.collect::<String>()
-				})
-			})
-			.chunks(5);
-
-		while let Some(chunk) = chunks.next().await {
-			let chunk = chunk.into_iter().collect::<Result<Vec<_>, _>>()?;
-			let mut stream = stream.lock().unwrap();
-
-			stream
-				.write(chunk.join(join_str).as_bytes())
-				.map_err(|_| ChunkError::Io)?;
-
-			stream
-				.write(join_str.as_bytes())
-				.map_err(|_| ChunkError::Io)?;
-		}
-
-		Ok(())
+		checkpoints: Option<&dyn CheckpointStore>,
+	) -> Result<(), StreamError> {
+		self.write_to_sink(
+			gql,
+			retry,
+			&StreamSink::new(stream, format),
+			checkpoints,
+			false,
+		)
+		.await
 	}
 }
 
@@ -370,71 +596,178 @@ pub struct CommentEntry<'a> {
 	pub video_id: i64,
 	pub comment_id: &'a str,
 	pub commenter: &'a str,
+	pub display_name: &'a str,
+	pub color: Option<&'a str>,
+	pub badges: Vec<BadgeEntry<'a>>,
 	pub created_at: DateTime<FixedOffset>,
+	/// The number of seconds into the video the comment was sent at
+	pub offset: u32,
 	pub text: &'a str,
+	pub fragments: Vec<FragmentEntry<'a>>,
+	/// The kind of the message, its bits and its sub metadata
+	#[serde(flatten)]
+	pub details: MessageDetails,
+	/// Whether a moderator deleted the message
+	pub deleted: bool,
 }
 
-fn format_data(
+/// A badge shown next to the commenter's name, such as `moderator`, `vip`,
+/// `broadcaster` or `subscriber` (with the tenure in months as the version)
+#[derive(Serialize)]
+pub struct BadgeEntry<'a> {
+	pub set_id: &'a str,
+	pub version: &'a str,
+}
+
+/// A piece of a message, which is either plain text or a single emote
+#[derive(Serialize)]
+pub struct FragmentEntry<'a> {
+	pub text: &'a str,
+	pub emote: Option<EmoteEntry<'a>>,
+}
+
+/// An emote and the range of characters it covers in the message
+#[derive(Serialize)]
+pub struct EmoteEntry<'a> {
+	pub id: &'a str,
+	/// The index of the first character of the emote
+	pub start: u32,
+	/// The index after the last character of the emote
+	pub end: u32,
+}
+
+/// Gets the fragments of a message, locating each emote by its character offsets
+pub(crate) fn fragment_entries(fragments: &[GqlCommentMessageFragment]) -> Vec<FragmentEntry<'_>> {
+	let mut start = 0u32;
+
+	fragments
+		.iter()
+		.map(|fragment| {
+			let end = start
+				.saturating_add(u32::try_from(fragment.text.chars().count()).unwrap_or(u32::MAX));
+			let entry = FragmentEntry {
+				text: &fragment.text,
+				emote: fragment.emote.as_ref().map(|emote| EmoteEntry {
+					id: &emote.emote_id,
+					start,
+					end,
+				}),
+			};
+
+			start = end;
+			entry
+		})
+		.collect()
+}
+
+// Only Parquet cannot be written to a stream
+#[cfg_attr(not(feature = "parquet"), allow(clippy::unnecessary_wraps))]
+pub(crate) fn format_data(
 	format: &Format,
-	author: &String,
+	author: &str,
 	video_id: i64,
-	comment_id: &String,
-	commenter: &String,
-	created_at: DateTime<FixedOffset>,
-	text: &String,
-) -> String {
-	match format {
+	comment: &GqlComment,
+	commenter: &GqlCommenter,
+) -> io::Result<String> {
+	let text = comment.message.text();
+
+	Ok(match format {
 		Format::JsonLines => serde_json::to_string(&CommentEntry {
 			channel: author,
 			video_id,
-			comment_id,
-			commenter,
-			created_at,
-			text,
+			comment_id: &comment.id,
+			commenter: &commenter.username,
+			display_name: &commenter.display_name,
+			color: comment.message.color.as_deref(),
+			badges: comment
+				.message
+				.badges
+				.iter()
+				.map(|badge| BadgeEntry {
+					set_id: &badge.set_id,
+					version: &badge.version,
+				})
+				.collect(),
+			created_at: comment.created_at,
+			offset: comment.offset,
+			text: &text,
+			fragments: fragment_entries(&comment.message.fragments),
+			details: comment.details(),
+			deleted: comment.deleted,
 		})
 		.unwrap(),
 		Format::Csv => {
-			format!(
-				"{},{},{},{},\"{}\",{:?}",
-				author, video_id, comment_id, commenter, created_at, text
-			)
+			let details = comment.details();
+
+			csv_record(&[
+				author,
+				&video_id.to_string(),
+				&comment.id,
+				&commenter.username,
+				&comment.created_at.to_string(),
+				&comment.offset.to_string(),
+				&text,
+				details.kind.name(),
+				&details.bits.to_string(),
+				&details.inferred.to_string(),
+			])
+		}
+		#[cfg(feature = "parquet")]
+		Format::Parquet => {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Parquet files must be written with a ParquetWriter",
+			))
 		}
+	})
+}
+
+/// Waits for the next page of a part of a video, handing back the rest of the part with it
+fn next_page(
+	part: usize,
+	pages: Pages<'_>,
+) -> impl Future<
+	Output = (
+		usize,
+		Option<Result<GqlEdgeContainer<GqlComment>, ChunkError>>,
+		Pages<'_>,
+	),
+> {
+	pages
+		.into_future()
+		.map(move |(page, pages)| (part, page, pages))
+}
+
+/// Formats a single CSV record without its terminator, quoting fields as needed
+fn csv_record(fields: &[&str]) -> String {
+	let mut writer = csv::WriterBuilder::new()
+		.has_headers(false)
+		.from_writer(vec![]);
+
+	writer.write_record(fields).unwrap();
+
+	let mut record = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+	if record.ends_with('\n') {
+		record.pop();
 	}
+
+	record
 }
 
 impl PaginateFilter<GqlVideo> for Video {
 	// Gets all videos for the channel that are in the given ids
 	fn paginate_filter<'a>(
-		http: &'a reqwest::Client,
+		gql: &'a GqlClient,
+		retry: &'a RetryPolicy,
 		ids: &'a [i64],
 	) -> BoxStream<'a, Result<GqlVideo, ChunkError>> {
 		Box::pin(try_stream! {
 			for id in ids {
-				let response = http
-					.post("https://gql.twitch.tv/gql")
-					.json(&GqlRequest {
-						operation_name: "VideoMetadata",
-						variables: GqlVideoMetadataVariables {
-							username: "",
-							video_id: id,
-						},
-						extensions: GqlRequestExtensions {
-							persisted_query: GqlRequestPersistedQuery {
-								version: 1,
-								sha256_hash:
-									"49b5b8f268cdeb259d75b58dcb0c1a748e3b575003448a2333dc5cdafd49adad",
-							},
-						},
-					})
-					.send()
-					.await
-					.map_err(|_| ChunkError::Reqwest)?;
-
-				let body: GqlResponse<GqlVideoMetadataResponse> = response.json().await.map_err(|_| ChunkError::Reqwest)?;
+				let body: GqlResponse<GqlVideoMetadataResponse> = retry.run(|| Self::metadata(gql, *id)).await?;
 
-				match body.data.video {
-					Some(video) => yield video,
-					None => continue,
+				if let Some(video) = body.data.video {
+					yield video;
 				}
 			}
 		})
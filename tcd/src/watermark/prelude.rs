@@ -0,0 +1,514 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	io,
+	path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "postgres")]
+use crate::prisma::{self, PrismaClient};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStore;
+use crate::{
+	channel::VideoSort,
+	checkpoint::{CheckpointStore, StoreError},
+	gql::{client::GqlClient, prelude::PaginateFilter, retry::RetryPolicy},
+	sidecar::{sidecar_path, Keyed, SidecarFile},
+	sink::Sink,
+	video::Video,
+};
+
+/// The number of minutes after a video ends that it may still be recording,
+/// since the video of a stream keeps growing until the stream ends
+const GROWING_MINUTES: i64 = 10;
+
+/// The newest video downloaded from a channel
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watermark {
+	pub channel_id: i64,
+	pub video_id: i64,
+	pub created_at: DateTime<FixedOffset>,
+}
+
+impl Watermark {
+	/// Whether a video is newer than the watermark
+	#[must_use]
+	pub fn is_before(&self, video: &Video) -> bool {
+		(video.created_at, video.id) > (self.created_at, self.video_id)
+	}
+}
+
+/// A place to persist watermarks so that polling can continue after a restart,
+/// which is only done when the store is opened with `resume`
+#[async_trait]
+pub trait WatermarkStore: Send + Sync {
+	async fn load(&self, channel_id: i64) -> Result<Option<Watermark>, StoreError>;
+	async fn save(&self, watermark: &Watermark) -> Result<(), StoreError>;
+
+	/// Gets the videos of a channel that may still be recording, with their lengths in seconds
+	async fn load_growing(&self, channel_id: i64) -> Result<BTreeMap<i64, u32>, StoreError>;
+	/// Replaces the videos of a channel that may still be recording
+	async fn save_growing(
+		&self,
+		channel_id: i64,
+		growing: &BTreeMap<i64, u32>,
+	) -> Result<(), StoreError>;
+}
+
+impl Keyed for Watermark {
+	fn key(&self) -> i64 {
+		self.channel_id
+	}
+}
+
+/// The videos of a channel that may still be recording, as saved in a sidecar file
+#[derive(Clone, Serialize, Deserialize)]
+struct Growing {
+	channel_id: i64,
+	videos: BTreeMap<i64, u32>,
+}
+
+impl Keyed for Growing {
+	fn key(&self) -> i64 {
+		self.channel_id
+	}
+}
+
+/// Stores watermarks in a JSON Lines file next to the output file,
+/// and the videos that may still be recording in another one
+pub struct FileWatermarks {
+	watermarks: SidecarFile<Watermark>,
+	growing: SidecarFile<Growing>,
+}
+
+impl FileWatermarks {
+	/// Opens the watermark file at `path`, and the file of growing videos next to it
+	#[allow(clippy::missing_errors_doc)]
+	pub fn open<P: Into<PathBuf>>(path: P, resume: bool) -> io::Result<Self> {
+		let path = path.into();
+
+		Ok(Self {
+			growing: SidecarFile::open(sidecar_path(&path, "growing"), resume)?,
+			watermarks: SidecarFile::open(path, resume)?,
+		})
+	}
+
+	/// The path of the watermark file for an output file
+	#[must_use]
+	pub fn sidecar(output: &Path) -> PathBuf {
+		sidecar_path(output, "watermarks")
+	}
+}
+
+#[async_trait]
+impl WatermarkStore for FileWatermarks {
+	async fn load(&self, channel_id: i64) -> Result<Option<Watermark>, StoreError> {
+		Ok(self.watermarks.get(channel_id))
+	}
+
+	async fn save(&self, watermark: &Watermark) -> Result<(), StoreError> {
+		Ok(self.watermarks.save(watermark).await?)
+	}
+
+	async fn load_growing(&self, channel_id: i64) -> Result<BTreeMap<i64, u32>, StoreError> {
+		Ok(self
+			.growing
+			.get(channel_id)
+			.map(|growing| growing.videos)
+			.unwrap_or_default())
+	}
+
+	async fn save_growing(
+		&self,
+		channel_id: i64,
+		growing: &BTreeMap<i64, u32>,
+	) -> Result<(), StoreError> {
+		Ok(self
+			.growing
+			.save(&Growing {
+				channel_id,
+				videos: growing.clone(),
+			})
+			.await?)
+	}
+}
+
+/// Stores watermarks in the `Watermark` table
+#[cfg(feature = "postgres")]
+pub struct PrismaWatermarks<'a> {
+	client: &'a PrismaClient,
+	resume: bool,
+}
+
+#[cfg(feature = "postgres")]
+impl PrismaWatermarks<'_> {
+	/// Stores watermarks with `client`
+	#[must_use]
+	pub fn new(client: &PrismaClient, resume: bool) -> PrismaWatermarks<'_> {
+		PrismaWatermarks { client, resume }
+	}
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl WatermarkStore for PrismaWatermarks<'_> {
+	async fn load(&self, channel_id: i64) -> Result<Option<Watermark>, StoreError> {
+		if !self.resume {
+			return Ok(None);
+		}
+
+		let watermark = self
+			.client
+			.watermark()
+			.find_unique(prisma::watermark::UniqueWhereParam::ChannelIdEquals(
+				channel_id,
+			))
+			.exec()
+			.await?;
+
+		Ok(watermark.map(|w| Watermark {
+			channel_id: w.channel_id,
+			video_id: w.video_id,
+			created_at: w.created_at,
+		}))
+	}
+
+	async fn save(&self, watermark: &Watermark) -> Result<(), StoreError> {
+		self.client
+			.watermark()
+			.upsert(
+				prisma::watermark::UniqueWhereParam::ChannelIdEquals(watermark.channel_id),
+				prisma::watermark::create(
+					watermark.channel_id,
+					watermark.video_id,
+					watermark.created_at,
+					vec![],
+				),
+				vec![
+					prisma::watermark::video_id::set(watermark.video_id),
+					prisma::watermark::created_at::set(watermark.created_at),
+				],
+			)
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+
+	async fn load_growing(&self, channel_id: i64) -> Result<BTreeMap<i64, u32>, StoreError> {
+		if !self.resume {
+			return Ok(BTreeMap::new());
+		}
+
+		let videos = self
+			.client
+			.growing_video()
+			.find_many(vec![prisma::growing_video::channel_id::equals(channel_id)])
+			.exec()
+			.await?;
+
+		Ok(videos
+			.into_iter()
+			.map(|video| (video.video_id, u32::try_from(video.length).unwrap_or(0)))
+			.collect())
+	}
+
+	async fn save_growing(
+		&self,
+		channel_id: i64,
+		growing: &BTreeMap<i64, u32>,
+	) -> Result<(), StoreError> {
+		self.client
+			.growing_video()
+			.delete_many(vec![prisma::growing_video::channel_id::equals(channel_id)])
+			.exec()
+			.await?;
+		self.client
+			.growing_video()
+			.create_many(
+				growing
+					.iter()
+					.map(|(&video_id, &length)| {
+						prisma::growing_video::create_unchecked(
+							video_id,
+							channel_id,
+							i32::try_from(length).unwrap_or(i32::MAX),
+							vec![],
+						)
+					})
+					.collect(),
+			)
+			.exec()
+			.await?;
+
+		Ok(())
+	}
+}
+
+#[cfg(feature = "sqlite")]
+/// Stores watermarks in the `Watermark` table of a [`SqliteStore`]
+pub struct SqliteWatermarks<'a> {
+	store: &'a SqliteStore,
+	resume: bool,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteWatermarks<'_> {
+	/// Stores watermarks in `store`
+	#[must_use]
+	pub fn new(store: &SqliteStore, resume: bool) -> SqliteWatermarks<'_> {
+		SqliteWatermarks { store, resume }
+	}
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl WatermarkStore for SqliteWatermarks<'_> {
+	async fn load(&self, channel_id: i64) -> Result<Option<Watermark>, StoreError> {
+		if !self.resume {
+			return Ok(None);
+		}
+
+		Ok(self.store.load_watermark(channel_id).await?)
+	}
+
+	async fn save(&self, watermark: &Watermark) -> Result<(), StoreError> {
+		Ok(self.store.save_watermark(watermark).await?)
+	}
+
+	async fn load_growing(&self, channel_id: i64) -> Result<BTreeMap<i64, u32>, StoreError> {
+		if !self.resume {
+			return Ok(BTreeMap::new());
+		}
+
+		Ok(self.store.load_growing(channel_id).await?)
+	}
+
+	async fn save_growing(
+		&self,
+		channel_id: i64,
+		growing: &BTreeMap<i64, u32>,
+	) -> Result<(), StoreError> {
+		Ok(self.store.save_growing(channel_id, growing).await?)
+	}
+}
+
+/// Tracks the newest video downloaded from each channel between polls,
+/// and the videos that were still recording when their chat was downloaded
+pub struct WatermarkTracker<'a> {
+	store: Option<&'a dyn WatermarkStore>,
+	watermarks: HashMap<i64, Option<Watermark>>,
+	/// The length of each video that may still be recording in seconds, by channel
+	growing: HashMap<i64, BTreeMap<i64, u32>>,
+}
+
+impl WatermarkTracker<'_> {
+	/// Watermarks are kept in memory, and saved to `store` if there is one
+	#[must_use]
+	pub fn new(store: Option<&dyn WatermarkStore>) -> WatermarkTracker<'_> {
+		WatermarkTracker {
+			store,
+			watermarks: HashMap::new(),
+			growing: HashMap::new(),
+		}
+	}
+
+	/// Gets the watermark of a channel, loading it and the videos
+	/// that may still be recording from the store the first time
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn watermark(&mut self, channel_id: i64) -> Result<Option<&Watermark>, StoreError> {
+		if !self.watermarks.contains_key(&channel_id) {
+			let (watermark, growing) = match self.store {
+				Some(store) => (
+					store.load(channel_id).await?,
+					store.load_growing(channel_id).await?,
+				),
+				None => (None, BTreeMap::new()),
+			};
+
+			if !growing.is_empty() {
+				self.growing.entry(channel_id).or_default().extend(growing);
+			}
+
+			self.watermarks.insert(channel_id, watermark);
+		}
+
+		Ok(self.watermarks[&channel_id].as_ref())
+	}
+
+	/// Removes the videos of a channel that are not newer than its watermark,
+	/// returning whether every later page can be skipped too
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn retain_new(
+		&mut self,
+		channel_id: i64,
+		sort: VideoSort,
+		videos: &mut Vec<Video>,
+	) -> Result<bool, StoreError> {
+		let Some(watermark) = self.watermark(channel_id).await? else {
+			return Ok(false);
+		};
+		let len = videos.len();
+
+		videos.retain(|video| watermark.is_before(video));
+
+		// Videos sorted by time are newest first, so the rest are older too
+		Ok(sort == VideoSort::Time && videos.len() < len)
+	}
+
+	/// Moves the watermark of a channel to the newest of the downloaded videos that is
+	/// older than every video that failed to download, so that those are downloaded
+	/// again by the next poll, and starts watching the ones that may still be recording
+	#[allow(clippy::missing_errors_doc)]
+	pub async fn record(
+		&mut self,
+		channel_id: i64,
+		videos: &[Video],
+		failed: &[Video],
+	) -> Result<(), StoreError> {
+		let now: DateTime<FixedOffset> = Utc::now().into();
+		let current = self.watermark(channel_id).await?.cloned();
+		let recording: Vec<_> = videos
+			.iter()
+			.filter(|video| is_recording(video, now))
+			.map(|video| (video.id, video.length))
+			.collect();
+
+		if !recording.is_empty() {
+			self.growing
+				.entry(channel_id)
+				.or_default()
+				.extend(recording);
+			self.save_growing(channel_id).await?;
+		}
+
+		let oldest_failed = failed
+			.iter()
+			.map(|video| (video.created_at, video.id))
+			.min();
+		let Some(newest) = videos
+			.iter()
+			.filter(|video| {
+				oldest_failed.is_none_or(|oldest| (video.created_at, video.id) < oldest)
+			})
+			.max_by_key(|video| (video.created_at, video.id))
+		else {
+			return Ok(());
+		};
+
+		if current.is_some_and(|watermark| !watermark.is_before(newest)) {
+			return Ok(());
+		}
+
+		let watermark = Watermark {
+			channel_id,
+			video_id: newest.id,
+			created_at: newest.created_at,
+		};
+
+		if let Some(store) = self.store {
+			store.save(&watermark).await?;
+		}
+
+		self.watermarks.insert(channel_id, Some(watermark));
+
+		Ok(())
+	}
+
+	/// Downloads the chat sent since the last poll for the videos that were still
+	/// recording, continuing from their checkpoints. Each video is downloaded one
+	/// last time once its length stops changing, and is then no longer watched.
+	/// Returns the errors of the videos that failed, which are tried again next time.
+	#[must_use]
+	pub async fn recheck<S: Sink + ?Sized>(
+		&mut self,
+		gql: &GqlClient,
+		retry: &RetryPolicy,
+		sink: &S,
+		checkpoints: &dyn CheckpointStore,
+		verbose: bool,
+	) -> Vec<S::Error> {
+		let mut errors = Vec::new();
+
+		for (channel_id, videos) in self.growing.clone() {
+			let mut growing = videos.clone();
+
+			for (id, length) in videos {
+				match recheck_video(gql, retry, sink, checkpoints, id, verbose).await {
+					Ok(Some(latest)) if latest != length => {
+						growing.insert(id, latest);
+					}
+					// The video stopped growing, or was deleted
+					Ok(_) => {
+						growing.remove(&id);
+					}
+					Err(e) => errors.push(e),
+				}
+			}
+
+			self.growing.insert(channel_id, growing);
+
+			if let Err(e) = self.save_growing(channel_id).await {
+				errors.push(e.into());
+			}
+		}
+
+		self.growing.retain(|_, growing| !growing.is_empty());
+
+		errors
+	}
+
+	/// Saves the videos of a channel that may still be recording
+	async fn save_growing(&self, channel_id: i64) -> Result<(), StoreError> {
+		let Some(store) = self.store else {
+			return Ok(());
+		};
+
+		store
+			.save_growing(
+				channel_id,
+				self.growing.get(&channel_id).unwrap_or(&BTreeMap::new()),
+			)
+			.await
+	}
+}
+
+/// Downloads the chat of a video from its checkpoint,
+/// returning its length or `None` if it was deleted
+async fn recheck_video<S: Sink + ?Sized>(
+	gql: &GqlClient,
+	retry: &RetryPolicy,
+	sink: &S,
+	checkpoints: &dyn CheckpointStore,
+	id: i64,
+	verbose: bool,
+) -> Result<Option<u32>, S::Error> {
+	let Some(video) = Video::paginate_filter(gql, retry, &[id])
+		.next()
+		.await
+		.transpose()?
+	else {
+		return Ok(None);
+	};
+	let video = Video::from(video);
+	let length = video.length;
+
+	checkpoints.reopen(id).await?;
+	video
+		.write_to_sink(gql, retry, sink, Some(checkpoints), verbose)
+		.await?;
+
+	Ok(Some(length))
+}
+
+/// Whether a video ended recently enough that it may still be recording
+fn is_recording(video: &Video, now: DateTime<FixedOffset>) -> bool {
+	video.created_at
+		+ Duration::seconds(i64::from(video.length))
+		+ Duration::minutes(GROWING_MINUTES)
+		>= now
+}
@@ -1,32 +1,42 @@
-use tcd::channel::Channel;
-
-static CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
-
-fn get_client() -> reqwest::Client {
-	reqwest::Client::builder()
-		.user_agent("tcd")
-		.default_headers({
-			let mut headers = reqwest::header::HeaderMap::new();
-			headers.insert(
-				"Client-ID",
-				reqwest::header::HeaderValue::from_static(CLIENT_ID),
-			);
-			headers
-		})
-		.build()
-		.unwrap()
-}
+mod common;
+
+use common::{fast_retry, MockGql, Route};
+use futures::StreamExt;
+use serde_json::json;
+use tcd::{
+	channel::{BroadcastType, Channel, VideoSort},
+	gql::{prelude::PaginateMut, request::Operation},
+};
 
 #[tokio::test]
 async fn test_valid_channel() {
-	let client = get_client();
-	let channel = Channel::from_username(&client, "atrioc").await.unwrap();
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::PlayerTrackingContextQuery,
+			json!({ "channel": "atrioc" }),
+			"player_context",
+		),
+		Route::new(
+			Operation::ViewerCard,
+			json!({ "channelID": "23211159" }),
+			"viewer_card",
+		),
+	])
+	.await;
+
+	let channel = Channel::from_username(&server.client(), "atrioc")
+		.await
+		.unwrap();
 
 	assert_eq!(
 		Some(Channel {
 			id: 23211159,
 			username: "atrioc".to_string(),
 			last_video_id: None,
+			broadcast_type: BroadcastType::default(),
+			sort: VideoSort::default(),
+			since: None,
+			until: None,
 		}),
 		channel
 	);
@@ -34,8 +44,184 @@ async fn test_valid_channel() {
 
 #[tokio::test]
 async fn test_invalid_channel() {
-	let client = get_client();
-	let channel = Channel::from_username(&client, "_").await.unwrap();
+	let server = MockGql::start(vec![Route::new(
+		Operation::PlayerTrackingContextQuery,
+		json!({ "channel": "_" }),
+		"player_context_missing",
+	)])
+	.await;
+
+	let channel = Channel::from_username(&server.client(), "_").await.unwrap();
 
 	assert_eq!(None, channel);
+	assert_eq!(0, server.requests(Operation::ViewerCard));
+}
+
+#[tokio::test]
+async fn test_channel_videos_pagination() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+			"videos_page_1",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": "MTY2ODU5MDAyNQ==" }),
+			"videos_page_2",
+		),
+	])
+	.await;
+
+	let gql = server.client();
+	let retry = fast_retry();
+	let mut channel = Channel {
+		id: 23211159,
+		username: "atrioc".to_string(),
+		last_video_id: None,
+		broadcast_type: BroadcastType::default(),
+		sort: VideoSort::default(),
+		since: None,
+		until: None,
+	};
+
+	let ids = channel
+		.paginate_mut(&gql, &retry)
+		.flat_map(|chunk| futures::stream::iter(chunk.unwrap().edges))
+		.map(|edge| edge.node.id)
+		.collect::<Vec<_>>()
+		.await;
+
+	assert_eq!(vec![1649326959, 1648474855, 1647620781], ids);
+	assert_eq!(Some(1647620781), channel.last_video_id);
+	assert_eq!(2, server.requests(Operation::FilterableVideoTowerVideos));
+}
+
+#[tokio::test]
+async fn test_channel_videos_page_error() {
+	let server = MockGql::start(vec![Route::new(
+		Operation::FilterableVideoTowerVideos,
+		json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+		"videos_page_1",
+	)])
+	.await;
+
+	let gql = server.client();
+	let retry = fast_retry();
+	let mut channel = Channel {
+		id: 23211159,
+		username: "atrioc".to_string(),
+		last_video_id: None,
+		broadcast_type: BroadcastType::default(),
+		sort: VideoSort::default(),
+		since: None,
+		until: None,
+	};
+
+	let pages = channel.paginate_mut(&gql, &retry).collect::<Vec<_>>().await;
+
+	// The page that could not be downloaded ends the stream with its error
+	assert_eq!(2, pages.len());
+	assert!(pages[0].is_ok());
+	assert!(pages[1].is_err());
+}
+
+#[tokio::test]
+async fn test_channel_video_filters() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "broadcastType": "HIGHLIGHT", "videoSort": "VIEWS" }),
+			"videos_page_2",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "broadcastType": null, "videoSort": "TIME" }),
+			"videos_page_2",
+		),
+	])
+	.await;
+
+	let gql = server.client();
+	let retry = fast_retry();
+	let channel = Channel {
+		id: 23211159,
+		username: "atrioc".to_string(),
+		last_video_id: None,
+		broadcast_type: BroadcastType::default(),
+		sort: VideoSort::default(),
+		since: None,
+		until: None,
+	};
+
+	for mut channel in [
+		channel
+			.clone()
+			.with_broadcast_type(BroadcastType::Highlight)
+			.with_sort(VideoSort::Views),
+		channel.with_broadcast_type(BroadcastType::All),
+	] {
+		let ids = channel
+			.paginate_mut(&gql, &retry)
+			.flat_map(|chunk| futures::stream::iter(chunk.unwrap().edges))
+			.map(|edge| edge.node.id)
+			.collect::<Vec<_>>()
+			.await;
+
+		assert_eq!(vec![1647620781], ids);
+	}
+}
+
+#[tokio::test]
+async fn test_channel_date_range() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+			"videos_page_1",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": "MTY2ODU5MDAyNQ==" }),
+			"videos_page_2",
+		),
+	])
+	.await;
+
+	let gql = server.client();
+	let retry = fast_retry();
+	let date = |date| Some(chrono::DateTime::parse_from_rfc3339(date).unwrap());
+	let channel = Channel {
+		id: 23211159,
+		username: "atrioc".to_string(),
+		last_video_id: None,
+		broadcast_type: BroadcastType::default(),
+		sort: VideoSort::default(),
+		since: None,
+		until: None,
+	};
+
+	let mut since = channel
+		.clone()
+		.with_date_range(date("2022-11-05T00:00:00Z"), None);
+	let ids = since
+		.paginate_mut(&gql, &retry)
+		.flat_map(|chunk| futures::stream::iter(chunk.unwrap().edges))
+		.map(|edge| edge.node.id)
+		.collect::<Vec<_>>()
+		.await;
+
+	// The first page has a video older than `since`, so the second is never requested
+	assert_eq!(vec![1649326959], ids);
+	assert_eq!(1, server.requests(Operation::FilterableVideoTowerVideos));
+
+	let mut until = channel.with_date_range(None, date("2022-11-05T00:00:00Z"));
+	let ids = until
+		.paginate_mut(&gql, &retry)
+		.flat_map(|chunk| futures::stream::iter(chunk.unwrap().edges))
+		.map(|edge| edge.node.id)
+		.collect::<Vec<_>>()
+		.await;
+
+	assert_eq!(vec![1648474855, 1647620781], ids);
 }
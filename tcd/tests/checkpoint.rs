@@ -0,0 +1,88 @@
+use std::{io::Write, path::Path};
+
+use tcd::{
+	checkpoint::{Checkpoint, CheckpointStore, FileCheckpoints},
+	gql::prelude::StartAt,
+};
+
+#[tokio::test]
+async fn test_file_checkpoints_resume() {
+	let output = std::env::temp_dir().join(format!("tcd-checkpoint-{}.csv", std::process::id()));
+	let path = FileCheckpoints::sidecar(&output);
+
+	let checkpoints = FileCheckpoints::open(&path, false).unwrap();
+	let checkpoint = Checkpoint {
+		video_id: 1,
+		cursor: Some("eyJpZCI6IjEifQ==".to_string()),
+		offset: 120,
+		completed: false,
+	};
+
+	checkpoints.save(&checkpoint).await.unwrap();
+	checkpoints.complete(2).await.unwrap();
+
+	let resumed = FileCheckpoints::open(&path, true).unwrap();
+
+	assert_eq!(Some(checkpoint), resumed.load(1).await.unwrap());
+	assert!(resumed.load(2).await.unwrap().unwrap().completed);
+
+	let fresh = FileCheckpoints::open(&path, false).unwrap();
+
+	assert_eq!(None, fresh.load(1).await.unwrap());
+
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_file_checkpoints_compact() {
+	let path = std::env::temp_dir().join(format!("tcd-compact-{}.csv.state", std::process::id()));
+	let checkpoints = FileCheckpoints::open(&path, false).unwrap();
+
+	for offset in 0..1500 {
+		checkpoints
+			.save(&Checkpoint {
+				video_id: 1,
+				cursor: None,
+				offset,
+				completed: false,
+			})
+			.await
+			.unwrap();
+	}
+
+	// Only the saves since the file was last compacted are left in it
+	assert!(std::fs::read_to_string(&path).unwrap().lines().count() <= 1000);
+
+	// A checkpoint cut off by a crash is skipped
+	let mut file = std::fs::OpenOptions::new()
+		.append(true)
+		.open(&path)
+		.unwrap();
+	file.write_all(b"{\"video_id\":1,\"cur").unwrap();
+
+	let resumed = FileCheckpoints::open(&path, true).unwrap();
+
+	assert_eq!(1499, resumed.load(1).await.unwrap().unwrap().offset);
+
+	std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_checkpoint_start_at() {
+	let mut checkpoint = Checkpoint {
+		video_id: 1,
+		cursor: Some("cursor".to_string()),
+		offset: 60,
+		completed: false,
+	};
+
+	assert_eq!(StartAt::Cursor("cursor".to_string()), checkpoint.start_at());
+
+	checkpoint.cursor = None;
+
+	assert_eq!(StartAt::Offset(60), checkpoint.start_at());
+	assert_eq!(
+		Path::new("out.csv.state"),
+		FileCheckpoints::sidecar(Path::new("out.csv"))
+	);
+}
@@ -0,0 +1,52 @@
+use tcd::gql::{
+	client::{GqlClient, GqlConfig, ENDPOINT},
+	request::Operation,
+};
+
+#[test]
+fn test_default_hashes() {
+	let gql = GqlClient::new(reqwest::Client::new());
+
+	assert_eq!(ENDPOINT, gql.endpoint());
+	assert_eq!(
+		"a937f1d22e269e39a03b509f65a7490f9fc247d7f83d6ac1421523e3b68042cb",
+		gql.hash(Operation::FilterableVideoTowerVideos)
+	);
+}
+
+#[test]
+fn test_config_overrides() {
+	let path = std::env::temp_dir().join(format!("tcd-gql-{}.json", std::process::id()));
+
+	std::fs::write(
+		&path,
+		r#"{
+			"endpoint": "http://localhost:8080/gql",
+			"operations": { "FilterableVideoTower_Videos": "rotated" }
+		}"#,
+	)
+	.unwrap();
+
+	let config = GqlConfig::from_file(&path).unwrap();
+	let gql = GqlClient::new(reqwest::Client::new()).with_config(config);
+
+	assert_eq!("http://localhost:8080/gql", gql.endpoint());
+	assert_eq!("rotated", gql.hash(Operation::FilterableVideoTowerVideos));
+	assert_eq!(
+		Operation::ViewerCard.default_hash(),
+		gql.hash(Operation::ViewerCard)
+	);
+
+	std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_config_rejects_unknown_operations() {
+	let path = std::env::temp_dir().join(format!("tcd-gql-unknown-{}.json", std::process::id()));
+
+	std::fs::write(&path, r#"{ "operations": { "NotAnOperation": "hash" } }"#).unwrap();
+
+	assert!(GqlConfig::from_file(&path).is_err());
+
+	std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use std::{io::BufWriter, sync::Mutex};
+
+use common::{fast_retry, MockGql, Route};
+use futures::TryStreamExt;
+use serde_json::json;
+use tcd::{
+	channel::{BroadcastType, Channel, VideoSort},
+	clip::Clip,
+	gql::{prelude::Format, request::Operation},
+	sink::StreamSink,
+};
+
+const SLUG: &str = "AmazingCrepuscularSnailKappa-8fQ2xWm1TgRbYq7N";
+
+#[tokio::test]
+async fn test_clip_from_slug() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::ShareClipRenderStatus,
+			json!({ "slug": SLUG }),
+			"clip",
+		),
+		Route::new(Operation::ShareClipRenderStatus, json!({}), "clip_missing"),
+	])
+	.await;
+
+	let clip = Clip::from_slug(&server.client(), &fast_retry(), SLUG)
+		.await
+		.unwrap()
+		.unwrap();
+
+	assert_eq!(1427906386, clip.id);
+	assert_eq!("atrioc", clip.broadcaster);
+	assert_eq!(Some(1649326959), clip.video_id);
+	assert_eq!(Some(4..=9), clip.range());
+	assert_eq!(
+		None,
+		Clip::from_slug(&server.client(), &fast_retry(), "_")
+			.await
+			.unwrap()
+	);
+}
+
+#[tokio::test]
+async fn test_clip_from_slug_retry() {
+	let server = MockGql::start(vec![
+		Route::new(Operation::ShareClipRenderStatus, json!({}), "clip")
+			.status(503)
+			.times(2),
+		Route::new(Operation::ShareClipRenderStatus, json!({}), "clip"),
+	])
+	.await;
+
+	let clip = Clip::from_slug(&server.client(), &fast_retry(), SLUG)
+		.await
+		.unwrap();
+
+	assert!(clip.is_some());
+	assert_eq!(3, server.requests(Operation::ShareClipRenderStatus));
+}
+
+#[tokio::test]
+async fn test_channel_clips_pagination() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::ClipsCardsUser,
+			json!({ "login": "atrioc", "cursor": "MQ==" }),
+			"clips_page_2",
+		),
+		Route::new(
+			Operation::ClipsCardsUser,
+			json!({ "login": "atrioc", "cursor": null }),
+			"clips_page_1",
+		),
+	])
+	.await;
+	let channel = Channel {
+		id: 23211159,
+		username: "atrioc".to_string(),
+		last_video_id: None,
+		broadcast_type: BroadcastType::default(),
+		sort: VideoSort::default(),
+		since: None,
+		until: None,
+	};
+
+	let slugs = channel
+		.clips(&server.client(), &fast_retry())
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap()
+		.into_iter()
+		.flat_map(|page| page.edges)
+		.map(|edge| Clip::from(edge).slug)
+		.collect::<Vec<_>>();
+
+	assert_eq!(
+		vec![SLUG, "SpunkyHelplessPonyDatSheffy-Zx3nTqL5vWp8RcKa"],
+		slugs
+	);
+}
+
+#[tokio::test]
+async fn test_clip_chat_window() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::ShareClipRenderStatus,
+			json!({ "slug": SLUG }),
+			"clip",
+		),
+		Route::new(
+			Operation::VideoMetadata,
+			json!({ "videoID": "1649326959" }),
+			"video_metadata",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "contentOffsetSeconds": 4 }),
+			"comments_page_1",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+	])
+	.await;
+	let gql = server.client();
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+
+	Clip::from_slug(&gql, &fast_retry(), SLUG)
+		.await
+		.unwrap()
+		.unwrap()
+		.write_to_sink(
+			&gql,
+			&fast_retry(),
+			&StreamSink::new(&stream, &Format::JsonLines),
+			false,
+		)
+		.await
+		.unwrap();
+
+	let output = String::from_utf8(stream.into_inner().unwrap().into_inner().unwrap()).unwrap();
+	let comments = output
+		.lines()
+		.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+		.collect::<Vec<_>>();
+
+	// Only the comment at 8 seconds is inside the clip, since the one at 3 seconds
+	// is before it, the one at 12 seconds is after it and the one at 5 seconds has no commenter
+	assert_eq!(1, comments.len());
+	assert_eq!("linkus7", comments[0]["commenter"]);
+	assert_eq!(1649326959, comments[0]["video_id"]);
+	assert_eq!(2, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+}
@@ -0,0 +1,205 @@
+//! An in-process mock of the Twitch GraphQL API that serves the recorded
+//! responses in `tests/fixtures`, so tests never touch the network
+#![allow(dead_code)]
+
+use std::{
+	net::SocketAddr,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use serde_json::Value;
+use tcd::gql::{client::GqlClient, request::Operation, retry::RetryPolicy};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
+
+/// Reads a recorded response from `tests/fixtures`
+pub fn fixture(name: &str) -> String {
+	std::fs::read_to_string(format!(
+		"{}/tests/fixtures/{name}.json",
+		env!("CARGO_MANIFEST_DIR")
+	))
+	.unwrap()
+}
+
+/// A retry policy that does not slow the tests down
+pub fn fast_retry() -> RetryPolicy {
+	RetryPolicy {
+		base_delay: Duration::from_millis(1),
+		max_delay: Duration::from_millis(1),
+		..RetryPolicy::default()
+	}
+}
+
+/// A response served to requests for an operation whose variables
+/// contain every field in `variables`
+pub struct Route {
+	operation: Operation,
+	variables: Value,
+	status: u16,
+	body: String,
+	/// The number of times the route is served before it stops matching
+	times: Option<usize>,
+}
+
+impl Route {
+	pub fn new(operation: Operation, variables: Value, fixture_name: &str) -> Self {
+		Self {
+			operation,
+			variables,
+			status: 200,
+			body: fixture(fixture_name),
+			times: None,
+		}
+	}
+
+	pub fn status(self, status: u16) -> Self {
+		Self { status, ..self }
+	}
+
+	pub fn times(self, times: usize) -> Self {
+		Self {
+			times: Some(times),
+			..self
+		}
+	}
+
+	fn matches(&self, request: &Value) -> bool {
+		if request["operationName"] != self.operation.name() {
+			return false;
+		}
+
+		match &self.variables {
+			Value::Object(fields) => fields
+				.iter()
+				.all(|(key, value)| &request["variables"][key] == value),
+			_ => true,
+		}
+	}
+}
+
+struct State {
+	routes: Vec<Route>,
+	requests: Vec<Value>,
+}
+
+/// A mock GraphQL server listening on a random local port
+pub struct MockGql {
+	addr: SocketAddr,
+	state: Arc<Mutex<State>>,
+}
+
+impl MockGql {
+	/// Starts a server that answers with the first matching route,
+	/// or 404 if none match
+	pub async fn start(routes: Vec<Route>) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let state = Arc::new(Mutex::new(State {
+			routes,
+			requests: Vec::new(),
+		}));
+
+		tokio::spawn({
+			let state = Arc::clone(&state);
+
+			async move {
+				while let Ok((socket, _)) = listener.accept().await {
+					tokio::spawn(respond(socket, Arc::clone(&state)));
+				}
+			}
+		});
+
+		Self { addr, state }
+	}
+
+	/// A client that sends every request to this server
+	pub fn client(&self) -> GqlClient {
+		GqlClient::new(reqwest::Client::new()).with_endpoint(format!("http://{}/gql", self.addr))
+	}
+
+	/// The number of requests received for an operation
+	pub fn requests(&self, operation: Operation) -> usize {
+		self.state
+			.lock()
+			.unwrap()
+			.requests
+			.iter()
+			.filter(|request| request["operationName"] == operation.name())
+			.count()
+	}
+}
+
+async fn respond(mut socket: TcpStream, state: Arc<Mutex<State>>) {
+	let Some(request) = read_request(&mut socket).await else {
+		return;
+	};
+
+	let (status, body) = {
+		let mut state = state.lock().unwrap();
+		let route = state
+			.routes
+			.iter_mut()
+			.find(|route| route.times != Some(0) && route.matches(&request));
+
+		let response = match route {
+			Some(route) => {
+				route.times = route.times.map(|times| times - 1);
+
+				(route.status, route.body.clone())
+			}
+			None => (404, String::new()),
+		};
+
+		state.requests.push(request);
+		response
+	};
+
+	let response = format!(
+		"HTTP/1.1 {status} Mock\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+		body.len()
+	);
+
+	socket.write_all(response.as_bytes()).await.ok();
+}
+
+/// Reads the JSON body of a single HTTP request
+async fn read_request(socket: &mut TcpStream) -> Option<Value> {
+	let mut buffer = Vec::new();
+	let mut chunk = [0; 4096];
+
+	let (header_end, content_length) = loop {
+		let read = socket.read(&mut chunk).await.ok()?;
+
+		if read == 0 {
+			return None;
+		}
+
+		buffer.extend_from_slice(&chunk[..read]);
+
+		if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+			let headers = String::from_utf8_lossy(&buffer[..end]).to_lowercase();
+			let length = headers
+				.lines()
+				.find_map(|line| line.strip_prefix("content-length:"))
+				.and_then(|length| length.trim().parse::<usize>().ok())
+				.unwrap_or(0);
+
+			break (end + 4, length);
+		}
+	};
+
+	while buffer.len() < header_end + content_length {
+		let read = socket.read(&mut chunk).await.ok()?;
+
+		if read == 0 {
+			return None;
+		}
+
+		buffer.extend_from_slice(&chunk[..read]);
+	}
+
+	serde_json::from_slice(&buffer[header_end..header_end + content_length]).ok()
+}
@@ -0,0 +1,29 @@
+use std::time::{Duration, Instant};
+
+use tcd::gql::limiter::RateLimiter;
+
+#[tokio::test]
+async fn test_burst_is_immediate() {
+	let limiter = RateLimiter::new(1., 5);
+	let start = Instant::now();
+
+	for _ in 0..5 {
+		limiter.acquire().await;
+	}
+
+	assert!(start.elapsed() < Duration::from_millis(500));
+}
+
+#[test]
+fn test_adaptive_rate() {
+	let limiter = RateLimiter::new(8., 1);
+
+	limiter.on_rate_limited();
+	assert!((limiter.current_rate() - 4.).abs() < f64::EPSILON);
+
+	for _ in 0..100 {
+		limiter.on_success();
+	}
+
+	assert!((limiter.current_rate() - 8.).abs() < f64::EPSILON);
+}
@@ -0,0 +1,490 @@
+#![cfg(feature = "live")]
+
+mod common;
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use common::{fast_retry, MockGql, Route};
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use tcd::{
+	clip::Clip,
+	gql::{
+		request::Operation,
+		structs::{GqlComment, GqlCommenter, GqlEdge, MessageDetails, MessageKind},
+	},
+	live::{capture, Batches, ChatEvent, IrcMessage},
+	sink::{ClearChat, ClearMessage, ModerationSink, Sink, StreamError},
+	video::Video,
+};
+use tokio::net::TcpListener;
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+const PRIVMSG: &str = "@badges=subscriber/12,bits/100;color=#1E90FF;display-name=Linkus7;emotes=25:6-10;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;room-id=23211159;tmi-sent-ts=1667848812000;user-id=136054137 :linkus7!linkus7@linkus7.tmi.twitch.tv PRIVMSG #atrioc :hello Kappa \\o/";
+const USERNOTICE: &str = "@badges=;color=;display-name=xQc;emotes=;id=5d4e2a13-42ec-4d3c-9b88-c8a4b1f3d0a1;login=xqc;msg-id=resub;msg-param-cumulative-months=3;msg-param-sub-plan=1000;room-id=23211159;system-msg=xQc\\ssubscribed\\sfor\\s3\\smonths!;tmi-sent-ts=1667848815000;user-id=71092938 :tmi.twitch.tv USERNOTICE #atrioc";
+const CHEER: &str = "@badges=;bits=150;color=;display-name=xQc;emotes=;id=0f1e2d3c-4b5a-4978-8a6b-5c4d3e2f1a0b;room-id=23211159;tmi-sent-ts=1667848818000;user-id=71092938 :xqc!xqc@xqc.tmi.twitch.tv PRIVMSG #atrioc :Cheer100 Cheer50 nice";
+const CLEARMSG: &str = "@login=linkus7;room-id=23211159;target-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;tmi-sent-ts=1667848819000 :tmi.twitch.tv CLEARMSG #atrioc :hello Kappa \\o/";
+const CLEARCHAT: &str = "@ban-duration=600;room-id=23211159;target-user-id=136054137;tmi-sent-ts=1667848820000 :tmi.twitch.tv CLEARCHAT #atrioc :linkus7";
+
+/// A sink that keeps everything in memory
+#[derive(Default)]
+struct MemorySink {
+	users: Mutex<Vec<String>>,
+	videos: Mutex<Vec<i64>>,
+	comments: Mutex<Vec<String>>,
+	offsets: Mutex<Vec<u32>>,
+	/// The number of comments saved before each event
+	clears: Mutex<Vec<(usize, ClearChat)>>,
+	/// The ids of the comments saved as deleted or deleted after they were saved
+	deleted: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl Sink for MemorySink {
+	type Error = StreamError;
+
+	async fn save_users(
+		&self,
+		users: &[&GqlCommenter],
+		_seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, StreamError> {
+		self.users
+			.lock()
+			.unwrap()
+			.extend(users.iter().map(|user| user.username.clone()));
+
+		Ok(users.len())
+	}
+
+	async fn save_video(&self, video: &Video) -> Result<(), StreamError> {
+		self.videos.lock().unwrap().push(video.id);
+
+		Ok(())
+	}
+
+	async fn save_clip(&self, _clip: &Clip) -> Result<(), StreamError> {
+		Ok(())
+	}
+
+	async fn save_comments(
+		&self,
+		_video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, StreamError> {
+		self.comments
+			.lock()
+			.unwrap()
+			.extend(comments.iter().map(|comment| comment.node.message.text()));
+		self.offsets
+			.lock()
+			.unwrap()
+			.extend(comments.iter().map(|comment| comment.node.offset));
+		self.deleted.lock().unwrap().extend(
+			comments
+				.iter()
+				.filter(|comment| comment.node.deleted)
+				.map(|comment| comment.node.id.clone()),
+		);
+
+		Ok(comments.len())
+	}
+
+	async fn flush(&self) -> Result<(), StreamError> {
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl ModerationSink for MemorySink {
+	async fn clear_comments(&self, _video: &Video, clear: &ClearChat) -> Result<(), StreamError> {
+		let saved = self.comments.lock().unwrap().len();
+
+		self.clears.lock().unwrap().push((saved, clear.clone()));
+
+		Ok(())
+	}
+
+	async fn delete_comment(&self, delete: &ClearMessage) -> Result<(), StreamError> {
+		self.deleted.lock().unwrap().push(delete.id.clone());
+
+		Ok(())
+	}
+}
+
+#[test]
+fn test_parse_privmsg() {
+	let message = IrcMessage::parse(PRIVMSG).unwrap();
+
+	assert_eq!(message.command, "PRIVMSG");
+	assert_eq!(message.params, ["#atrioc", "hello Kappa \\o/"]);
+	assert_eq!(message.tags["display-name"], "Linkus7");
+
+	let Some(ChatEvent::Comment {
+		channel,
+		channel_id,
+		comment,
+	}) = ChatEvent::from_irc(&message)
+	else {
+		panic!("expected a comment");
+	};
+
+	assert_eq!(channel, "atrioc");
+	assert_eq!(channel_id, 23211159);
+	assert_eq!(comment.id, "b34ccfc7-4977-403a-8a94-33c6bac34fb8");
+	assert_eq!(comment.commenter.as_ref().unwrap().username, "linkus7");
+	assert_eq!(comment.commenter.as_ref().unwrap().id, 136054137);
+	assert_eq!(comment.message.color.as_deref(), Some("#1E90FF"));
+	assert_eq!(comment.message.badges.len(), 2);
+	assert_eq!(comment.message.text(), "hello Kappa \\o/");
+	assert_eq!(comment.created_at.to_rfc3339(), "2022-11-07T19:20:12+00:00");
+
+	let fragments = &comment.message.fragments;
+
+	assert_eq!(fragments.len(), 3);
+	assert_eq!(fragments[1].text, "Kappa");
+	assert_eq!(fragments[1].emote.as_ref().unwrap().emote_id, "25");
+}
+
+#[test]
+fn test_parse_usernotice_and_clearchat() {
+	let message = IrcMessage::parse(USERNOTICE).unwrap();
+	let Some(ChatEvent::Comment { comment, .. }) = ChatEvent::from_irc(&message) else {
+		panic!("expected a comment");
+	};
+
+	assert_eq!(comment.commenter.as_ref().unwrap().username, "xqc");
+	assert_eq!(comment.message.text(), "xQc subscribed for 3 months!");
+	assert!(comment.message.color.is_none());
+	assert!(comment.message.badges.is_empty());
+	assert_eq!(
+		comment.details(),
+		MessageDetails {
+			kind: MessageKind::Resub,
+			sub_tier: Some("1000".to_string()),
+			sub_months: Some(3),
+			..MessageDetails::default()
+		}
+	);
+
+	let message = IrcMessage::parse(CLEARCHAT).unwrap();
+	let Some(ChatEvent::Clear(clear)) = ChatEvent::from_irc(&message) else {
+		panic!("expected a clear chat");
+	};
+
+	assert_eq!(clear.channel, "atrioc");
+	assert_eq!(clear.target, Some((136054137, "linkus7".to_string())));
+	assert_eq!(clear.duration, Some(600));
+
+	assert!(ChatEvent::from_irc(&IrcMessage::parse("PING :tmi.twitch.tv").unwrap()).is_none());
+}
+
+#[test]
+fn test_parse_cheer_and_clearmsg() {
+	let message = IrcMessage::parse(CHEER).unwrap();
+	let Some(ChatEvent::Comment { comment, .. }) = ChatEvent::from_irc(&message) else {
+		panic!("expected a comment");
+	};
+
+	assert_eq!(comment.details().kind, MessageKind::Cheer);
+	assert_eq!(comment.details().bits, 150);
+
+	// The bits of live chat come from the tags, not from text that looks like a cheer
+	let message = IrcMessage::parse(PRIVMSG).unwrap();
+	let Some(ChatEvent::Comment { comment, .. }) = ChatEvent::from_irc(&message) else {
+		panic!("expected a comment");
+	};
+
+	assert_eq!(comment.details(), MessageDetails::default());
+
+	// Neither are words that only look like cheermotes
+	let message =
+		IrcMessage::parse(&PRIVMSG.replace("hello Kappa \\o/", "pride2022 kappa123")).unwrap();
+	let Some(ChatEvent::Comment { comment, .. }) = ChatEvent::from_irc(&message) else {
+		panic!("expected a comment");
+	};
+
+	assert_eq!(comment.message.text(), "pride2022 kappa123");
+	assert_eq!(comment.details(), MessageDetails::default());
+
+	let message = IrcMessage::parse(CLEARMSG).unwrap();
+	let Some(ChatEvent::Delete(delete)) = ChatEvent::from_irc(&message) else {
+		panic!("expected a deleted message");
+	};
+
+	assert_eq!(
+		delete,
+		ClearMessage {
+			channel: "atrioc".to_string(),
+			channel_id: 23211159,
+			id: "b34ccfc7-4977-403a-8a94-33c6bac34fb8".to_string(),
+		}
+	);
+}
+
+/// Starts a chat server that reads the login, answers a ping, sends `frames`,
+/// then closes the connection and returns what it received
+async fn serve(frames: Vec<String>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+
+	let server = tokio::spawn(async move {
+		let (stream, _) = listener.accept().await.unwrap();
+		let mut socket = accept_async(stream).await.unwrap();
+		let mut received = Vec::new();
+
+		while received.len() < 4 {
+			if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+				received.push(text);
+			}
+		}
+
+		socket
+			.send(Message::Text("PING :tmi.twitch.tv\r\n".to_string()))
+			.await
+			.unwrap();
+
+		if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+			received.push(text);
+		}
+
+		for frame in frames {
+			socket.send(Message::Text(frame)).await.unwrap();
+		}
+
+		socket.close(None).await.unwrap();
+
+		received
+	});
+
+	(endpoint, server)
+}
+
+#[tokio::test]
+async fn test_capture() {
+	let gql = MockGql::start(vec![
+		Route::new(
+			Operation::UseLive,
+			json!({ "channelLogin": "atrioc" }),
+			"use_live",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+			"videos_page_1",
+		),
+	])
+	.await;
+	let (endpoint, server) = serve(vec![
+		format!("{PRIVMSG}\r\n{CLEARMSG}\r\n{USERNOTICE}\r\n"),
+		format!("{CLEARCHAT}\r\n{CLEARMSG}\r\n"),
+	])
+	.await;
+	let sink = MemorySink::default();
+
+	capture(
+		&endpoint,
+		&gql.client(),
+		&fast_retry(),
+		&["Atrioc".to_string()],
+		&sink,
+		&mut Batches::default(),
+		false,
+	)
+	.await
+	.unwrap();
+
+	let received = server.await.unwrap();
+
+	assert_eq!(received[0], "CAP REQ :twitch.tv/tags twitch.tv/commands");
+	assert!(received[2].starts_with("NICK justinfan"));
+	assert_eq!(received[3], "JOIN #atrioc");
+	assert_eq!(received[4], "PONG :tmi.twitch.tv");
+
+	// Comments are saved under the archive of the broadcast, with offsets from its start
+	assert_eq!(*sink.videos.lock().unwrap(), [1649326959]);
+	assert_eq!(*sink.users.lock().unwrap(), ["atrioc", "xqc", "linkus7"]);
+	assert_eq!(
+		*sink.comments.lock().unwrap(),
+		["hello Kappa \\o/", "xQc subscribed for 3 months!"]
+	);
+	assert_eq!(*sink.offsets.lock().unwrap(), [1200, 1203]);
+
+	let clears = sink.clears.lock().unwrap();
+
+	assert_eq!(clears.len(), 1);
+	// The comments are saved before the event that removes them
+	assert_eq!(clears[0].0, 2);
+	// A comment deleted before it is saved is saved as deleted,
+	// and one deleted afterwards is marked where it was saved
+	assert_eq!(
+		*sink.deleted.lock().unwrap(),
+		[
+			"b34ccfc7-4977-403a-8a94-33c6bac34fb8",
+			"b34ccfc7-4977-403a-8a94-33c6bac34fb8"
+		]
+	);
+}
+
+#[tokio::test]
+async fn test_capture_clear() {
+	let gql = MockGql::start(vec![
+		Route::new(
+			Operation::UseLive,
+			json!({ "channelLogin": "atrioc" }),
+			"use_live",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+			"videos_page_1",
+		),
+	])
+	.await;
+	let (endpoint, server) = serve(vec![format!("{PRIVMSG}\r\n{CHEER}\r\n{CLEARCHAT}\r\n")]).await;
+	let sink = MemorySink::default();
+
+	capture(
+		&endpoint,
+		&gql.client(),
+		&fast_retry(),
+		&["Atrioc".to_string()],
+		&sink,
+		&mut Batches::default(),
+		false,
+	)
+	.await
+	.unwrap();
+	server.await.unwrap();
+
+	assert_eq!(sink.clears.lock().unwrap().len(), 1);
+	// Only the comments of the banned user are saved as deleted
+	assert_eq!(
+		*sink.deleted.lock().unwrap(),
+		["b34ccfc7-4977-403a-8a94-33c6bac34fb8"]
+	);
+}
+
+#[tokio::test]
+async fn test_capture_offline() {
+	let gql = MockGql::start(vec![Route::new(
+		Operation::UseLive,
+		json!({ "channelLogin": "atrioc" }),
+		"use_live_offline",
+	)])
+	.await;
+	let (endpoint, server) = serve(vec![format!("{PRIVMSG}\r\n{USERNOTICE}\r\n")]).await;
+	let sink = MemorySink::default();
+
+	capture(
+		&endpoint,
+		&gql.client(),
+		&fast_retry(),
+		&["Atrioc".to_string()],
+		&sink,
+		&mut Batches::default(),
+		false,
+	)
+	.await
+	.unwrap();
+	server.await.unwrap();
+
+	// Chat sent while the channel is offline is not part of any broadcast
+	assert!(sink.videos.lock().unwrap().is_empty());
+	assert!(sink.comments.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_capture_unarchived() {
+	let gql = MockGql::start(vec![
+		Route::new(
+			Operation::UseLive,
+			json!({ "channelLogin": "atrioc" }),
+			"use_live",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+			"videos_page_2",
+		),
+	])
+	.await;
+	let (endpoint, server) = serve(vec![format!("{PRIVMSG}\r\n{USERNOTICE}\r\n")]).await;
+	let sink = MemorySink::default();
+
+	capture(
+		&endpoint,
+		&gql.client(),
+		&fast_retry(),
+		&["Atrioc".to_string()],
+		&sink,
+		&mut Batches::default(),
+		false,
+	)
+	.await
+	.unwrap();
+	server.await.unwrap();
+
+	// The stream id is not a video, so nothing is saved without an archive
+	assert!(sink.videos.lock().unwrap().is_empty());
+	assert!(sink.comments.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_capture_reconnect() {
+	let down = MockGql::start(vec![]).await;
+	let gql = MockGql::start(vec![
+		Route::new(
+			Operation::UseLive,
+			json!({ "channelLogin": "atrioc" }),
+			"use_live",
+		),
+		Route::new(
+			Operation::FilterableVideoTowerVideos,
+			json!({ "channelOwnerLogin": "atrioc", "cursor": null }),
+			"videos_page_1",
+		),
+	])
+	.await;
+	let sink = MemorySink::default();
+	let mut batches = Batches::default();
+
+	// The broadcast cannot be looked up, so the comments are kept for the next connection
+	let (endpoint, server) = serve(vec![format!("{PRIVMSG}\r\n")]).await;
+
+	capture(
+		&endpoint,
+		&down.client(),
+		&fast_retry(),
+		&["Atrioc".to_string()],
+		&sink,
+		&mut batches,
+		false,
+	)
+	.await
+	.unwrap();
+	server.await.unwrap();
+
+	assert!(sink.comments.lock().unwrap().is_empty());
+
+	let (endpoint, server) = serve(vec![format!("{USERNOTICE}\r\n")]).await;
+
+	capture(
+		&endpoint,
+		&gql.client(),
+		&fast_retry(),
+		&["Atrioc".to_string()],
+		&sink,
+		&mut batches,
+		false,
+	)
+	.await
+	.unwrap();
+	server.await.unwrap();
+
+	assert_eq!(
+		*sink.comments.lock().unwrap(),
+		["hello Kappa \\o/", "xQc subscribed for 3 months!"]
+	);
+}
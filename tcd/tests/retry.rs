@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use tcd::gql::{
+	prelude::ChunkError,
+	retry::{ErrorClass, RetryPolicy},
+};
+
+fn missing() -> ChunkError {
+	ChunkError::DataMissing {
+		operation: "VideoCommentsByOffsetOrCursor",
+		id: 1,
+	}
+}
+
+#[test]
+fn test_delay_is_bounded() {
+	let policy = RetryPolicy {
+		base_delay: Duration::from_millis(100),
+		max_delay: Duration::from_secs(1),
+		..RetryPolicy::default()
+	};
+
+	for attempt in 1..20 {
+		assert!(policy.delay(attempt) <= Duration::from_secs(1));
+	}
+
+	assert!(policy.delay(1) <= Duration::from_millis(100));
+}
+
+#[test]
+fn test_retry_on_class() {
+	let mut policy = RetryPolicy::default();
+
+	assert!(!policy.should_retry(&missing(), 1));
+
+	policy.retry_on.push(ErrorClass::DataMissing);
+
+	assert!(policy.should_retry(&missing(), 1));
+	assert!(!policy.should_retry(&missing(), policy.max_attempts));
+}
+
+#[test]
+fn test_none_never_retries() {
+	let mut policy = RetryPolicy::none();
+	policy.retry_on.push(ErrorClass::DataMissing);
+
+	assert!(!policy.should_retry(&missing(), 1));
+}
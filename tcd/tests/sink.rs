@@ -0,0 +1,177 @@
+mod common;
+
+use std::{io::BufWriter, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset};
+use common::{fast_retry, MockGql, Route};
+use serde_json::json;
+use tcd::{
+	clip::Clip,
+	gql::{
+		prelude::Format,
+		request::Operation,
+		structs::{GqlComment, GqlCommenter, GqlEdge},
+	},
+	sink::{Sink, StreamError, StreamSink},
+	video::{Video, CSV_HEADER},
+};
+
+/// A sink that keeps everything in memory
+#[derive(Default)]
+struct MemorySink {
+	users: Mutex<Vec<String>>,
+	comments: Mutex<Vec<(i64, String)>>,
+}
+
+#[async_trait]
+impl Sink for MemorySink {
+	type Error = StreamError;
+
+	async fn save_users(
+		&self,
+		users: &[&GqlCommenter],
+		_seen_at: DateTime<FixedOffset>,
+	) -> Result<usize, StreamError> {
+		self.users
+			.lock()
+			.unwrap()
+			.extend(users.iter().map(|user| user.username.clone()));
+
+		Ok(users.len())
+	}
+
+	async fn save_comments(
+		&self,
+		video: &Video,
+		comments: &[GqlEdge<GqlComment>],
+	) -> Result<usize, StreamError> {
+		let mut saved = self.comments.lock().unwrap();
+		let before = saved.len();
+
+		saved.extend(
+			comments
+				.iter()
+				.filter(|comment| comment.node.commenter.is_some())
+				.map(|comment| (video.id, comment.node.message.text())),
+		);
+
+		Ok(saved.len() - before)
+	}
+
+	async fn save_video(&self, _video: &Video) -> Result<(), StreamError> {
+		Ok(())
+	}
+
+	async fn save_clip(&self, _clip: &Clip) -> Result<(), StreamError> {
+		Ok(())
+	}
+
+	async fn flush(&self) -> Result<(), StreamError> {
+		Ok(())
+	}
+}
+
+fn video() -> Video {
+	Video {
+		id: 1649326959,
+		title: "Marketing Monday".to_string(),
+		author: "atrioc".to_string(),
+		author_id: 23211159,
+		cursor: None,
+		created_at: chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:12Z").unwrap(),
+		length: 9000,
+		thumbnail_url: String::new(),
+		thumbnail: None,
+		game: None,
+		view_count: None,
+		language: None,
+	}
+}
+
+fn routes() -> Vec<Route> {
+	vec![
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "contentOffsetSeconds": 0 }),
+			"comments_page_1",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+	]
+}
+
+#[tokio::test]
+async fn test_custom_sink() {
+	let server = MockGql::start(routes()).await;
+	let sink = MemorySink::default();
+
+	video()
+		.write_to_sink(&server.client(), &fast_retry(), &sink, None, false)
+		.await
+		.unwrap();
+
+	// Commenters are saved once per page, so xqc is saved for both pages
+	assert_eq!(vec!["linkus7", "xqc", "xqc"], *sink.users.lock().unwrap());
+	assert_eq!(3, sink.comments.lock().unwrap().len());
+	assert_eq!(
+		(1649326959, "Kappa is not Kappa 🙂".to_string()),
+		sink.comments.lock().unwrap()[2]
+	);
+}
+
+#[tokio::test]
+async fn test_csv_header_matches_rows() {
+	let server = MockGql::start(routes()).await;
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+	let format = Format::Csv;
+
+	video()
+		.write_to_sink(
+			&server.client(),
+			&fast_retry(),
+			&StreamSink::new(&stream, &format),
+			None,
+			false,
+		)
+		.await
+		.unwrap();
+
+	let output = stream.into_inner().unwrap().into_inner().unwrap();
+	let mut reader = csv::ReaderBuilder::new()
+		.has_headers(false)
+		.from_reader(output.as_slice());
+	let columns = CSV_HEADER.split(',').count();
+	let mut rows = 0;
+
+	for record in reader.records() {
+		assert_eq!(columns, record.unwrap().len());
+		rows += 1;
+	}
+
+	assert_eq!(3, rows);
+}
+
+#[cfg(feature = "parquet")]
+#[tokio::test]
+async fn test_stream_sink_rejects_parquet() {
+	let server = MockGql::start(routes()).await;
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+	let format = Format::Parquet;
+
+	// Parquet files are written with a ParquetWriter, so a stream returns an error
+	let result = video()
+		.write_to_sink(
+			&server.client(),
+			&fast_retry(),
+			&StreamSink::new(&stream, &format),
+			None,
+			false,
+		)
+		.await;
+
+	assert!(matches!(result, Err(StreamError::Io(_))));
+}
@@ -0,0 +1,527 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use common::{fast_retry, MockGql, Route};
+use rusqlite::Connection;
+use serde_json::json;
+use tcd::{
+	checkpoint::{CheckpointStore, SqliteCheckpoints},
+	clip::Clip,
+	gql::{
+		request::Operation,
+		structs::{
+			GqlComment, GqlCommentMessage, GqlCommentMessageFragment, GqlCommenter, GqlEdge,
+			MessageKind,
+		},
+	},
+	sink::{ClearChat, ClearMessage, ModerationSink, Sink},
+	sqlite::SqliteStore,
+	video::Video,
+};
+
+fn video(id: i64, created_at: &str) -> Video {
+	Video {
+		id,
+		title: "Marketing Monday".to_string(),
+		author: "atrioc".to_string(),
+		author_id: 23211159,
+		cursor: None,
+		created_at: chrono::DateTime::parse_from_rfc3339(created_at).unwrap(),
+		length: 9000,
+		thumbnail_url: String::new(),
+		thumbnail: None,
+		game: None,
+		view_count: None,
+		language: None,
+	}
+}
+
+#[tokio::test]
+async fn test_save_comments() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "contentOffsetSeconds": 0 }),
+			"comments_page_1",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+	])
+	.await;
+	let path = std::env::temp_dir().join(format!("tcd-{}.sqlite", std::process::id()));
+	let store = SqliteStore::open(&path).unwrap();
+	let checkpoints = SqliteCheckpoints::new(&store, true);
+	let video = video(1649326959, "2022-11-07T19:00:12Z");
+
+	store.save_video(&video).await.unwrap();
+	video
+		.write_to_sink(
+			&server.client(),
+			&fast_retry(),
+			&store,
+			Some(&checkpoints),
+			false,
+		)
+		.await
+		.unwrap();
+
+	assert!(
+		checkpoints
+			.load(1649326959)
+			.await
+			.unwrap()
+			.unwrap()
+			.completed
+	);
+
+	let connection = Connection::open(&path).unwrap();
+	let count = |table: &str| -> i64 {
+		connection
+			.query_row(&format!(r#"SELECT COUNT(*) FROM "{table}""#), [], |row| {
+				row.get(0)
+			})
+			.unwrap()
+	};
+
+	assert_eq!(3, count("Comment"));
+	assert_eq!(2, count("CommentBadge"));
+	assert_eq!(1, count("Video"));
+
+	let text: String = connection
+		.query_row(
+			r#"SELECT "text" FROM "Comment" WHERE "offset" = 12"#,
+			[],
+			|row| row.get(0),
+		)
+		.unwrap();
+
+	assert_eq!("Kappa is not Kappa 🙂", text);
+
+	drop(connection);
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_oldest_video() {
+	let store = SqliteStore::open_in_memory().unwrap();
+
+	assert_eq!(None, store.oldest_video(23211159).await.unwrap());
+
+	store
+		.save_video(&video(1649326959, "2022-11-07T19:00:12Z"))
+		.await
+		.unwrap();
+	store
+		.save_video(&video(1642642569, "2022-11-03T21:25:22+02:00"))
+		.await
+		.unwrap();
+
+	assert_eq!(
+		chrono::DateTime::parse_from_rfc3339("2022-11-03T19:25:22Z").unwrap(),
+		store.oldest_video(23211159).await.unwrap().unwrap()
+	);
+}
+
+#[tokio::test]
+async fn test_video_metadata_columns() {
+	let path = std::env::temp_dir().join(format!("tcd-{}-columns.sqlite", std::process::id()));
+
+	let store = SqliteStore::open(&path).unwrap();
+	let mut video = video(1649326959, "2022-11-07T19:00:12Z");
+
+	store.save_video(&video).await.unwrap();
+
+	// Saving a video again updates its metadata
+	video.length = 9600;
+	video.game = Some("Just Chatting".to_string());
+	store.save_video(&video).await.unwrap();
+
+	let (title, length, game): (String, u32, String) = Connection::open(&path)
+		.unwrap()
+		.query_row(
+			r#"SELECT "title", "length", "game" FROM "Video" WHERE "id" = 1649326959"#,
+			[],
+			|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+		)
+		.unwrap();
+
+	assert_eq!("Marketing Monday", title);
+	assert_eq!(9600, length);
+	assert_eq!("Just Chatting", game);
+
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_username_history() {
+	let path = std::env::temp_dir().join(format!("tcd-{}-usernames.sqlite", std::process::id()));
+	let store = SqliteStore::open(&path).unwrap();
+	let time = |time: &str| chrono::DateTime::parse_from_rfc3339(time).unwrap();
+	let commenter = |username: &str| GqlCommenter {
+		id: 71092938,
+		username: username.to_string(),
+		display_name: username.to_string(),
+	};
+
+	assert_eq!(
+		1,
+		store
+			.save_users(&[&commenter("xqc")], time("2022-11-07T19:00:00Z"))
+			.await
+			.unwrap()
+	);
+	store
+		.save_users(&[&commenter("xqc_new")], time("2022-11-09T19:00:00Z"))
+		.await
+		.unwrap();
+	// A page of an older video does not undo the rename
+	store
+		.save_users(&[&commenter("xqc")], time("2022-11-01T19:00:00Z"))
+		.await
+		.unwrap();
+
+	let connection = Connection::open(&path).unwrap();
+	let username: String = connection
+		.query_row(
+			r#"SELECT "username" FROM "User" WHERE "id" = 71092938"#,
+			[],
+			|row| row.get(0),
+		)
+		.unwrap();
+	let history = connection
+		.prepare(
+			r#"SELECT "username", "firstSeen", "lastSeen" FROM "UsernameHistory" WHERE "userId" = 71092938 ORDER BY "firstSeen""#,
+		)
+		.unwrap()
+		.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+		.unwrap()
+		.collect::<rusqlite::Result<Vec<(String, String, String)>>>()
+		.unwrap();
+
+	assert_eq!("xqc_new", username);
+	assert_eq!(
+		vec![
+			(
+				"xqc".to_string(),
+				"2022-11-01T19:00:00.000Z".to_string(),
+				"2022-11-07T19:00:00.000Z".to_string()
+			),
+			(
+				"xqc_new".to_string(),
+				"2022-11-09T19:00:00.000Z".to_string(),
+				"2022-11-09T19:00:00.000Z".to_string()
+			),
+		],
+		history
+	);
+
+	drop(connection);
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
+
+/// A replayed comment by xQc, whose details are read from its text
+fn comment(id: &str, text: &str) -> GqlEdge<GqlComment> {
+	GqlEdge {
+		cursor: None,
+		node: GqlComment {
+			id: id.to_string(),
+			commenter: Some(GqlCommenter {
+				id: 71092938,
+				username: "xqc".to_string(),
+				display_name: "xQc".to_string(),
+			}),
+			offset: 5,
+			created_at: chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:17Z").unwrap(),
+			message: GqlCommentMessage {
+				fragments: vec![GqlCommentMessageFragment {
+					emote: None,
+					text: text.to_string(),
+				}],
+				badges: vec![],
+				color: None,
+			},
+			tags: None,
+			deleted: false,
+		},
+	}
+}
+
+#[tokio::test]
+async fn test_message_details() {
+	let path = std::env::temp_dir().join(format!("tcd-{}-details.sqlite", std::process::id()));
+	let store = SqliteStore::open(&path).unwrap();
+	let video = video(1649326959, "2022-11-07T19:00:12Z");
+	let comments = [
+		comment("1", "Cheer100 nice Cheer50"),
+		comment(
+			"2",
+			"xQc subscribed at Tier 1. They've subscribed for 14 months!",
+		),
+		comment("3", "xqc gifted a Tier 2 sub to Linkus7!"),
+		comment("4", "xQc is gifting 5 Tier 1 Subs to Atrioc's community!"),
+		comment("5", "Cheer me up"),
+	];
+
+	store.save_video(&video).await.unwrap();
+	store
+		.save_users(
+			&[comments[0].node.commenter.as_ref().unwrap()],
+			video.created_at,
+		)
+		.await
+		.unwrap();
+	store.save_comments(&video, &comments).await.unwrap();
+	store
+		.delete_comment(&ClearMessage {
+			channel: "atrioc".to_string(),
+			channel_id: 23211159,
+			id: "5".to_string(),
+		})
+		.await
+		.unwrap();
+
+	let rows = Connection::open(&path)
+		.unwrap()
+		.prepare(
+			r#"SELECT "kind", "bits", "subTier", "subMonths", "giftCount", "giftRecipient", "deleted"
+			FROM "Comment" ORDER BY "id""#,
+		)
+		.unwrap()
+		.query_map([], |row| {
+			Ok((
+				row.get::<_, String>(0)?,
+				row.get::<_, u32>(1)?,
+				row.get::<_, Option<String>>(2)?,
+				row.get::<_, Option<u32>>(3)?,
+				row.get::<_, Option<u32>>(4)?,
+				row.get::<_, Option<String>>(5)?,
+				row.get::<_, bool>(6)?,
+			))
+		})
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	assert_eq!(
+		vec![
+			("cheer".to_string(), 150, None, None, None, None, false),
+			(
+				"resub".to_string(),
+				0,
+				Some("1000".to_string()),
+				Some(14),
+				None,
+				None,
+				false
+			),
+			(
+				"subgift".to_string(),
+				0,
+				Some("2000".to_string()),
+				None,
+				Some(1),
+				Some("Linkus7".to_string()),
+				false
+			),
+			(
+				"submysterygift".to_string(),
+				0,
+				Some("1000".to_string()),
+				None,
+				Some(5),
+				None,
+				false
+			),
+			("chat".to_string(), 0, None, None, None, None, true),
+		],
+		rows
+	);
+
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_inferred_details() {
+	let path = std::env::temp_dir().join(format!("tcd-{}-inferred.sqlite", std::process::id()));
+	let store = SqliteStore::open(&path).unwrap();
+	let video = video(1649326959, "2022-11-07T19:00:12Z");
+	// Replayed chat has no tags, so a year or an emote with a number reads like a cheer
+	let comments = [comment("1", "happy pride2022"), comment("2", "kappa123")];
+
+	for comment in &comments {
+		let details = comment.node.details();
+
+		assert_eq!(MessageKind::Cheer, details.kind);
+		assert!(details.inferred);
+	}
+
+	store.save_video(&video).await.unwrap();
+	store
+		.save_users(
+			&[comments[0].node.commenter.as_ref().unwrap()],
+			video.created_at,
+		)
+		.await
+		.unwrap();
+	store.save_comments(&video, &comments).await.unwrap();
+
+	let rows = Connection::open(&path)
+		.unwrap()
+		.prepare(r#"SELECT "bits", "inferred" FROM "Comment" ORDER BY "id""#)
+		.unwrap()
+		.query_map([], |row| {
+			Ok((row.get::<_, u32>(0)?, row.get::<_, bool>(1)?))
+		})
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	assert_eq!(vec![(2022, true), (123, true)], rows);
+
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_clear_comments() {
+	let path = std::env::temp_dir().join(format!("tcd-{}-clear.sqlite", std::process::id()));
+	let store = SqliteStore::open(&path).unwrap();
+	let video = video(1649326959, "2022-11-07T19:00:12Z");
+	let mut later = comment("2", "still here");
+
+	later.node.created_at = chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:30Z").unwrap();
+
+	let comments = [comment("1", "banned"), later];
+
+	store.save_video(&video).await.unwrap();
+	store
+		.save_users(
+			&[comments[0].node.commenter.as_ref().unwrap()],
+			video.created_at,
+		)
+		.await
+		.unwrap();
+	store.save_comments(&video, &comments).await.unwrap();
+
+	let clear = |target, created_at| ClearChat {
+		channel: "atrioc".to_string(),
+		channel_id: 23211159,
+		target,
+		duration: None,
+		created_at: chrono::DateTime::parse_from_rfc3339(created_at).unwrap(),
+	};
+	let deleted = || {
+		Connection::open(&path)
+			.unwrap()
+			.prepare(r#"SELECT "deleted" FROM "Comment" ORDER BY "id""#)
+			.unwrap()
+			.query_map([], |row| row.get::<_, bool>(0))
+			.unwrap()
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap()
+	};
+
+	// A ban only removes the comments the user sent before it
+	store
+		.clear_comments(
+			&video,
+			&clear(Some((71092938, "xqc".to_string())), "2022-11-07T19:00:20Z"),
+		)
+		.await
+		.unwrap();
+	assert_eq!(vec![true, false], deleted());
+
+	// Clearing the chat removes every comment sent before it
+	store
+		.clear_comments(&video, &clear(None, "2022-11-07T19:00:40Z"))
+		.await
+		.unwrap();
+	assert_eq!(vec![true, true], deleted());
+
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_save_clip() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::ShareClipRenderStatus,
+			json!({ "slug": "AmazingCrepuscularSnailKappa-8fQ2xWm1TgRbYq7N" }),
+			"clip",
+		),
+		Route::new(
+			Operation::VideoMetadata,
+			json!({ "videoID": "1649326959" }),
+			"video_metadata",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "contentOffsetSeconds": 4 }),
+			"comments_page_1",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+	])
+	.await;
+	let gql = server.client();
+	let path = std::env::temp_dir().join(format!("tcd-{}-clip.sqlite", std::process::id()));
+	let store = SqliteStore::open(&path).unwrap();
+	let clip = Clip::from_slug(
+		&gql,
+		&fast_retry(),
+		"AmazingCrepuscularSnailKappa-8fQ2xWm1TgRbYq7N",
+	)
+	.await
+	.unwrap()
+	.unwrap();
+
+	// Saving a clip twice keeps the first row
+	for _ in 0..2 {
+		clip.write_to_sink(&gql, &fast_retry(), &store, false)
+			.await
+			.unwrap();
+	}
+
+	let rows = Connection::open(&path)
+		.unwrap()
+		.prepare(r#"SELECT "id", "broadcasterId", "videoId", "duration", "offset" FROM "Clip""#)
+		.unwrap()
+		.query_map([], |row| {
+			Ok((
+				row.get::<_, i64>(0)?,
+				row.get::<_, i64>(1)?,
+				row.get::<_, Option<i64>>(2)?,
+				row.get::<_, u32>(3)?,
+				row.get::<_, Option<u32>>(4)?,
+			))
+		})
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	assert_eq!(
+		vec![(
+			1427906386,
+			clip.broadcaster_id,
+			Some(1649326959),
+			clip.duration,
+			Some(4)
+		)],
+		rows
+	);
+
+	drop(store);
+	std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,218 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use common::{fast_retry, MockGql, Route};
+use serde_json::json;
+use tcd::{
+	gql::{request::Operation, structs::GqlCommenter},
+	sink::{Sink, UserSink},
+	sqlite::SqliteStore,
+	user::{EnrichUsers, User, UserEnricher},
+	video::Video,
+};
+
+#[tokio::test]
+async fn test_user_details() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::PlayerTrackingContextQuery,
+			json!({ "channel": "atrioc" }),
+			"player_context",
+		),
+		Route::new(
+			Operation::ViewerCard,
+			json!({ "channelID": "23211159" }),
+			"viewer_card",
+		),
+	])
+	.await;
+
+	let user = User::from_username(&server.client(), "atrioc")
+		.await
+		.unwrap()
+		.unwrap();
+
+	assert_eq!(
+		User {
+			id: 23211159,
+			username: "atrioc".to_string(),
+			display_name: "Atrioc".to_string(),
+			created_at: chrono::DateTime::parse_from_rfc3339("2011-06-18T19:37:44.27189Z").unwrap(),
+			profile_image_url: Some(
+				"https://static-cdn.jtvnw.net/jtv_user_pictures/atrioc-profile_image-70x70.png"
+					.to_string()
+			),
+			description: Some("Marketing Monday, every Monday".to_string()),
+		},
+		user
+	);
+}
+
+#[tokio::test]
+async fn test_enrich_users() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::ViewerCard,
+			json!({ "channelID": "23211159" }),
+			"viewer_card",
+		),
+		Route::new(
+			Operation::ViewerCard,
+			json!({ "channelID": "71092938" }),
+			"viewer_card_xqc",
+		),
+		Route::new(
+			Operation::ViewerCard,
+			json!({ "channelID": "40934651" }),
+			"viewer_card_missing",
+		),
+	])
+	.await;
+	let gql = server.client();
+	let retry = fast_retry();
+	let store = SqliteStore::open_in_memory().unwrap();
+	let commenters = [
+		GqlCommenter {
+			id: 71092938,
+			username: "xqc".to_string(),
+			display_name: "xQc".to_string(),
+		},
+		GqlCommenter {
+			id: 40934651,
+			username: "linkus7".to_string(),
+			display_name: "Linkus7".to_string(),
+		},
+	];
+
+	store
+		.save_video(&Video {
+			id: 1649326959,
+			title: "Marketing Monday".to_string(),
+			author: "atrioc".to_string(),
+			author_id: 23211159,
+			cursor: None,
+			created_at: chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:12Z").unwrap(),
+			length: 9000,
+			thumbnail_url: String::new(),
+			thumbnail: None,
+			game: None,
+			view_count: None,
+			language: None,
+		})
+		.await
+		.unwrap();
+	store
+		.save_users(
+			&commenters.iter().collect::<Vec<_>>(),
+			chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:17Z").unwrap(),
+		)
+		.await
+		.unwrap();
+
+	// Only the channel is looked up, and only once
+	let mut enricher = UserEnricher::new(EnrichUsers::Channels);
+
+	assert_eq!(
+		1,
+		enricher
+			.enrich(&gql, &retry, &store, 2)
+			.await
+			.unwrap()
+			.count
+	);
+	assert_eq!(
+		0,
+		enricher
+			.enrich(&gql, &retry, &store, 2)
+			.await
+			.unwrap()
+			.count
+	);
+	assert_eq!(1, server.requests(Operation::ViewerCard));
+
+	// The commenter whose account no longer exists is not looked up again
+	let mut enricher = UserEnricher::new(EnrichUsers::Commenters);
+
+	assert_eq!(
+		1,
+		enricher
+			.enrich(&gql, &retry, &store, 2)
+			.await
+			.unwrap()
+			.count
+	);
+	assert_eq!(
+		0,
+		enricher
+			.enrich(&gql, &retry, &store, 2)
+			.await
+			.unwrap()
+			.count
+	);
+	assert_eq!(3, server.requests(Operation::ViewerCard));
+	assert_eq!(
+		vec![(40934651, "linkus7".to_string())],
+		store
+			.users_without_details(EnrichUsers::Commenters, i64::MIN, 10)
+			.await
+			.unwrap()
+	);
+}
+
+#[tokio::test]
+async fn test_enrich_users_failed_lookup() {
+	// The viewer card of the commenter is not served, so its lookup fails
+	let server = MockGql::start(vec![Route::new(
+		Operation::ViewerCard,
+		json!({ "channelID": "23211159" }),
+		"viewer_card",
+	)])
+	.await;
+	let gql = server.client();
+	let retry = fast_retry();
+	let store = SqliteStore::open_in_memory().unwrap();
+
+	store
+		.save_users(
+			&[&GqlCommenter {
+				id: 71092938,
+				username: "xqc".to_string(),
+				display_name: "xQc".to_string(),
+			}],
+			chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:17Z").unwrap(),
+		)
+		.await
+		.unwrap();
+	store
+		.save_users(
+			&[&GqlCommenter {
+				id: 23211159,
+				username: "atrioc".to_string(),
+				display_name: "Atrioc".to_string(),
+			}],
+			chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:17Z").unwrap(),
+		)
+		.await
+		.unwrap();
+
+	// The failed lookup is returned, and the other users are still saved
+	let mut enricher = UserEnricher::new(EnrichUsers::Commenters);
+	let enriched = enricher.enrich(&gql, &retry, &store, 2).await.unwrap();
+
+	assert_eq!(1, enriched.count);
+	assert_eq!(1, enriched.failed.len());
+	assert_eq!(
+		vec![(71092938, "xqc".to_string())],
+		store
+			.users_without_details(EnrichUsers::Commenters, i64::MIN, 10)
+			.await
+			.unwrap()
+	);
+
+	// The failed user is looked up again by the next pass
+	let enriched = enricher.enrich(&gql, &retry, &store, 2).await.unwrap();
+
+	assert_eq!(0, enriched.count);
+	assert_eq!(1, enriched.failed.len());
+}
@@ -0,0 +1,499 @@
+mod common;
+
+#[cfg(feature = "parquet")]
+use std::fs::File;
+use std::{io::BufWriter, sync::Mutex};
+
+use common::{fast_retry, MockGql, Route};
+use futures::{StreamExt, TryStreamExt};
+#[cfg(feature = "parquet")]
+use parquet::{
+	file::reader::{FileReader, SerializedFileReader},
+	record::RowAccessor,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use tcd::{
+	gql::{
+		prelude::{ChunkError, Format, Paginate, PaginateFilter, WriteChunk},
+		request::Operation,
+		retry::{ErrorClass, RetryPolicy},
+	},
+	video::{Video, WHOLE_VIDEO},
+};
+#[cfg(feature = "parquet")]
+use tcd::{parquet::ParquetWriter, sink::Sink};
+
+fn comment_routes() -> Vec<Route> {
+	vec![
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "contentOffsetSeconds": 0 }),
+			"comments_page_1",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+	]
+}
+
+/// A video known to the mock server, without fetching its metadata
+fn video(id: i64) -> Video {
+	Video {
+		id,
+		title: "Marketing Monday".to_string(),
+		author: "atrioc".to_string(),
+		author_id: 23211159,
+		cursor: None,
+		created_at: chrono::DateTime::parse_from_rfc3339("2022-11-07T19:00:12Z").unwrap(),
+		length: 9000,
+		thumbnail_url: String::new(),
+		thumbnail: None,
+		game: None,
+		view_count: None,
+		language: None,
+	}
+}
+
+async fn get_video(server: &MockGql, id: i64) -> Option<Video> {
+	let gql = server.client();
+	let retry = fast_retry();
+	let ids = [id];
+
+	let videos = Video::paginate_filter(&gql, &retry, &ids)
+		.map_ok(Video::from)
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap();
+
+	videos.into_iter().next()
+}
+
+#[tokio::test]
+async fn test_video_metadata() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::VideoMetadata,
+			json!({ "videoID": "1649326959" }),
+			"video_metadata",
+		),
+		Route::new(
+			Operation::VideoMetadata,
+			json!({ "videoID": "1" }),
+			"video_metadata_missing",
+		),
+	])
+	.await;
+
+	let video = get_video(&server, 1649326959).await.unwrap();
+
+	assert_eq!("atrioc", video.author);
+	assert_eq!(23211159, video.author_id);
+	assert_eq!(9000, video.length);
+	assert_eq!(Some("Just Chatting"), video.game.as_deref());
+	assert_eq!(Some(120000), video.view_count);
+	assert_eq!(Some("en"), video.language.as_deref());
+	assert_eq!(None, get_video(&server, 1).await);
+}
+
+#[tokio::test]
+async fn test_comments_pagination() {
+	let server = MockGql::start(comment_routes()).await;
+	let video = video(1649326959);
+
+	let chunks = video
+		.paginate(&server.client(), &fast_retry())
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap();
+
+	assert_eq!(
+		vec![3, 1],
+		chunks.iter().map(|c| c.edges.len()).collect::<Vec<_>>()
+	);
+	assert!(chunks[0].edges[1].node.commenter.is_none());
+}
+
+#[tokio::test]
+async fn test_comments_range() {
+	let server = MockGql::start(vec![Route::new(
+		Operation::VideoCommentsByOffsetOrCursor,
+		json!({ "videoID": "1649326959", "contentOffsetSeconds": 4 }),
+		"comments_page_1",
+	)])
+	.await;
+	let video = video(1649326959);
+
+	let offsets = video
+		.paginate_range(&server.client(), &fast_retry(), 4..=6)
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap()
+		.into_iter()
+		.flat_map(|page| page.edges)
+		.map(|edge| edge.node.offset)
+		.collect::<Vec<_>>();
+
+	// The first page already passes the end of the range, so the next is never requested
+	assert_eq!(vec![5], offsets);
+	assert_eq!(1, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+}
+
+#[tokio::test]
+async fn test_comments_segments() {
+	let mut routes = comment_routes();
+	routes.push(Route::new(
+		Operation::VideoCommentsByOffsetOrCursor,
+		json!({ "videoID": "1649326959", "contentOffsetSeconds": 6 }),
+		"comments_page_1",
+	));
+
+	let server = MockGql::start(routes).await;
+	let video = Video {
+		length: 12,
+		..video(1649326959)
+	};
+
+	let offsets = video
+		.paginate_segments(&server.client(), &fast_retry(), WHOLE_VIDEO, 2)
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap()
+		.into_iter()
+		.flat_map(|page| page.edges)
+		.map(|edge| edge.node.offset)
+		.collect::<Vec<_>>();
+
+	// The first part stops at 5 seconds, and the second part starts at 6 seconds
+	// even though the page it is given starts before it
+	assert_eq!(vec![3, 5, 8, 12], offsets);
+	assert_eq!(3, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+}
+
+#[tokio::test]
+async fn test_comments_segments_error() {
+	// The second part is never served, so it fails after the first part is downloaded
+	let server = MockGql::start(comment_routes()).await;
+	let video = Video {
+		length: 12,
+		..video(1649326959)
+	};
+
+	let pages = video
+		.paginate_segments(&server.client(), &fast_retry(), WHOLE_VIDEO, 2)
+		.collect::<Vec<_>>()
+		.await;
+
+	// The pages of the first part are still streamed in order before the error
+	assert_eq!(2, pages.len());
+	assert_eq!(
+		vec![3, 5],
+		pages[0]
+			.as_ref()
+			.unwrap()
+			.edges
+			.iter()
+			.map(|edge| edge.node.offset)
+			.collect::<Vec<_>>()
+	);
+	assert!(pages[1].is_err());
+}
+
+#[tokio::test]
+async fn test_comments_segments_bounded() {
+	// Every page links back to itself, so neither part of the video ever ends
+	let server = MockGql::start(vec![Route::new(
+		Operation::VideoCommentsByOffsetOrCursor,
+		json!({}),
+		"comments_page_1",
+	)])
+	.await;
+	let video = Video {
+		length: 18,
+		..video(1649326959)
+	};
+
+	let pages = video
+		.paginate_segments(&server.client(), &fast_retry(), WHOLE_VIDEO, 2)
+		.take(20)
+		.try_collect::<Vec<_>>()
+		.await
+		.unwrap();
+
+	// The second part stops a few pages ahead instead of keeping up with the first
+	assert_eq!(20, pages.len());
+	assert!(server.requests(Operation::VideoCommentsByOffsetOrCursor) <= 26);
+}
+
+#[tokio::test]
+async fn test_null_commenters_are_skipped() {
+	let server = MockGql::start(comment_routes()).await;
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+
+	video(1649326959)
+		.write_to_stream(
+			&server.client(),
+			&fast_retry(),
+			&stream,
+			&Format::JsonLines,
+			None,
+		)
+		.await
+		.unwrap();
+
+	let output = String::from_utf8(stream.into_inner().unwrap().into_inner().unwrap()).unwrap();
+	let commenters = output
+		.lines()
+		.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["commenter"].clone())
+		.collect::<Vec<_>>();
+
+	assert_eq!(vec!["xqc", "linkus7", "xqc"], commenters);
+}
+
+#[tokio::test]
+async fn test_missing_video() {
+	let server = MockGql::start(vec![Route::new(
+		Operation::VideoCommentsByOffsetOrCursor,
+		json!({}),
+		"comments_missing_video",
+	)])
+	.await;
+
+	let error = video(1)
+		.chunk_by_offset(&server.client(), 0)
+		.await
+		.unwrap_err();
+
+	assert!(matches!(error, ChunkError::DataMissing { id: 1, .. }));
+}
+
+#[tokio::test]
+async fn test_server_errors_are_retried() {
+	let mut routes = comment_routes();
+	routes.insert(
+		0,
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({}),
+			"server_error",
+		)
+		.status(500)
+		.times(2),
+	);
+
+	let server = MockGql::start(routes).await;
+	let gql = server.client();
+	let video = video(1649326959);
+
+	let comments = fast_retry()
+		.run(|| video.chunk_by_offset(&gql, 0))
+		.await
+		.unwrap();
+
+	assert_eq!(3, comments.edges.len());
+	assert_eq!(3, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+}
+
+#[tokio::test]
+async fn test_error_responses() {
+	let server = MockGql::start(vec![Route::new(
+		Operation::VideoCommentsByOffsetOrCursor,
+		json!({ "videoID": "1649326959" }),
+		"persisted_query_not_found",
+	)])
+	.await;
+
+	let gql = server.client();
+	let retry = RetryPolicy::none();
+
+	let rotated = video(1649326959);
+
+	let error = retry
+		.run(|| rotated.chunk_by_offset(&gql, 0))
+		.await
+		.unwrap_err();
+
+	assert_eq!(ErrorClass::Decode, error.class());
+	assert_eq!(1, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+
+	let error = video(2).chunk_by_offset(&gql, 0).await.unwrap_err();
+
+	assert_eq!(Some(StatusCode::NOT_FOUND), error.status());
+}
+
+#[tokio::test]
+async fn test_stale_query_is_not_retried() {
+	let server = MockGql::start(vec![Route::new(
+		Operation::VideoCommentsByOffsetOrCursor,
+		json!({ "videoID": "1649326959" }),
+		"persisted_query_not_found",
+	)])
+	.await;
+
+	let gql = server.client();
+	let rotated = video(1649326959);
+
+	// A stale query hash fails the same way every time, so it is not retried by default
+	fast_retry()
+		.run(|| rotated.chunk_by_offset(&gql, 0))
+		.await
+		.unwrap_err();
+
+	assert_eq!(1, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+}
+
+#[tokio::test]
+async fn test_emote_fragments() {
+	let server = MockGql::start(comment_routes()).await;
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+
+	video(1649326959)
+		.write_to_stream(
+			&server.client(),
+			&fast_retry(),
+			&stream,
+			&Format::JsonLines,
+			None,
+		)
+		.await
+		.unwrap();
+
+	let output = String::from_utf8(stream.into_inner().unwrap().into_inner().unwrap()).unwrap();
+	let entry: serde_json::Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+
+	assert_eq!("Kappa is not Kappa 🙂", entry["text"]);
+	assert_eq!(
+		json!([
+			{ "text": "Kappa is not ", "emote": null },
+			{ "text": "Kappa", "emote": { "id": "25", "start": 13, "end": 18 } },
+			{ "text": " 🙂", "emote": null },
+		]),
+		entry["fragments"]
+	);
+}
+
+#[tokio::test]
+async fn test_commenter_badges() {
+	let server = MockGql::start(comment_routes()).await;
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+
+	video(1649326959)
+		.write_to_stream(
+			&server.client(),
+			&fast_retry(),
+			&stream,
+			&Format::JsonLines,
+			None,
+		)
+		.await
+		.unwrap();
+
+	let output = String::from_utf8(stream.into_inner().unwrap().into_inner().unwrap()).unwrap();
+	let entries = output
+		.lines()
+		.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+		.collect::<Vec<_>>();
+
+	assert_eq!("xQc", entries[0]["display_name"]);
+	assert_eq!("#FF4500", entries[0]["color"]);
+	assert_eq!(
+		json!([
+			{ "set_id": "subscriber", "version": "12" },
+			{ "set_id": "moderator", "version": "1" },
+		]),
+		entries[0]["badges"]
+	);
+	assert_eq!(json!(null), entries[1]["color"]);
+	assert_eq!(json!([]), entries[1]["badges"]);
+}
+
+#[tokio::test]
+async fn test_csv_round_trip() {
+	let server = MockGql::start(comment_routes()).await;
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+
+	video(1649326959)
+		.write_to_stream(&server.client(), &fast_retry(), &stream, &Format::Csv, None)
+		.await
+		.unwrap();
+
+	let output = stream.into_inner().unwrap().into_inner().unwrap();
+	let records = csv::ReaderBuilder::new()
+		.has_headers(false)
+		.from_reader(output.as_slice())
+		.into_records()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	assert_eq!(
+		vec!["3", "8", "12"],
+		records.iter().map(|r| &r[5]).collect::<Vec<_>>()
+	);
+	assert_eq!("LETS GO, he said \"GG\"\nnew line 🎉", &records[1][6]);
+	assert_eq!("Kappa is not Kappa 🙂", &records[2][6]);
+}
+
+#[cfg(feature = "parquet")]
+#[tokio::test]
+async fn test_parquet_round_trip() {
+	let server = MockGql::start(comment_routes()).await;
+	let path = std::env::temp_dir().join(format!("tcd-comments-{}.parquet", std::process::id()));
+	// A row group size of 2 splits the three comments across two row groups
+	let writer = ParquetWriter::new(File::create(&path).unwrap(), 2).unwrap();
+
+	video(1649326959)
+		.write_to_sink(&server.client(), &fast_retry(), &writer, None, false)
+		.await
+		.unwrap();
+
+	writer.finish().unwrap();
+
+	let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+	let rows = reader
+		.get_row_iter(None)
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	assert_eq!(2, reader.metadata().num_row_groups());
+	assert_eq!(3, rows.len());
+	assert_eq!("atrioc", rows[0].get_string(0).unwrap());
+	assert_eq!(1649326959, rows[0].get_long(1).unwrap());
+	assert_eq!(
+		vec![3, 8, 12],
+		rows.iter()
+			.map(|r| r.get_int(6).unwrap())
+			.collect::<Vec<_>>()
+	);
+	assert_eq!("Kappa is not Kappa 🙂", rows[2].get_string(7).unwrap());
+
+	std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "parquet")]
+#[tokio::test]
+async fn test_parquet_flush_writes_row_group() {
+	let server = MockGql::start(comment_routes()).await;
+	let path = std::env::temp_dir().join(format!("tcd-flush-{}.parquet", std::process::id()));
+	let writer = ParquetWriter::new(File::create(&path).unwrap(), 100).unwrap();
+
+	// Flushing writes the buffered comments as a row group before the buffer is full
+	for _ in 0..2 {
+		video(1649326959)
+			.write_to_sink(&server.client(), &fast_retry(), &writer, None, false)
+			.await
+			.unwrap();
+		writer.flush().await.unwrap();
+	}
+
+	writer.finish().unwrap();
+
+	let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+
+	assert_eq!(2, reader.metadata().num_row_groups());
+	assert_eq!(6, reader.metadata().file_metadata().num_rows());
+
+	std::fs::remove_file(path).unwrap();
+}
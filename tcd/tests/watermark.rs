@@ -0,0 +1,292 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use std::{io::BufWriter, sync::Mutex};
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use common::{fast_retry, MockGql, Route};
+use serde_json::json;
+use tcd::{
+	channel::VideoSort,
+	checkpoint::{Checkpoint, CheckpointStore, SqliteCheckpoints},
+	gql::{prelude::Format, request::Operation},
+	sink::StreamSink,
+	sqlite::SqliteStore,
+	video::Video,
+	watermark::{FileWatermarks, SqliteWatermarks, Watermark, WatermarkStore, WatermarkTracker},
+};
+
+fn video(id: i64, created_at: DateTime<FixedOffset>, length: u32) -> Video {
+	Video {
+		id,
+		title: "Marketing Monday".to_string(),
+		author: "atrioc".to_string(),
+		author_id: 23211159,
+		cursor: None,
+		created_at,
+		length,
+		thumbnail_url: String::new(),
+		thumbnail: None,
+		game: None,
+		view_count: None,
+		language: None,
+	}
+}
+
+fn time(time: &str) -> DateTime<FixedOffset> {
+	DateTime::parse_from_rfc3339(time).unwrap()
+}
+
+#[tokio::test]
+async fn test_watermarks_resume() {
+	let store = SqliteStore::open_in_memory().unwrap();
+	let watermarks = SqliteWatermarks::new(&store, true);
+	let mut tracker = WatermarkTracker::new(Some(&watermarks));
+
+	tracker
+		.record(
+			23211159,
+			&[
+				video(2, time("2022-11-08T19:00:00Z"), 9000),
+				video(1, time("2022-11-07T19:00:00Z"), 9000),
+			],
+			&[],
+		)
+		.await
+		.unwrap();
+
+	// A new tracker continues from the newest video saved by the last one
+	let mut tracker = WatermarkTracker::new(Some(&watermarks));
+	let mut videos = vec![
+		video(3, time("2022-11-09T19:00:00Z"), 9000),
+		video(2, time("2022-11-08T19:00:00Z"), 9000),
+		video(1, time("2022-11-07T19:00:00Z"), 9000),
+	];
+
+	assert!(tracker
+		.retain_new(23211159, VideoSort::Time, &mut videos)
+		.await
+		.unwrap());
+	assert_eq!(vec![3], videos.iter().map(|v| v.id).collect::<Vec<_>>());
+
+	let mut videos = vec![video(1, time("2022-11-07T19:00:00Z"), 9000)];
+
+	assert!(!tracker
+		.retain_new(23211159, VideoSort::Views, &mut videos)
+		.await
+		.unwrap());
+	assert!(videos.is_empty());
+
+	let watermarks = SqliteWatermarks::new(&store, false);
+	let mut tracker = WatermarkTracker::new(Some(&watermarks));
+
+	assert_eq!(None, tracker.watermark(23211159).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_file_watermarks_resume() {
+	let path = FileWatermarks::sidecar(
+		&std::env::temp_dir().join(format!("tcd-watermark-{}.csv", std::process::id())),
+	);
+	let watermark = Watermark {
+		channel_id: 23211159,
+		video_id: 2,
+		created_at: time("2022-11-08T19:00:00Z"),
+	};
+
+	FileWatermarks::open(&path, false)
+		.unwrap()
+		.save(&watermark)
+		.await
+		.unwrap();
+
+	let resumed = FileWatermarks::open(&path, true).unwrap();
+
+	assert_eq!(Some(watermark), resumed.load(23211159).await.unwrap());
+	assert_eq!(
+		None,
+		FileWatermarks::open(&path, false)
+			.unwrap()
+			.load(23211159)
+			.await
+			.unwrap()
+	);
+
+	std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn test_watermark_stops_before_failed_video() {
+	let mut tracker = WatermarkTracker::new(None);
+
+	tracker
+		.record(
+			23211159,
+			&[
+				video(3, time("2022-11-09T19:00:00Z"), 9000),
+				video(1, time("2022-11-07T19:00:00Z"), 9000),
+			],
+			&[video(2, time("2022-11-08T19:00:00Z"), 9000)],
+		)
+		.await
+		.unwrap();
+
+	// The failed video and the ones after it are downloaded again by the next poll
+	let mut videos = vec![
+		video(3, time("2022-11-09T19:00:00Z"), 9000),
+		video(2, time("2022-11-08T19:00:00Z"), 9000),
+		video(1, time("2022-11-07T19:00:00Z"), 9000),
+	];
+
+	assert!(tracker
+		.retain_new(23211159, VideoSort::Time, &mut videos)
+		.await
+		.unwrap());
+	assert_eq!(vec![3, 2], videos.iter().map(|v| v.id).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_recheck_recording_videos() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::VideoMetadata,
+			json!({ "videoID": "1649326959" }),
+			"video_metadata",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImE1ZjdkOWUxIn0=" }),
+			"comments_empty",
+		),
+	])
+	.await;
+	let gql = server.client();
+	let store = SqliteStore::open_in_memory().unwrap();
+	let checkpoints = SqliteCheckpoints::new(&store, true);
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+	let sink = StreamSink::new(&stream, &Format::JsonLines);
+	let mut tracker = WatermarkTracker::new(None);
+
+	// The video was downloaded while it was 8000 seconds long and still recording
+	checkpoints
+		.save(&Checkpoint {
+			video_id: 1649326959,
+			cursor: Some("eyJpZCI6ImU0YjhjNmQwIn0=".to_string()),
+			offset: 8,
+			completed: true,
+		})
+		.await
+		.unwrap();
+	tracker
+		.record(
+			23211159,
+			&[video(
+				1649326959,
+				(Utc::now() - Duration::seconds(8000)).into(),
+				8000,
+			)],
+			&[],
+		)
+		.await
+		.unwrap();
+
+	for _ in 0..3 {
+		assert!(tracker
+			.recheck(&gql, &fast_retry(), &sink, &checkpoints, false)
+			.await
+			.is_empty());
+	}
+
+	let output = String::from_utf8(stream.into_inner().unwrap().into_inner().unwrap()).unwrap();
+
+	// The first recheck continues after the last saved comment, the second finds that
+	// the length is unchanged and the third has no videos left to download
+	assert_eq!(1, output.lines().count());
+	assert_eq!(2, server.requests(Operation::VideoMetadata));
+	assert_eq!(2, server.requests(Operation::VideoCommentsByOffsetOrCursor));
+	assert!(
+		checkpoints
+			.load(1649326959)
+			.await
+			.unwrap()
+			.unwrap()
+			.completed
+	);
+}
+
+#[tokio::test]
+async fn test_recheck_after_restart() {
+	let server = MockGql::start(vec![
+		Route::new(
+			Operation::VideoMetadata,
+			json!({ "videoID": "1649326959" }),
+			"video_metadata",
+		),
+		Route::new(
+			Operation::VideoCommentsByOffsetOrCursor,
+			json!({ "videoID": "1649326959", "cursor": "eyJpZCI6ImU0YjhjNmQwIn0=" }),
+			"comments_page_2",
+		),
+	])
+	.await;
+	let gql = server.client();
+	let store = SqliteStore::open_in_memory().unwrap();
+	let watermarks = SqliteWatermarks::new(&store, true);
+	let created_at = (Utc::now() - Duration::seconds(8000)).into();
+
+	WatermarkTracker::new(Some(&watermarks))
+		.record(
+			23211159,
+			&[
+				video(1649326959, created_at, 8000),
+				video(1, created_at, 8000),
+			],
+			&[],
+		)
+		.await
+		.unwrap();
+
+	// Checkpoints saved by this run are used even though it is not resuming
+	let checkpoints = SqliteCheckpoints::new(&store, false);
+
+	checkpoints
+		.save(&Checkpoint {
+			video_id: 1649326959,
+			cursor: Some("eyJpZCI6ImU0YjhjNmQwIn0=".to_string()),
+			offset: 8,
+			completed: true,
+		})
+		.await
+		.unwrap();
+
+	// A new tracker watches the videos that the last one saw recording
+	let stream = Mutex::new(BufWriter::new(Vec::new()));
+	let sink = StreamSink::new(&stream, &Format::JsonLines);
+	let mut tracker = WatermarkTracker::new(Some(&watermarks));
+
+	tracker.watermark(23211159).await.unwrap();
+
+	// The missing video fails without stopping the other one
+	let errors = tracker
+		.recheck(&gql, &fast_retry(), &sink, &checkpoints, false)
+		.await;
+	let output = String::from_utf8(stream.into_inner().unwrap().into_inner().unwrap()).unwrap();
+
+	assert_eq!(1, errors.len());
+	assert_eq!(1, output.lines().count());
+	assert_eq!(
+		vec![(1, 8000), (1649326959, 9000)],
+		watermarks
+			.load_growing(23211159)
+			.await
+			.unwrap()
+			.into_iter()
+			.collect::<Vec<_>>()
+	);
+}